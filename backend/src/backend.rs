@@ -5,7 +5,7 @@ use glib::{
     SignalHandlerId,
 };
 use once_cell::sync::Lazy;
-use std::{cell::RefCell, collections::HashMap, process, sync::Arc, time::Duration};
+use std::{cell::RefCell, collections::HashMap, path::Path, process, sync::Arc, time::Duration};
 
 use crate::daemon::*;
 use crate::{Board, DerefCell};
@@ -42,6 +42,12 @@ impl ObjectImpl for BackendInner {
                     glib::Type::UNIT.into(),
                 )
                 .build(),
+                Signal::builder(
+                    "daemon-died",
+                    &[glib::Type::STRING.into()],
+                    glib::Type::UNIT.into(),
+                )
+                .build(),
             ]
         });
         SIGNALS.as_ref()
@@ -74,10 +80,14 @@ impl Backend {
                         self_.inner().boards.borrow_mut().insert(board.board(), board);
                     },
                     ThreadResponse::BoardRemoved(id) => {
-                        let boards = self_.inner().boards.borrow();
-                        let board = &boards[&id];
-                        self_.emit_by_name("board-removed", &[board]).unwrap();
-                        board.emit_by_name("removed", &[]).unwrap();
+                        let board = self_.inner().boards.borrow_mut().remove(&id);
+                        if let Some(board) = board {
+                            self_.emit_by_name("board-removed", &[&board]).unwrap();
+                            board.emit_by_name("removed", &[]).unwrap();
+                        }
+                    },
+                    ThreadResponse::DaemonDied(reason) => {
+                        self_.emit_by_name("daemon-died", &[&reason]).unwrap();
                     },
                 }
             }),
@@ -87,7 +97,39 @@ impl Backend {
     }
 
     pub fn new_dummy(board_names: Vec<String>) -> Result<Self, String> {
-        Self::new_internal(DaemonDummy::new(board_names))
+        Self::new_internal(DaemonDummy::new(board_names)?)
+    }
+
+    /// Like `new_dummy`, persisting settings in `state_file` if set, and
+    /// returning a handle for simulating key presses and errors
+    pub fn new_dummy_controlled(
+        board_names: Vec<String>,
+        state_file: Option<&Path>,
+    ) -> Result<(Self, DummyControl), String> {
+        let mut daemon = DaemonDummy::new(board_names)?;
+        if let Some(state_file) = state_file {
+            daemon = daemon.with_state_file(state_file)?;
+        }
+        let control = daemon.control();
+        Ok((Self::new_internal(daemon)?, control))
+    }
+
+    /// Play back a session recorded by `DaemonRecorder`, for reproducible tests
+    pub fn new_replay<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let session = Session::load(path)?;
+        Self::new_internal(DaemonReplay::new(session, ReplayTiming::Realtime))
+    }
+
+    /// Like `new`, recording the session to `path` for `new_replay`
+    pub fn new_recording<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let daemon = DaemonServer::new_stdio()?;
+        Self::new_internal(DaemonRecorder::new(Box::new(daemon), path)?)
+    }
+
+    /// Like `new_pkexec`, recording the session to `path` for `new_replay`
+    pub fn new_pkexec_recording<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let daemon = DaemonClient::new_pkexec()?;
+        Self::new_internal(DaemonRecorder::new(Box::new(daemon), path)?)
     }
 
     #[cfg(target_os = "linux")]
@@ -96,7 +138,7 @@ impl Backend {
     }
 
     pub fn new_pkexec() -> Result<Self, String> {
-        Self::new_internal(DaemonClient::new_pkexec())
+        Self::new_internal(DaemonClient::new_pkexec()?)
     }
 
     pub fn new() -> Result<Self, String> {
@@ -112,7 +154,7 @@ impl Backend {
     /// This function does not block, and loads new boards in the background.
     pub fn refresh(&self) {
         let self_ = self.clone();
-        glib::MainContext::default().spawn_local(async move {
+        glib::MainContext::ref_thread_default().spawn_local(async move {
             if let Err(err) = self_.inner().thread_client.refresh().await {
                 error!("Failed to refresh boards: {}", err);
             }
@@ -121,7 +163,7 @@ impl Backend {
 
     pub fn set_matrix_get_rate(&self, rate: Option<Duration>) {
         let self_ = self.clone();
-        glib::MainContext::default().spawn_local(async move {
+        glib::MainContext::ref_thread_default().spawn_local(async move {
             let _ = self_.inner().thread_client.set_matrix_get_rate(rate).await;
         });
     }
@@ -157,10 +199,31 @@ impl Backend {
         })
         .unwrap()
     }
+
+    /// Called with the reason when the daemon dies. Its boards are removed,
+    /// and a new daemon is started, emitting `board-added` for the boards it
+    /// finds. The reason says if restarting failed, or if it was given up
+    /// after dying too many times in a row.
+    pub fn connect_daemon_died<F: Fn(String) + 'static>(&self, cb: F) -> SignalHandlerId {
+        self.connect_local("daemon-died", false, move |values| {
+            cb(values[1].get::<String>().unwrap().unwrap());
+            None
+        })
+        .unwrap()
+    }
 }
 
 pub fn run_daemon() -> ! {
-    let server = DaemonServer::new_stdio().expect("Failed to create server");
-    server.run().expect("Failed to run server");
+    let server = match DaemonServer::new_stdio() {
+        Ok(server) => server,
+        Err(err) => {
+            eprintln!("Failed to create server: {}", err);
+            process::exit(1)
+        }
+    };
+    if let Err(err) = server.run() {
+        eprintln!("Failed to run server: {}", err);
+        process::exit(1)
+    }
     process::exit(0)
 }
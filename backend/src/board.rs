@@ -1,14 +1,21 @@
-use futures::{channel::mpsc as async_mpsc, prelude::*};
+use futures::{channel::mpsc as async_mpsc, future, prelude::*};
 use glib::{
     prelude::*,
     subclass::{prelude::*, Signal},
     SignalHandlerId,
 };
 use once_cell::sync::Lazy;
-use std::{cell::Cell, collections::HashMap, sync::Arc};
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    sync::Arc,
+};
 
-use crate::daemon::ThreadClient;
-use crate::{BoardId, Daemon, DerefCell, Key, KeyMap, KeyMapLayer, Layer, Layout, Matrix};
+use crate::daemon::{LayerLeds, ThreadClient};
+use crate::{
+    BoardCapabilities, BoardId, Calibration, Daemon, DerefCell, Firmware, FlashProgress, Hsv, Key,
+    KeyMap, KeyMapLayer, Layer, Layout, LedFrame, Macro, Matrix, Paint, SetError,
+};
 
 #[derive(Default)]
 #[doc(hidden)]
@@ -22,11 +29,12 @@ pub struct BoardInner {
     layers: DerefCell<Vec<Layer>>,
     max_brightness: DerefCell<i32>,
     leds_changed: Cell<bool>,
-    has_led_save: DerefCell<bool>,
+    capabilities: DerefCell<BoardCapabilities>,
+    num_layers: DerefCell<usize>,
+    macros: RefCell<Vec<Macro>>,
     led_save_blocked: Cell<bool>,
-    has_matrix: DerefCell<bool>,
     is_fake: DerefCell<bool>,
-    has_keymap: DerefCell<bool>,
+    calibration: Cell<Calibration>,
 }
 
 #[glib::object_subclass]
@@ -61,6 +69,7 @@ impl Board {
         thread_client: Arc<ThreadClient>,
         board: BoardId,
         mut matrix_reciever: async_mpsc::UnboundedReceiver<Matrix>,
+        mut leds_reciever: async_mpsc::UnboundedReceiver<Vec<(usize, LayerLeds)>>,
     ) -> Result<Self, String> {
         let model = match daemon.model(board) {
             Ok(model) => model,
@@ -80,16 +89,31 @@ impl Board {
             100
         });
 
-        let num_layers = if layout.meta.has_per_layer {
-            layout.meta.num_layers
+        let capabilities = daemon.capabilities(board).unwrap_or_else(|err| {
+            error!("Error getting board capabilities: {}", err);
+            BoardCapabilities::default()
+        });
+
+        // Firmware may be built with a different number of layers than the
+        // layout describes
+        let num_layers = capabilities.num_layers.unwrap_or(layout.meta.num_layers);
+        let num_led_layers = if layout.meta.has_per_layer {
+            num_layers
         } else {
             1
         };
 
-        let has_led_save = daemon.led_save(board).is_ok();
-        let has_matrix = daemon.matrix_get(board).is_ok();
-        let logical = layout.layout.values().next().unwrap();
-        let has_keymap = daemon.keymap_get(board, 0, logical.0, logical.1).is_ok();
+        let macros = (0..capabilities.num_macros)
+            .map(|index| {
+                daemon
+                    .macro_get(board, index)
+                    .and_then(|data| Macro::from_bytes(&data, &layout))
+                    .unwrap_or_else(|err| {
+                        error!("Error getting macro {}: {}", index, err);
+                        Macro::default()
+                    })
+            })
+            .collect();
 
         let self_ = glib::Object::new::<Board>(&[]).unwrap();
         self_.inner().thread_client.set(thread_client);
@@ -98,10 +122,13 @@ impl Board {
         self_.inner().version.set(version);
         self_.inner().layout.set(layout);
         self_.inner().max_brightness.set(max_brightness);
-        self_.inner().has_led_save.set(has_led_save);
-        self_.inner().has_matrix.set(has_matrix);
+        self_.inner().capabilities.set(capabilities);
+        self_.inner().num_layers.set(num_layers.into());
+        self_.inner().macros.replace(macros);
         self_.inner().is_fake.set(daemon.is_fake());
-        self_.inner().has_keymap.set(has_keymap);
+        let calibration =
+            Calibration::load(self_.model()).unwrap_or(self_.layout().meta.calibration);
+        self_.inner().calibration.set(calibration);
 
         let keys = self_
             .layout()
@@ -112,14 +139,14 @@ impl Board {
             .collect();
         self_.inner().keys.set(keys);
 
-        let layers = (0..num_layers)
+        let layers = (0..num_led_layers)
             .map(|layer| Layer::new(daemon, &self_, layer))
             .collect();
         self_.inner().layers.set(layers);
 
         {
             let self_ = self_.clone();
-            glib::MainContext::default().spawn(async move {
+            glib::MainContext::ref_thread_default().spawn(async move {
                 while let Some(matrix) = matrix_reciever.next().await {
                     for key in self_.keys() {
                         let pressed = matrix
@@ -132,6 +159,22 @@ impl Board {
             });
         }
 
+        {
+            let self_ = self_.clone();
+            glib::MainContext::ref_thread_default().spawn(async move {
+                while let Some(changed) = leds_reciever.next().await {
+                    let mut any_changed = false;
+                    for (layer, leds) in changed {
+                        any_changed |= self_.layers()[layer].update_leds(leds);
+                    }
+                    // Changed outside this program, so there's nothing to save
+                    if any_changed {
+                        self_.emit_by_name("leds-changed", &[]).unwrap();
+                    }
+                }
+            });
+        }
+
         Ok(self_)
     }
 
@@ -152,6 +195,8 @@ impl Board {
         self.emit_by_name("leds-changed", &[]).unwrap();
     }
 
+    /// Called when LED settings of a key or layer change, either through this
+    /// `Board`, or externally (like by a brightness hotkey)
     pub fn connect_leds_changed<F: Fn() + 'static>(&self, cb: F) -> SignalHandlerId {
         self.connect_local("leds-changed", false, move |_| {
             cb();
@@ -176,10 +221,6 @@ impl Board {
         &self.inner().version
     }
 
-    pub fn has_matrix(&self) -> bool {
-        *self.inner().has_matrix
-    }
-
     pub fn connect_matrix_changed<F: Fn() + 'static>(&self, cb: F) -> SignalHandlerId {
         self.connect_local("matrix-changed", false, move |_| {
             cb();
@@ -192,11 +233,11 @@ impl Board {
         *self.inner().max_brightness
     }
 
-    pub async fn led_save(&self) -> Result<(), String> {
+    pub async fn led_save(&self) -> Result<(), SetError> {
         if self.inner().led_save_blocked.get() {
             return Ok(());
         }
-        if self.has_led_save() && self.inner().leds_changed.get() {
+        if self.capabilities().led_save && self.inner().leds_changed.get() {
             self.thread_client().led_save(self.board()).await?;
             self.inner().leds_changed.set(false);
             debug!("led_save");
@@ -204,6 +245,67 @@ impl Board {
         Ok(())
     }
 
+    /// Set the colors of many LEDs at once, for animations. Colors of `Key`s
+    /// aren't changed, so this isn't saved to the keymap.
+    ///
+    /// If the keyboard is slower than frames are set, older frames are
+    /// dropped and fail with `SetError::Superseded`.
+    pub async fn set_colors_frame(&self, frame: LedFrame) -> Result<(), SetError> {
+        self.thread_client()
+            .set_colors_frame(self.board(), frame)
+            .await
+    }
+
+    /// Colors `paint` gives to the keys with LEDs, by index in `keys()`
+    pub fn paint_colors(&self, paint: &Paint) -> Vec<(usize, Hsv)> {
+        let keys = self
+            .keys()
+            .iter()
+            .enumerate()
+            .filter(|(_, key)| !key.leds.is_empty())
+            .collect::<Vec<_>>();
+        let rects = keys.iter().map(|(_, key)| key.physical).collect::<Vec<_>>();
+        keys.iter()
+            .map(|(i, _)| *i)
+            .zip(paint.colors(&rects).into_iter().map(|rgb| rgb.to_hsv()))
+            .collect()
+    }
+
+    /// Set the color of every key with LEDs from `paint`, which is saved to
+    /// the keymap like other key colors
+    pub async fn apply_paint(&self, paint: &Paint) -> Result<(), SetError> {
+        for (i, color) in self.paint_colors(paint) {
+            self.keys()[i].set_color(Some(color)).await?;
+        }
+        Ok(())
+    }
+
+    /// Number of frames from `set_colors_frame` written to the keyboard in
+    /// the last second
+    pub fn frames_per_second(&self) -> usize {
+        self.thread_client().frames_per_second(self.board())
+    }
+
+    /// Flash `firmware`, calling `progress` as each stage is reached
+    ///
+    /// The board is removed while it is in its bootloader, and added again
+    /// as a new `Board` once it restarts with the new firmware.
+    pub async fn flash<F: FnMut(FlashProgress)>(
+        &self,
+        firmware: &Firmware,
+        mut progress: F,
+    ) -> Result<(), SetError> {
+        let (sender, receiver) = async_mpsc::unbounded();
+        let res =
+            self.thread_client()
+                .flash(self.board(), Arc::new(firmware.image.clone()), sender);
+        let progress = receiver.for_each(|stage| {
+            progress(stage);
+            future::ready(())
+        });
+        future::join(res, progress).await.0
+    }
+
     pub fn block_led_save(&self) {
         self.inner().led_save_blocked.set(true);
     }
@@ -216,12 +318,62 @@ impl Board {
         *self.inner().is_fake
     }
 
-    pub fn has_led_save(&self) -> bool {
-        *self.inner().has_led_save
+    /// Correction of the colors sent to the LEDs, from the user's config or
+    /// the layout, unless changed with `set_calibration`
+    pub fn calibration(&self) -> Calibration {
+        self.inner().calibration.get()
+    }
+
+    /// Change the correction of colors, setting the LEDs again so the
+    /// change is visible
+    pub async fn set_calibration(&self, calibration: Calibration) -> Result<(), SetError> {
+        self.inner().calibration.set(calibration);
+        for layer in self.layers() {
+            layer.set_color(layer.color()).await?;
+        }
+        for key in self.keys() {
+            if !key.leds.is_empty() && key.color().is_some() {
+                key.set_color(key.color()).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Features supported by the board's firmware
+    pub fn capabilities(&self) -> &BoardCapabilities {
+        &self.inner().capabilities
+    }
+
+    /// Number of keymap layers, from the firmware if it reports it, or else
+    /// from the layout
+    pub fn num_layers(&self) -> usize {
+        *self.inner().num_layers
     }
 
-    pub fn has_keymap(&self) -> bool {
-        *self.inner().has_keymap
+    /// Macros stored in the firmware, which is empty if it doesn't support
+    /// macros
+    pub fn macros(&self) -> Vec<Macro> {
+        self.inner().macros.borrow().clone()
+    }
+
+    pub async fn set_macro(&self, index: usize, macro_: Macro) -> Result<(), SetError> {
+        if index >= self.inner().macros.borrow().len() {
+            return Err(format!("Board has no macro {}", index + 1).into());
+        }
+        let data = macro_.to_bytes(self.layout())?;
+        if data.len() > self.capabilities().macro_size.into() {
+            return Err(format!(
+                "Macro needs {} bytes, but only {} are available",
+                data.len(),
+                self.capabilities().macro_size
+            )
+            .into());
+        }
+        self.thread_client()
+            .macro_set(self.board(), index as u8, data)
+            .await?;
+        self.inner().macros.borrow_mut()[index] = macro_;
+        Ok(())
     }
 
     pub fn layout(&self) -> &Layout {
@@ -240,7 +392,7 @@ impl Board {
         let mut map = HashMap::new();
         let mut key_leds = HashMap::new();
         for key in self.keys().iter() {
-            let scancodes = (0..self.layout().meta.num_layers as usize)
+            let scancodes = (0..self.num_layers())
                 .map(|layer| key.get_scancode(layer).unwrap().1)
                 .collect();
             map.insert(key.logical_name.clone(), scancodes);
@@ -263,6 +415,7 @@ impl Board {
             map,
             key_leds,
             layers,
+            macros: self.macros(),
         }
     }
 }
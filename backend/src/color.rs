@@ -1,7 +1,11 @@
 use ordered_float::NotNan;
 use palette::{Component, IntoColor, RgbHue};
 use serde::{de, Deserialize, Serialize};
-use std::{f64::consts::PI, fmt};
+use std::{collections::BTreeMap, f64::consts::PI, fmt};
+
+use crate::config::{load_config, save_config};
+
+const CALIBRATION_FILE: &str = "calibration.json";
 
 type PaletteHsv = palette::Hsv<palette::encoding::Srgb, f64>;
 type PaletteLinSrgb = palette::LinSrgb<f64>;
@@ -55,6 +59,74 @@ impl Hs {
     }
 }
 
+/// Floating point hue/saturation/value color, for keys that can be dimmer
+/// than others
+#[derive(
+    Clone, Copy, Debug, Serialize, Deserialize, PartialEq, glib::GBoxed, Hash, Eq, Ord, PartialOrd,
+)]
+#[gboxed(type_name = "S76Hsv")]
+pub struct Hsv {
+    /// Hue, in radians
+    pub h: NotNan<f64>,
+    /// Saturation, from 0.0 to 1.0
+    pub s: NotNan<f64>,
+    /// Value, from 0.0 (off) to 1.0 (full brightness)
+    pub v: NotNan<f64>,
+}
+
+impl Hsv {
+    pub fn new(h: f64, s: f64, v: f64) -> Self {
+        Self {
+            h: NotNan::new(h).unwrap(),
+            s: NotNan::new(s).unwrap(),
+            v: NotNan::new(v).unwrap(),
+        }
+    }
+
+    pub fn from_ints(h: u8, s: u8, v: u8) -> Self {
+        Self::new(h.convert::<f64>() * (2. * PI), s.convert(), v.convert())
+    }
+
+    pub fn to_ints(self) -> (u8, u8, u8) {
+        let (h, s) = self.hs().to_ints();
+        (h, s, self.v.convert())
+    }
+
+    /// Hue and saturation, without the value
+    pub fn hs(self) -> Hs {
+        Hs {
+            h: self.h,
+            s: self.s,
+        }
+    }
+
+    pub fn to_rgb(self) -> Rgb {
+        let hue = RgbHue::from_radians(*self.h);
+        let hsv = PaletteHsv::new(hue, *self.s, *self.v);
+        let rgb: PaletteLinSrgb = hsv.into_rgb();
+        let (r, g, b) = rgb.into_components();
+        Rgb::from_floats(r, g, b)
+    }
+}
+
+/// White at full brightness, like the default `Hs`
+impl Default for Hsv {
+    fn default() -> Self {
+        Hs::default().into()
+    }
+}
+
+/// Full brightness
+impl From<Hs> for Hsv {
+    fn from(hs: Hs) -> Self {
+        Self {
+            h: hs.h,
+            s: hs.s,
+            v: NotNan::new(1.).unwrap(),
+        }
+    }
+}
+
 /// Integer RGB color
 #[derive(Clone, Copy, Debug, Default, glib::GBoxed)]
 #[gboxed(type_name = "S76Rgb")]
@@ -104,6 +176,105 @@ impl Rgb {
         let (h, s, _) = hsv.into_components();
         Hs::new(h.to_radians(), s)
     }
+
+    pub fn to_hsv(self) -> Hsv {
+        let (r, g, b) = self.to_floats();
+        let rgb = PaletteLinSrgb::new(r, g, b);
+        let hsv: PaletteHsv = rgb.into_hsv();
+        let (h, s, v) = hsv.into_components();
+        Hsv::new(h.to_radians(), s, v)
+    }
+}
+
+/// Correction of the colors sent to a keyboard's LEDs, so they look like the
+/// colors chosen on screen
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Calibration {
+    /// Power each channel, from 0.0 to 1.0, is raised to
+    pub gamma: f64,
+    /// Multipliers of the red, green and blue channels, from 0.0 to 1.0, to
+    /// correct the white point
+    pub gains: (f64, f64, f64),
+}
+
+impl Default for Calibration {
+    fn default() -> Self {
+        Self {
+            gamma: 1.,
+            gains: (1., 1., 1.),
+        }
+    }
+}
+
+impl Calibration {
+    /// Calibration saved by the user for keyboards of `model`
+    pub fn load(model: &str) -> Option<Self> {
+        load_config::<BTreeMap<String, Self>>(CALIBRATION_FILE).remove(model)
+    }
+
+    /// Save as the calibration of keyboards of `model`, used instead of the
+    /// one from the layout
+    pub fn save(&self, model: &str) -> Result<(), String> {
+        let mut calibrations = load_config::<BTreeMap<String, Self>>(CALIBRATION_FILE);
+        calibrations.insert(model.to_string(), *self);
+        save_config(CALIBRATION_FILE, &calibrations)
+    }
+
+    pub fn is_identity(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Color to send to the LEDs to show `rgb`
+    pub fn apply(&self, rgb: Rgb) -> Rgb {
+        if self.is_identity() {
+            return rgb;
+        }
+        let (r, g, b) = rgb.to_floats();
+        let channel = |value: f64, gain: f64| (value.powf(self.gamma) * gain).max(0.).min(1.);
+        Rgb::from_floats(
+            channel(r, self.gains.0),
+            channel(g, self.gains.1),
+            channel(b, self.gains.2),
+        )
+    }
+
+    /// Color shown by LEDs set to `rgb`, reversing `apply`
+    pub fn invert(&self, rgb: Rgb) -> Rgb {
+        if self.is_identity() {
+            return rgb;
+        }
+        let (r, g, b) = rgb.to_floats();
+        let channel = |value: f64, gain: f64| {
+            if gain > 0. && self.gamma > 0. {
+                (value / gain).max(0.).min(1.).powf(1. / self.gamma)
+            } else {
+                0.
+            }
+        };
+        Rgb::from_floats(
+            channel(r, self.gains.0),
+            channel(g, self.gains.1),
+            channel(b, self.gains.2),
+        )
+    }
+
+    /// Like `apply`, for LEDs set by hue and saturation, which loses the
+    /// change in brightness
+    pub fn apply_hs(&self, hs: Hs) -> Hs {
+        if self.is_identity() {
+            return hs;
+        }
+        self.apply(hs.to_rgb()).to_hs_lossy()
+    }
+
+    /// Like `invert`, for LEDs set by hue and saturation
+    pub fn invert_hs(&self, hs: Hs) -> Hs {
+        if self.is_identity() {
+            return hs;
+        }
+        self.invert(hs.to_rgb()).to_hs_lossy()
+    }
 }
 
 /// Convert to hexadecimal string
@@ -153,4 +324,17 @@ mod test {
         assert!((hs2.h - hs3.h).abs() < 0.0001);
         assert!((hs2.s - hs3.s).abs() < 0.0001);
     }
+
+    #[test]
+    fn test_hsv_rgb_hsv() {
+        let hsv1 = Hsv::new(0.3, 0.4, 0.5);
+        let hsv2 = hsv1.to_rgb().to_hsv();
+        assert!((hsv1.h - hsv2.h).abs() < 0.01);
+        assert!((hsv1.s - hsv2.s).abs() < 0.01);
+        assert!((hsv1.v - hsv2.v).abs() < 0.01);
+        assert_eq!(
+            Hsv::from(Hs::new(0.3, 0.4)).to_rgb().r,
+            Hs::new(0.3, 0.4).to_rgb().r
+        );
+    }
 }
@@ -0,0 +1,34 @@
+use serde::{de::DeserializeOwned, Serialize};
+use std::{fs, path::PathBuf};
+
+fn config_path(name: &str) -> PathBuf {
+    glib::get_user_config_dir()
+        .join("system76-keyboard-configurator")
+        .join(name)
+}
+
+/// Load `name` from the user's config directory, or the default if it hasn't
+/// been saved
+pub(crate) fn load_config<T: Default + DeserializeOwned>(name: &str) -> T {
+    let path = config_path(name);
+    if !path.exists() {
+        return T::default();
+    }
+    fs::read_to_string(&path)
+        .map_err(|err| err.to_string())
+        .and_then(|json| serde_json::from_str(&json).map_err(|err| err.to_string()))
+        .unwrap_or_else(|err| {
+            error!("Failed to load {}: {}", path.display(), err);
+            T::default()
+        })
+}
+
+/// Save `name` in the user's config directory
+pub(crate) fn save_config<T: Serialize>(name: &str, value: &T) -> Result<(), String> {
+    let path = config_path(name);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|err| err.to_string())?;
+    }
+    let json = serde_json::to_string_pretty(value).map_err(|err| err.to_string())?;
+    fs::write(&path, json).map_err(|err| err.to_string())
+}
@@ -1,26 +1,36 @@
 use std::{
     cell::RefCell,
+    collections::HashSet,
     env,
-    io::{BufRead, BufReader, Write},
+    io::{BufRead, BufReader, Read, Write},
     path::PathBuf,
-    process::{Child, ChildStdin, ChildStdout, Command, Stdio},
+    process::{Child, Command, Stdio},
 };
 
-use super::{err_str, Daemon, DaemonClientTrait, DaemonCommand, DaemonResponse};
+use super::{
+    err_str, Daemon, DaemonClientTrait, DaemonCommand, DaemonHello, DaemonResponse,
+    PROTOCOL_VERSION,
+};
 
-pub struct DaemonClient {
-    child: Child,
-    read: RefCell<BufReader<ChildStdout>>,
-    write: RefCell<ChildStdin>,
+/// A running daemon and its pipes; the `pkexec` child process, unless it was
+/// connected to some other way
+struct DaemonProcess {
+    child: Option<Child>,
+    read: BufReader<Box<dyn Read + Send>>,
+    write: Box<dyn Write + Send>,
+    capabilities: HashSet<String>,
 }
 
-impl DaemonClient {
-    pub fn new_pkexec() -> Self {
+impl DaemonProcess {
+    fn spawn_pkexec() -> Result<Self, String> {
         // Use canonicalized command name
         let command_path = if cfg!(feature = "appimage") {
-            PathBuf::from(env::var("APPIMAGE").expect("Failed to get executable path"))
+            PathBuf::from(
+                env::var("APPIMAGE")
+                    .map_err(|err| format!("Failed to get executable path: {}", err))?,
+            )
         } else {
-            env::current_exe().expect("Failed to get executable path")
+            env::current_exe().map_err(|err| format!("Failed to get executable path: {}", err))?
         };
 
         let mut child = Command::new("pkexec")
@@ -29,53 +39,241 @@ impl DaemonClient {
             .stdin(Stdio::piped())
             .stdout(Stdio::piped())
             .spawn()
-            .expect("Failed to spawn daemon");
+            .map_err(|err| format!("Failed to spawn daemon: {}", err))?;
+
+        let write = Box::new(child.stdin.take().unwrap());
+        let read = Box::new(child.stdout.take().unwrap());
+        Self::new(Some(child), read, write)
+    }
 
-        let stdin = child.stdin.take().unwrap();
-        let mut stdout = BufReader::new(child.stdout.take().unwrap());
+    /// Wait for the daemon to send its hello
+    fn new(
+        mut child: Option<Child>,
+        read: Box<dyn Read + Send>,
+        write: Box<dyn Write + Send>,
+    ) -> Result<Self, String> {
+        let mut read = BufReader::new(read);
 
         // Check if daemon has started
         let mut line = String::new();
-        if let Ok(count) = stdout.read_line(&mut line) {
-            // pkexec terminated returning EOF
-            if count == 0 {
-                panic!("Failed to start daemon with pkexec");
-            }
+        let count = read.read_line(&mut line).map_err(err_str)?;
+        // pkexec terminated returning EOF
+        if count == 0 {
+            return Err(match &mut child {
+                Some(child) => {
+                    let status = child.wait().map_err(err_str)?;
+                    format!("Failed to start daemon with pkexec: {}", status)
+                }
+                None => "Daemon closed connection before starting".to_string(),
+            });
         }
 
-        Self {
+        let mut process = Self {
             child,
-            read: RefCell::new(stdout),
-            write: RefCell::new(stdin),
+            read,
+            write,
+            capabilities: HashSet::new(),
+        };
+        match check_hello(&line) {
+            Ok(capabilities) => process.capabilities = capabilities,
+            Err(err) => {
+                process.kill();
+                return Err(err);
+            }
         }
+        Ok(process)
     }
-}
 
-impl DaemonClientTrait for DaemonClient {
-    fn send_command(&self, command: DaemonCommand) -> Result<DaemonResponse, String> {
-        let mut command_json = serde_json::to_string(&command).map_err(err_str)?;
+    /// Send a command, returning the outer `Err` if the daemon has died
+    fn send_command(
+        &mut self,
+        command: &DaemonCommand,
+    ) -> Result<Result<DaemonResponse, String>, String> {
+        let mut command_json = match serde_json::to_string(command) {
+            Ok(command_json) => command_json,
+            Err(err) => return Ok(Err(err_str(err))),
+        };
         command_json.push('\n');
         self.write
-            .borrow_mut()
             .write_all(command_json.as_bytes())
-            .map_err(err_str)?;
+            .map_err(|err| self.died(&err.to_string()))?;
 
         let mut response_json = String::new();
-        self.read
-            .borrow_mut()
+        let count = self
+            .read
             .read_line(&mut response_json)
-            .map_err(err_str)?;
-        serde_json::from_str(&response_json).map_err(err_str)?
+            .map_err(|err| self.died(&err.to_string()))?;
+        if count == 0 {
+            return Err(self.died("unexpected end of output"));
+        }
+        Ok(serde_json::from_str(&response_json).unwrap_or_else(|err| Err(err_str(err))))
+    }
+
+    /// Describe why the daemon stopped responding, including its exit status
+    /// if it has already terminated
+    fn died(&mut self, reason: &str) -> String {
+        match self.child.as_mut().map(Child::try_wait) {
+            Some(Ok(Some(status))) => format!("Daemon exited with {}: {}", status, reason),
+            _ => format!("Daemon stopped responding: {}", reason),
+        }
+    }
+
+    fn kill(&mut self) {
+        if let Some(child) = &mut self.child {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+/// Client for a `DaemonServer` running as root in a `pkexec` child process
+///
+/// If the child dies, commands fail with the reason (available from
+/// `Daemon::died`), until `Daemon::restart` spawns a new one.
+pub struct DaemonClient {
+    process: RefCell<Option<DaemonProcess>>,
+    died: RefCell<Option<String>>,
+    pkexec: bool,
+}
+
+impl DaemonClient {
+    pub fn new_pkexec() -> Result<Self, String> {
+        Ok(Self {
+            process: RefCell::new(Some(DaemonProcess::spawn_pkexec()?)),
+            died: RefCell::new(None),
+            pkexec: true,
+        })
+    }
+
+    /// Connect to a `DaemonServer` over an existing pipe or socket. Such a
+    /// daemon can not be restarted.
+    #[cfg(test)]
+    pub fn new_streams<R: Read + Send + 'static, W: Write + Send + 'static>(
+        read: R,
+        write: W,
+    ) -> Result<Self, String> {
+        Ok(Self {
+            process: RefCell::new(Some(DaemonProcess::new(
+                None,
+                Box::new(read),
+                Box::new(write),
+            )?)),
+            died: RefCell::new(None),
+            pkexec: false,
+        })
+    }
+
+    /// Test if the daemon reported support for a command
+    pub fn has_capability(&self, name: &str) -> bool {
+        self.process
+            .borrow()
+            .as_ref()
+            .map_or(false, |process| process.capabilities.contains(name))
+    }
+}
+
+/// Parse the hello line sent by the daemon, returning its capabilities
+fn check_hello(line: &str) -> Result<HashSet<String>, String> {
+    let hello = serde_json::from_str::<DaemonHello>(line).map_err(|_| {
+        format!(
+            "Daemon did not send a protocol version; expected version {}",
+            PROTOCOL_VERSION
+        )
+    })?;
+    if hello.version != PROTOCOL_VERSION {
+        return Err(format!(
+            "Daemon protocol version {} does not match version {}",
+            hello.version, PROTOCOL_VERSION
+        ));
+    }
+    Ok(hello.capabilities.into_iter().collect())
+}
+
+impl DaemonClientTrait for DaemonClient {
+    fn send_command(&self, command: DaemonCommand) -> Result<DaemonResponse, String> {
+        if let Some(reason) = &*self.died.borrow() {
+            return Err(reason.clone());
+        }
+
+        if !self.has_capability(command.name()) {
+            return Err(format!(
+                "Daemon does not support command '{}'",
+                command.name()
+            ));
+        }
+
+        let mut process = self.process.borrow_mut();
+        match process.as_mut().unwrap().send_command(&command) {
+            Ok(res) => res,
+            Err(reason) => {
+                error!("{}", reason);
+                if let Some(mut process) = process.take() {
+                    process.kill();
+                }
+                self.died.replace(Some(reason.clone()));
+                Err(reason)
+            }
+        }
+    }
+
+    fn died(&self) -> Option<String> {
+        self.died.borrow().clone()
+    }
+
+    fn restart(&self) -> Result<(), String> {
+        if !self.pkexec {
+            return Err("Daemon can not be restarted".to_string());
+        }
+        if let Some(mut process) = self.process.borrow_mut().take() {
+            process.kill();
+        }
+        let process = DaemonProcess::spawn_pkexec()?;
+        *self.process.borrow_mut() = Some(process);
+        self.died.replace(None);
+        info!("Restarted daemon");
+        Ok(())
     }
 }
 
 impl Drop for DaemonClient {
     fn drop(&mut self) {
+        if self.died.borrow().is_some() {
+            return;
+        }
+
         let _ = self.exit();
 
-        let status = self.child.wait().expect("Failed to wait for daemon");
-        if !status.success() {
-            panic!("Failed to run daemon with exit status {:?}", status);
+        let child = self
+            .process
+            .borrow_mut()
+            .take()
+            .and_then(|process| process.child);
+        if let Some(mut child) = child {
+            match child.wait() {
+                Ok(status) if !status.success() => {
+                    error!("Daemon exited with status {:?}", status);
+                }
+                Ok(_) => {}
+                Err(err) => error!("Failed to wait for daemon: {}", err),
+            }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hello_matching_version() {
+        let hello = serde_json::to_string(&DaemonHello::new()).unwrap();
+        let capabilities = check_hello(&hello).unwrap();
+        assert!(capabilities.contains("keymap_set"));
+    }
+
+    #[test]
+    fn hello_old_daemon() {
+        assert!(check_hello("Daemon started\n").is_err());
+        assert!(check_hello(r#"{"version":0,"capabilities":[]}"#).is_err());
+    }
+}
@@ -1,7 +1,7 @@
 use futures::{
     channel::{mpsc as async_mpsc, oneshot},
     executor::LocalPool,
-    future::{abortable, AbortHandle},
+    future::{self, abortable, AbortHandle, Either},
     prelude::*,
     task::LocalSpawnExt,
 };
@@ -10,16 +10,17 @@ use glib::clone;
 use std::{
     cell::{Cell, RefCell},
     cmp::PartialEq,
-    collections::HashMap,
+    collections::{HashMap, VecDeque},
+    fmt,
     hash::{Hash, Hasher},
     rc::Rc,
     sync::{Arc, Mutex, Weak},
     thread::{self, JoinHandle},
-    time::Duration,
+    time::{Duration, Instant},
 };
 
-use super::{BoardId, Daemon, Matrix};
-use crate::Board;
+use super::{BoardId, Daemon, LedFrame, Matrix};
+use crate::{flash, Board, DaemonBootloader, FlashProgress};
 
 #[derive(Clone, Debug)]
 struct Item<K: Hash + Eq, V> {
@@ -51,28 +52,119 @@ impl<K: Hash + Eq, V> Eq for Item<K, V> {}
 enum SetEnum {
     KeyMap(Item<(BoardId, u8, u8, u8), u16>),
     Color(Item<(BoardId, u8), (u8, u8, u8)>),
+    /// Only the newest frame is sent, so frames are dropped if the daemon is
+    /// slower than they are produced
+    ColorsFrame(Item<BoardId, LedFrame>),
     Brightness(Item<(BoardId, u8), i32>),
     Mode(Item<(BoardId, u8), (u8, u8)>),
     LedSave(BoardId),
+    Macro(Item<(BoardId, u8), Vec<u8>>),
     MatrixGetRate(Item<(), Option<Duration>>),
+    Flash(Item<BoardId, (Arc<Vec<u8>>, async_mpsc::UnboundedSender<FlashProgress>)>),
     Refresh,
     Exit,
 }
 
+/// Deadline for requests that write a setting to the daemon
+const SET_TIMEOUT: Duration = Duration::from_secs(5);
+
+impl SetEnum {
+    /// How long to wait for the daemon to handle this request; `None` to wait
+    /// indefinitely
+    fn timeout(&self) -> Option<Duration> {
+        match self {
+            // Loading new boards reads every key, and flashing writes all of
+            // the firmware, so these may legitimately be slow
+            SetEnum::Refresh | SetEnum::Flash(_) | SetEnum::Exit => None,
+            _ => Some(SET_TIMEOUT),
+        }
+    }
+}
+
+/// Error setting a value through `ThreadClient`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SetError {
+    /// A newer request for the same setting was made before this one was sent
+    /// to the daemon, so the newer value will be used instead
+    Superseded,
+    /// The daemon thread stopped before handling the request
+    Cancelled,
+    /// The daemon did not handle the request before its deadline
+    TimedOut,
+    /// The daemon returned an error
+    Failed(String),
+}
+
+impl SetError {
+    /// `true` if the request was replaced by a newer one, which is expected
+    /// when a value changes quickly (like dragging a slider) and can be ignored
+    pub fn is_superseded(&self) -> bool {
+        *self == SetError::Superseded
+    }
+}
+
+impl fmt::Display for SetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SetError::Superseded => write!(f, "superseded by a newer request"),
+            SetError::Cancelled => write!(f, "request cancelled"),
+            SetError::TimedOut => write!(f, "request timed out"),
+            SetError::Failed(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl From<String> for SetError {
+    fn from(err: String) -> Self {
+        SetError::Failed(err)
+    }
+}
+
+impl From<SetError> for String {
+    fn from(err: SetError) -> Self {
+        err.to_string()
+    }
+}
+
 #[derive(Debug)]
 struct Set {
     inner: SetEnum,
-    oneshot: oneshot::Sender<Result<(), String>>,
+    oneshot: oneshot::Sender<Result<(), SetError>>,
+    deadline: Option<Instant>,
 }
 
 impl Set {
-    fn reply(self, resp: Result<(), String>) {
+    fn reply(self, resp: Result<(), SetError>) {
         let _ = self.oneshot.send(resp);
     }
 }
 
+/// Times LED frames were written to a board in the last second
+#[derive(Default)]
+struct FrameCounter {
+    times: VecDeque<Instant>,
+}
+
+impl FrameCounter {
+    fn add_frame(&mut self) {
+        self.times.push_back(Instant::now());
+    }
+
+    fn frames_per_second(&mut self) -> usize {
+        let now = Instant::now();
+        while let Some(time) = self.times.front() {
+            if now.duration_since(*time) < Duration::from_secs(1) {
+                break;
+            }
+            self.times.pop_front();
+        }
+        self.times.len()
+    }
+}
+
 pub struct ThreadClient {
     cancels: Mutex<HashMap<SetEnum, AbortHandle>>,
+    frame_counters: Mutex<HashMap<BoardId, FrameCounter>>,
     channel: async_mpsc::UnboundedSender<Set>,
     join_handle: Mutex<Option<JoinHandle<()>>>,
 }
@@ -82,11 +174,12 @@ impl ThreadClient {
         let (sender, reciever) = async_mpsc::unbounded();
         let client = Arc::new(Self {
             cancels: Mutex::new(HashMap::new()),
+            frame_counters: Mutex::new(HashMap::new()),
             channel: sender,
             join_handle: Mutex::new(None),
         });
         let (response_sender, mut response_reciever) = async_mpsc::unbounded();
-        glib::MainContext::default().spawn_local(async move {
+        glib::MainContext::ref_thread_default().spawn_local(async move {
             while let Some(response) = response_reciever.next().await {
                 cb(response)
             }
@@ -97,7 +190,7 @@ impl ThreadClient {
         client
     }
 
-    async fn send(&self, set_enum: SetEnum) -> Result<(), String> {
+    async fn send(&self, set_enum: SetEnum) -> Result<(), SetError> {
         let mut cancels = self.cancels.lock().unwrap();
         if let Some(cancel) = cancels.remove(&set_enum) {
             cancel.abort();
@@ -107,18 +200,26 @@ impl ThreadClient {
         cancels.insert(set_enum.clone(), cancel);
         drop(cancels);
 
+        let timeout = set_enum.timeout();
         let _ = self.channel.unbounded_send(Set {
             inner: set_enum,
             oneshot: sender,
+            deadline: timeout.map(|timeout| Instant::now() + timeout),
         });
-        // XXX let caller know it was canceled?
-        match receiver.await {
-            Ok(Ok(res)) => res,
-            _ => Ok(()),
+
+        let timeout = match timeout {
+            Some(timeout) => Delay::new(timeout).boxed(),
+            None => future::pending().boxed(),
+        };
+        match future::select(receiver, timeout).await {
+            Either::Left((Ok(Ok(res)), _)) => res,
+            Either::Left((Ok(Err(oneshot::Canceled)), _)) => Err(SetError::Cancelled),
+            Either::Left((Err(future::Aborted), _)) => Err(SetError::Superseded),
+            Either::Right(((), _)) => Err(SetError::TimedOut),
         }
     }
 
-    pub async fn refresh(&self) -> Result<(), String> {
+    pub async fn refresh(&self) -> Result<(), SetError> {
         self.send(SetEnum::Refresh).await
     }
 
@@ -129,7 +230,7 @@ impl ThreadClient {
         output: u8,
         input: u8,
         value: u16,
-    ) -> Result<(), String> {
+    ) -> Result<(), SetError> {
         self.send(SetEnum::KeyMap(Item::new(
             (board, layer, output, input),
             value,
@@ -142,17 +243,30 @@ impl ThreadClient {
         board: BoardId,
         index: u8,
         color: (u8, u8, u8),
-    ) -> Result<(), String> {
+    ) -> Result<(), SetError> {
         self.send(SetEnum::Color(Item::new((board, index), color)))
             .await
     }
 
+    pub async fn set_colors_frame(&self, board: BoardId, frame: LedFrame) -> Result<(), SetError> {
+        self.send(SetEnum::ColorsFrame(Item::new(board, frame)))
+            .await
+    }
+
+    /// Number of LED frames written to `board` in the last second
+    pub fn frames_per_second(&self, board: BoardId) -> usize {
+        let mut frame_counters = self.frame_counters.lock().unwrap();
+        frame_counters
+            .get_mut(&board)
+            .map_or(0, FrameCounter::frames_per_second)
+    }
+
     pub async fn set_brightness(
         &self,
         board: BoardId,
         index: u8,
         brightness: i32,
-    ) -> Result<(), String> {
+    ) -> Result<(), SetError> {
         self.send(SetEnum::Brightness(Item::new((board, index), brightness)))
             .await
     }
@@ -163,19 +277,41 @@ impl ThreadClient {
         layer: u8,
         mode: u8,
         speed: u8,
-    ) -> Result<(), String> {
+    ) -> Result<(), SetError> {
         self.send(SetEnum::Mode(Item::new((board, layer), (mode, speed))))
             .await
     }
 
-    pub async fn set_matrix_get_rate(&self, rate: Option<Duration>) -> Result<(), String> {
+    pub async fn macro_set(
+        &self,
+        board: BoardId,
+        index: u8,
+        data: Vec<u8>,
+    ) -> Result<(), SetError> {
+        self.send(SetEnum::Macro(Item::new((board, index), data)))
+            .await
+    }
+
+    pub async fn set_matrix_get_rate(&self, rate: Option<Duration>) -> Result<(), SetError> {
         self.send(SetEnum::MatrixGetRate(Item::new((), rate))).await
     }
 
-    pub async fn led_save(&self, board: BoardId) -> Result<(), String> {
+    pub async fn led_save(&self, board: BoardId) -> Result<(), SetError> {
         self.send(SetEnum::LedSave(board)).await
     }
 
+    /// Flash `image` to `board` through its bootloader, sending each stage
+    /// to `progress`
+    pub async fn flash(
+        &self,
+        board: BoardId,
+        image: Arc<Vec<u8>>,
+        progress: async_mpsc::UnboundedSender<FlashProgress>,
+    ) -> Result<(), SetError> {
+        self.send(SetEnum::Flash(Item::new(board, (image, progress))))
+            .await
+    }
+
     pub fn close(&self) {
         let join_handle = match self.join_handle.lock().unwrap().take() {
             Some(join_handle) => join_handle,
@@ -189,6 +325,7 @@ impl ThreadClient {
         let _ = self.channel.unbounded_send(Set {
             inner: SetEnum::Exit,
             oneshot: sender,
+            deadline: None,
         });
 
         // Wait for thread to terminate
@@ -201,30 +338,116 @@ pub enum ThreadResponse {
     BoardLoadingDone,
     BoardAdded(Board),
     BoardRemoved(BoardId),
+    DaemonDied(String),
+}
+
+/// Number of times a dead daemon is restarted before giving up
+const MAX_DAEMON_RESTARTS: u32 = 3;
+
+/// How often LED settings are read, to notice changes made outside this
+/// program (like brightness hotkeys handled by the firmware)
+const LEDS_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// LED settings of a layer, as read from the daemon
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct LayerLeds {
+    pub mode: Option<(u8, u8)>,
+    pub brightness: i32,
+    pub color: (u8, u8, u8),
+}
+
+impl LayerLeds {
+    fn read(
+        daemon: &dyn Daemon,
+        board: BoardId,
+        layer: u8,
+        index: u8,
+        has_mode: bool,
+    ) -> Result<Self, String> {
+        let mode = if has_mode {
+            Some(daemon.mode(board, layer)?)
+        } else {
+            None
+        };
+        Ok(Self {
+            mode,
+            brightness: daemon.brightness(board, index)?,
+            color: daemon.color(board, index)?,
+        })
+    }
+}
+
+struct ThreadLayer {
+    layer: u8,
+    index: u8,
+    leds: Option<LayerLeds>,
 }
 
 struct ThreadBoard {
     matrix: Matrix,
     matrix_channel: async_mpsc::UnboundedSender<Matrix>,
     has_matrix: bool,
+    leds_channel: async_mpsc::UnboundedSender<Vec<(usize, LayerLeds)>>,
+    has_mode: bool,
+    layers: Vec<ThreadLayer>,
 }
 
 impl ThreadBoard {
-    fn new(matrix_channel: async_mpsc::UnboundedSender<Matrix>, has_matrix: bool) -> Self {
+    fn new(
+        daemon: &dyn Daemon,
+        board: &Board,
+        matrix_channel: async_mpsc::UnboundedSender<Matrix>,
+        leds_channel: async_mpsc::UnboundedSender<Vec<(usize, LayerLeds)>>,
+    ) -> Self {
+        let has_mode = board.layout().meta.has_mode;
+        let layers = board
+            .layers()
+            .iter()
+            .map(|layer| ThreadLayer {
+                layer: layer.layer(),
+                index: layer.index(),
+                leds: LayerLeds::read(
+                    daemon,
+                    board.board(),
+                    layer.layer(),
+                    layer.index(),
+                    has_mode,
+                )
+                .ok(),
+            })
+            .collect();
         Self {
             matrix: Matrix::default(),
             matrix_channel,
-            has_matrix,
+            has_matrix: board.capabilities().matrix,
+            leds_channel,
+            has_mode,
+            layers,
         }
     }
 }
 
+/// Last known LED settings of the layers of `board` matching `f`
+fn known_leds<'a, F: Fn(&ThreadLayer) -> bool + 'a>(
+    boards: &'a mut HashMap<BoardId, ThreadBoard>,
+    board: BoardId,
+    f: F,
+) -> impl Iterator<Item = &'a mut LayerLeds> + 'a {
+    boards
+        .get_mut(&board)
+        .into_iter()
+        .flat_map(|board| board.layers.iter_mut())
+        .filter(move |layer| f(layer))
+        .filter_map(|layer| layer.leds.as_mut())
+}
+
 struct Thread {
     daemon: Box<dyn Daemon>,
     boards: RefCell<HashMap<BoardId, ThreadBoard>>,
     client: Weak<ThreadClient>,
     response_channel: async_mpsc::UnboundedSender<ThreadResponse>,
     matrix_get_rate: Cell<Option<Duration>>,
+    restarts: Cell<u32>,
 }
 
 impl Thread {
@@ -239,6 +462,7 @@ impl Thread {
             response_channel,
             boards: RefCell::new(HashMap::new()),
             matrix_get_rate: Cell::new(None),
+            restarts: Cell::new(0),
         }
     }
 
@@ -255,6 +479,7 @@ impl Thread {
                         if let Some(rate) = self_.matrix_get_rate.get() {
                             Delay::new(rate).await;
                             self_.matrix_refresh_all();
+                            self_.check_died();
                         } else {
                             Delay::new(Duration::from_millis(100)).await;
                         }
@@ -262,6 +487,16 @@ impl Thread {
                 }))
                 .unwrap();
 
+            spawner
+                .spawn_local(clone!(@strong self_ => async move {
+                    loop {
+                        Delay::new(LEDS_POLL_INTERVAL).await;
+                        self_.leds_refresh_all();
+                        self_.check_died();
+                    }
+                }))
+                .unwrap();
+
             pool.run_until(async move {
                 while let Some(set) = channel.next().await {
                     if !self_.handle_set(set) {
@@ -277,11 +512,28 @@ impl Thread {
             return true;
         }
 
+        // Don't apply a stale value the caller has already given up on
+        if set
+            .deadline
+            .map_or(false, |deadline| Instant::now() > deadline)
+        {
+            set.reply(Err(SetError::TimedOut));
+            return true;
+        }
+
         let resp = match set.inner {
             SetEnum::KeyMap(Item { key, value }) => {
                 self.daemon.keymap_set(key.0, key.1, key.2, key.3, value)
             }
             SetEnum::Color(Item { key, value }) => self.daemon.set_color(key.0, key.1, value),
+            SetEnum::ColorsFrame(Item { key, ref value }) => {
+                let res = self.daemon.set_colors_frame(key, value.clone());
+                if let (Ok(()), Some(client)) = (&res, self.client.upgrade()) {
+                    let mut frame_counters = client.frame_counters.lock().unwrap();
+                    frame_counters.entry(key).or_default().add_frame();
+                }
+                res
+            }
             SetEnum::Brightness(Item { key, value }) => {
                 self.daemon.set_brightness(key.0, key.1, value)
             }
@@ -289,19 +541,129 @@ impl Thread {
                 self.daemon.set_mode(key.0, key.1, value.0, value.1)
             }
             SetEnum::LedSave(board) => self.daemon.led_save(board),
+            SetEnum::Macro(Item { key, ref value }) => {
+                self.daemon.macro_set(key.0, key.1, value.clone())
+            }
             SetEnum::MatrixGetRate(Item { value, .. }) => {
                 self.matrix_get_rate.set(value);
                 Ok(())
             }
+            SetEnum::Flash(Item { key, ref value }) => self.flash(key, &value.0, &value.1),
             SetEnum::Refresh => self.refresh(),
             SetEnum::Exit => return false,
         };
 
-        set.reply(resp);
+        match &resp {
+            Ok(()) => self.update_known_leds(&set.inner),
+            Err(_) => self.check_died(),
+        }
+
+        set.reply(resp.map_err(SetError::Failed));
 
         true
     }
 
+    /// Record LED settings changed through this client, so they aren't
+    /// reported as external changes
+    fn update_known_leds(&self, set_enum: &SetEnum) {
+        let mut boards = self.boards.borrow_mut();
+        match set_enum {
+            SetEnum::Color(Item { key, value }) => {
+                for leds in known_leds(&mut boards, key.0, |layer| layer.index == key.1) {
+                    leds.color = *value;
+                }
+            }
+            SetEnum::Brightness(Item { key, value }) => {
+                for leds in known_leds(&mut boards, key.0, |layer| layer.index == key.1) {
+                    leds.brightness = *value;
+                }
+            }
+            SetEnum::Mode(Item { key, value }) => {
+                for leds in known_leds(&mut boards, key.0, |layer| layer.layer == key.1) {
+                    leds.mode = Some(*value);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Send LED settings that changed since they were last read or set
+    fn leds_refresh_all(&self) {
+        if !self.daemon.leds_may_have_changed() {
+            return;
+        }
+
+        for (k, v) in self.boards.borrow_mut().iter_mut() {
+            let mut changed = Vec::new();
+            for (i, layer) in v.layers.iter_mut().enumerate() {
+                let leds = match LayerLeds::read(
+                    &*self.daemon,
+                    *k,
+                    layer.layer,
+                    layer.index,
+                    v.has_mode,
+                ) {
+                    Ok(leds) => leds,
+                    Err(err) => {
+                        debug!("Failed to read layer LEDs: {}", err);
+                        continue;
+                    }
+                };
+                if layer.leds != Some(leds) {
+                    layer.leds = Some(leds);
+                    changed.push((i, leds));
+                }
+            }
+            if !changed.is_empty() {
+                let _ = v.leds_channel.unbounded_send(changed);
+            }
+        }
+    }
+
+    /// If the daemon has died, remove its boards and try to start a new one
+    fn check_died(&self) {
+        let restarts = self.restarts.get();
+        // Already gave up on this daemon
+        if restarts > MAX_DAEMON_RESTARTS {
+            return;
+        }
+
+        let reason = match self.daemon.died() {
+            Some(reason) => reason,
+            None => return,
+        };
+
+        // `BoardId`s of the old daemon are no longer valid
+        for (id, _) in self.boards.borrow_mut().drain() {
+            let _ = self
+                .response_channel
+                .unbounded_send(ThreadResponse::BoardRemoved(id));
+        }
+
+        let reason = if restarts == MAX_DAEMON_RESTARTS {
+            self.restarts.set(restarts + 1);
+            format!(
+                "{}; giving up after restarting {} times",
+                reason, MAX_DAEMON_RESTARTS
+            )
+        } else {
+            self.restarts.set(restarts + 1);
+            match self.daemon.restart().and_then(|()| self.refresh()) {
+                Ok(()) => {
+                    // Recovered, so later unrelated failures get their own
+                    // restarts
+                    self.restarts.set(0);
+                    format!("{}; restarted", reason)
+                }
+                Err(err) => format!("{}; failed to restart: {}", reason, err),
+            }
+        };
+
+        let _ = self
+            .response_channel
+            .unbounded_send(ThreadResponse::DaemonDied(reason));
+    }
+
     fn matrix_refresh_all(&self) {
         for (k, v) in self.boards.borrow_mut().iter_mut() {
             if !v.has_matrix {
@@ -321,6 +683,26 @@ impl Thread {
         }
     }
 
+    fn flash(
+        &self,
+        board: BoardId,
+        image: &[u8],
+        progress: &async_mpsc::UnboundedSender<FlashProgress>,
+    ) -> Result<(), String> {
+        self.daemon.enter_bootloader(board)?;
+        // The board is replaced by its bootloader, and comes back with a new
+        // `BoardId` when it restarts
+        if self.boards.borrow_mut().remove(&board).is_some() {
+            let _ = self
+                .response_channel
+                .unbounded_send(ThreadResponse::BoardRemoved(board));
+        }
+        let mut bootloader = DaemonBootloader::open(&*self.daemon)?;
+        flash(&mut bootloader, image, |stage| {
+            let _ = progress.unbounded_send(stage);
+        })
+    }
+
     fn refresh(&self) -> Result<(), String> {
         let mut boards = self.boards.borrow_mut();
 
@@ -353,14 +735,18 @@ impl Thread {
             }
 
             let (matrix_sender, matrix_reciever) = async_mpsc::unbounded();
+            let (leds_sender, leds_reciever) = async_mpsc::unbounded();
             match Board::new(
                 self.daemon.as_ref(),
                 self.client.upgrade().unwrap(),
                 *i,
                 matrix_reciever,
+                leds_reciever,
             ) {
                 Ok(board) => {
-                    boards.insert(*i, ThreadBoard::new(matrix_sender, board.has_matrix()));
+                    let thread_board =
+                        ThreadBoard::new(self.daemon.as_ref(), &board, matrix_sender, leds_sender);
+                    boards.insert(*i, thread_board);
                     let _ = self
                         .response_channel
                         .unbounded_send(ThreadResponse::BoardAdded(board));
@@ -378,3 +764,113 @@ impl Thread {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{daemon::DaemonDummy, Firmware, FirmwareMetadata};
+
+    /// `ThreadClient` for a dummy Launch, with the boards it has added and
+    /// removed
+    fn dummy_client() -> (Arc<ThreadClient>, Rc<RefCell<Vec<ThreadResponse>>>) {
+        let daemon = DaemonDummy::new(vec!["system76/launch_1".to_string()]).unwrap();
+        let responses = Rc::new(RefCell::new(Vec::new()));
+        let thread_client = ThreadClient::new(
+            Box::new(daemon),
+            clone!(@strong responses => move |response| {
+                responses.borrow_mut().push(response);
+            }),
+        );
+        (thread_client, responses)
+    }
+
+    /// Handle the responses sent so far by the daemon thread
+    fn iterate() {
+        let context = glib::MainContext::ref_thread_default();
+        while context.pending() {
+            context.iteration(false);
+        }
+    }
+
+    fn added_board(responses: &RefCell<Vec<ThreadResponse>>) -> Board {
+        responses
+            .borrow()
+            .iter()
+            .find_map(|response| match response {
+                ThreadResponse::BoardAdded(board) => Some(board.clone()),
+                _ => None,
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn flash_board() {
+        let context = glib::MainContext::new();
+        context.with_thread_default(|| {
+            let (thread_client, responses) = dummy_client();
+            context.block_on(thread_client.refresh()).unwrap();
+            iterate();
+            let board = added_board(&responses);
+            responses.borrow_mut().clear();
+
+            let firmware = Firmware {
+                metadata: FirmwareMetadata {
+                    model: "system76/launch_1".to_string(),
+                    version: "2021-06-01_0123456".to_string(),
+                    image: "launch_1.bin".to_string(),
+                },
+                image: (0..1000).map(|i| i as u8).collect(),
+            };
+            let mut stages = Vec::new();
+            context
+                .block_on(board.flash(&firmware, |stage| stages.push(stage)))
+                .unwrap();
+            assert_eq!(stages.first(), Some(&FlashProgress::Erasing));
+            assert!(stages.contains(&FlashProgress::Writing(1000, 1000)));
+            assert_eq!(stages.last(), Some(&FlashProgress::Done));
+
+            // The board is removed, then added again once it restarts
+            iterate();
+            assert!(matches!(
+                responses.borrow().first(),
+                Some(ThreadResponse::BoardRemoved(_))
+            ));
+            context.block_on(thread_client.refresh()).unwrap();
+            iterate();
+            assert_eq!(added_board(&responses).model(), "system76/launch_1");
+
+            drop(board);
+            responses.borrow_mut().clear();
+            thread_client.close();
+        });
+    }
+
+    #[test]
+    fn colors_frames_superseded() {
+        let context = glib::MainContext::new();
+        context.with_thread_default(|| {
+            let (thread_client, responses) = dummy_client();
+            context.block_on(thread_client.refresh()).unwrap();
+            iterate();
+            let board = added_board(&responses).board();
+
+            // Sent faster than they can be written, so only the last is kept
+            let frames = (0..10).map(|i| {
+                let mut frame = LedFrame::new();
+                frame.push(0, (i, 0, 0));
+                thread_client.set_colors_frame(board, frame)
+            });
+            let results = context.block_on(future::join_all(frames));
+            let (last, superseded) = results.split_last().unwrap();
+            assert!(superseded
+                .iter()
+                .all(|res| matches!(res, Err(SetError::Superseded))));
+            assert_eq!(last, &Ok(()));
+            let fps = thread_client.frames_per_second(board);
+            assert!((1..=10).contains(&fps));
+
+            responses.borrow_mut().clear();
+            thread_client.close();
+        });
+    }
+}
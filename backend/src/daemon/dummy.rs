@@ -1,18 +1,131 @@
-use std::{cell::RefCell, collections::HashMap};
+use serde::{Deserialize, Serialize};
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt,
+    fs::{self, File},
+    io::BufWriter,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
 
-use super::{BoardId, Daemon};
+use super::{BoardCapabilities, BoardId, Daemon, DaemonCommand, LedFrame};
 use crate::{fl, Layout, Matrix};
 
+/// Settings of a dummy board that are persisted between runs
+#[derive(Clone, Default, Deserialize, Serialize)]
+struct BoardDummyState {
+    #[serde(default, with = "super::replay::entries")]
+    keymap: BTreeMap<(u8, u8, u8), u16>,
+    #[serde(default, with = "super::replay::entries")]
+    colors: BTreeMap<u8, (u8, u8, u8)>,
+    #[serde(default, with = "super::replay::entries")]
+    brightnesses: BTreeMap<u8, i32>,
+    #[serde(default, with = "super::replay::entries")]
+    modes: BTreeMap<u8, (u8, u8)>,
+    /// Encoded macros by index
+    #[serde(default, with = "super::replay::entries")]
+    macros: BTreeMap<u8, Vec<u8>>,
+}
+
+impl BoardDummyState {
+    /// State of a board after loading `default.json`
+    fn from_layout(layout: &Layout) -> Self {
+        let mut state = Self::default();
+        let default = &layout.default;
+
+        for (name, scancodes) in &default.map {
+            let (output, input) = match layout.layout.get(name) {
+                Some(electrical) => *electrical,
+                None => continue,
+            };
+            for (layer, scancode_name) in scancodes.iter().enumerate() {
+                if let Some(scancode) = layout.scancode_from_name(scancode_name) {
+                    state.keymap.insert((layer as u8, output, input), scancode);
+                }
+            }
+        }
+
+        // Stored like a real keyboard would, after calibration
+        let calibration = layout.meta.calibration;
+        for (name, hsv) in &default.key_leds {
+            if let (Some(hsv), Some(leds)) = (hsv, layout.leds.get(name)) {
+                let rgb = calibration.apply(hsv.to_rgb());
+                for index in leds {
+                    state.colors.insert(*index, (rgb.r, rgb.g, rgb.b));
+                }
+            }
+        }
+
+        for (layer, keymap_layer) in default.layers.iter().enumerate() {
+            let layer = layer as u8;
+            let index = if layout.meta.has_per_layer {
+                0xf0 + layer
+            } else if layer == 0 {
+                0xff
+            } else {
+                break;
+            };
+            let color = if index == 0xff {
+                let rgb = calibration.apply(keymap_layer.color.to_rgb());
+                (rgb.r, rgb.g, rgb.b)
+            } else {
+                let (h, s) = calibration.apply_hs(keymap_layer.color).to_ints();
+                (h, s, 0)
+            };
+            state.colors.insert(index, color);
+            state.brightnesses.insert(index, keymap_layer.brightness);
+            if let Some(mode) = keymap_layer.mode {
+                state.modes.insert(0xf0 + layer, mode);
+            }
+        }
+
+        state
+    }
+}
+
 struct BoardDummy {
     name: String,
     layout: Layout,
-    keymap: RefCell<HashMap<(u8, u8, u8), u16>>,
-    colors: RefCell<HashMap<u8, (u8, u8, u8)>>,
-    brightnesses: RefCell<HashMap<u8, i32>>,
-    modes: RefCell<HashMap<u8, (u8, u8)>>,
+    /// Number of `(outputs, inputs)` in the key matrix
+    matrix_size: (usize, usize),
+    state: RefCell<BoardDummyState>,
+    /// Colors shown by `set_colors_frame`, over those in `state`. Not saved,
+    /// since effects are shown by the host and gone after a restart.
+    frame_colors: RefCell<BTreeMap<u8, (u8, u8, u8)>>,
 }
 
 impl BoardDummy {
+    fn new(name: String) -> Result<Self, String> {
+        let layout =
+            Layout::from_board(&name).ok_or_else(|| format!("No layout for board '{}'", name))?;
+        let matrix_size = layout
+            .layout
+            .values()
+            .fold((0, 0), |(rows, cols), (output, input)| {
+                (
+                    rows.max(*output as usize + 1),
+                    cols.max(*input as usize + 1),
+                )
+            });
+        let state = RefCell::new(BoardDummyState::from_layout(&layout));
+        Ok(Self {
+            name,
+            layout,
+            matrix_size,
+            state,
+            frame_colors: RefCell::new(BTreeMap::new()),
+        })
+    }
+
+    fn num_macros(&self) -> u8 {
+        if self.layout.meta.is_qmk {
+            DUMMY_NUM_MACROS
+        } else {
+            0
+        }
+    }
+
     fn valid_index(&self, index: u8, allow_key: bool) -> bool {
         if !self.layout.meta.has_per_layer {
             index == 0xff
@@ -31,24 +144,146 @@ impl BoardDummy {
     }
 }
 
+/// Number of macros stored by dummy boards with QMK firmware
+const DUMMY_NUM_MACROS: u8 = 16;
+/// Size of each macro of a dummy board, in bytes
+const DUMMY_MACRO_SIZE: u16 = 128;
+
+// Flash of the emulated bootloader, the same size as the Launch's
+const DUMMY_PAGE_SIZE: usize = 256;
+const DUMMY_FLASH_SIZE: usize = 0xf000;
+
+/// Error the dummy daemon can be made to return, as a real EC might
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DummyError {
+    /// The EC did not respond in time
+    Timeout,
+    /// The EC firmware does not implement the command
+    Unsupported,
+}
+
+impl fmt::Display for DummyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Timeout => write!(f, "Timeout"),
+            Self::Unsupported => write!(f, "Unsupported command"),
+        }
+    }
+}
+
+#[derive(Default)]
+struct DummyControlInner {
+    /// Pressed keys, as `(board, output, input)`
+    pressed: HashSet<(usize, u8, u8)>,
+    /// Errors returned by commands, by name
+    errors: HashMap<String, DummyError>,
+}
+
+/// Handle for simulating key presses and errors on a `DaemonDummy`, which can
+/// be used from any thread
+#[derive(Clone, Default)]
+pub struct DummyControl(Arc<Mutex<DummyControlInner>>);
+
+impl DummyControl {
+    /// Daemon methods `set_error` can make fail, which are all but `exit`
+    pub fn commands() -> impl Iterator<Item = &'static str> {
+        DaemonCommand::names()
+            .iter()
+            .copied()
+            .filter(|name| *name != "exit")
+    }
+
+    /// Press the key at `(output, input)` in the matrix of the `board`th board
+    pub fn press(&self, board: usize, output: u8, input: u8) {
+        self.0
+            .lock()
+            .unwrap()
+            .pressed
+            .insert((board, output, input));
+    }
+
+    pub fn release(&self, board: usize, output: u8, input: u8) {
+        self.0
+            .lock()
+            .unwrap()
+            .pressed
+            .remove(&(board, output, input));
+    }
+
+    /// Make every following call to the daemon method `command` fail with
+    /// `error`, or succeed again if `None`
+    pub fn set_error(&self, command: &str, error: Option<DummyError>) {
+        let mut inner = self.0.lock().unwrap();
+        match error {
+            Some(error) => inner.errors.insert(command.to_string(), error),
+            None => inner.errors.remove(command),
+        };
+    }
+
+    fn error(&self, command: &str) -> Result<(), String> {
+        match self.0.lock().unwrap().errors.get(command) {
+            Some(error) => Err(error.to_string()),
+            None => Ok(()),
+        }
+    }
+
+    fn pressed(&self, board: usize, output: u8, input: u8) -> bool {
+        self.0
+            .lock()
+            .unwrap()
+            .pressed
+            .contains(&(board, output, input))
+    }
+}
+
 pub struct DaemonDummy {
     boards: Vec<BoardDummy>,
+    control: DummyControl,
+    state_path: Option<PathBuf>,
+    /// Flash of the emulated bootloader, while it is open
+    bootloader: RefCell<Option<Vec<u8>>>,
 }
 
 impl DaemonDummy {
-    pub fn new(board_names: Vec<String>) -> Self {
+    /// Create boards with the settings in their layout's `default.json`
+    pub fn new(board_names: Vec<String>) -> Result<Self, String> {
         let boards = board_names
             .into_iter()
-            .map(|name| BoardDummy {
-                layout: Layout::from_board(&name).unwrap(),
-                name,
-                keymap: Default::default(),
-                colors: Default::default(),
-                brightnesses: Default::default(),
-                modes: Default::default(),
-            })
-            .collect();
-        Self { boards }
+            .map(BoardDummy::new)
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            boards,
+            control: DummyControl::default(),
+            state_path: None,
+            bootloader: RefCell::new(None),
+        })
+    }
+
+    /// Load board settings from `path` if it exists, and write them back to
+    /// it whenever they are changed
+    pub fn with_state_file<P: AsRef<Path>>(mut self, path: P) -> Result<Self, String> {
+        let path = path.as_ref();
+        if path.exists() {
+            let json = fs::read_to_string(path).map_err(|err| {
+                format!("Failed to read dummy state '{}': {}", path.display(), err)
+            })?;
+            let mut states: BTreeMap<String, BoardDummyState> = serde_json::from_str(&json)
+                .map_err(|err| {
+                    format!("Failed to parse dummy state '{}': {}", path.display(), err)
+                })?;
+            for board in &self.boards {
+                if let Some(state) = states.remove(&board.name) {
+                    *board.state.borrow_mut() = state;
+                }
+            }
+        }
+        self.state_path = Some(path.to_owned());
+        Ok(self)
+    }
+
+    /// Handle for simulating key presses and errors
+    pub fn control(&self) -> DummyControl {
+        self.control.clone()
     }
 
     fn board(&self, board: BoardId) -> Result<&BoardDummy, String> {
@@ -56,28 +291,75 @@ impl DaemonDummy {
             .get(board.0 as usize)
             .ok_or_else(|| fl!("no-board"))
     }
+
+    /// Write settings to the state file, if there is one
+    fn save_state(&self) {
+        let path = match &self.state_path {
+            Some(path) => path,
+            None => return,
+        };
+        let states = self
+            .boards
+            .iter()
+            .map(|board| (board.name.clone(), board.state.borrow().clone()))
+            .collect::<BTreeMap<_, _>>();
+        let res = File::create(path)
+            .map_err(|err| err.to_string())
+            .and_then(|file| {
+                serde_json::to_writer_pretty(BufWriter::new(file), &states)
+                    .map_err(|err| err.to_string())
+            });
+        if let Err(err) = res {
+            error!("Failed to write dummy state '{}': {}", path.display(), err);
+        }
+    }
 }
 
 impl Daemon for DaemonDummy {
     fn boards(&self) -> Result<Vec<BoardId>, String> {
+        self.control.error("boards")?;
         Ok((0..self.boards.len() as u128).map(BoardId).collect())
     }
 
     fn model(&self, board: BoardId) -> Result<String, String> {
+        self.control.error("model")?;
         Ok(self.board(board)?.name.clone())
     }
 
     fn version(&self, _board: BoardId) -> Result<String, String> {
+        self.control.error("version")?;
         Ok("1970-01-01-deadbee".to_string())
     }
 
+    fn capabilities(&self, board: BoardId) -> Result<BoardCapabilities, String> {
+        self.control.error("capabilities")?;
+        let board = self.board(board)?;
+        Ok(BoardCapabilities {
+            keymap: true,
+            matrix: true,
+            led_save: true,
+            num_layers: Some(board.layout.meta.num_layers),
+            num_macros: board.num_macros(),
+            macro_size: DUMMY_MACRO_SIZE,
+        })
+    }
+
     fn is_fake(&self) -> bool {
         true
     }
 
+    fn leds_may_have_changed(&self) -> bool {
+        false
+    }
+
     fn keymap_get(&self, board: BoardId, layer: u8, output: u8, input: u8) -> Result<u16, String> {
-        let keymap = self.board(board)?.keymap.borrow();
-        Ok(keymap.get(&(layer, output, input)).copied().unwrap_or(0))
+        self.control.error("keymap_get")?;
+        let state = self.board(board)?.state.borrow();
+        Ok(state
+            .keymap
+            .get(&(layer, output, input))
+            .copied()
+            .unwrap_or(0))
     }
 
     fn keymap_set(
@@ -88,78 +370,211 @@ impl Daemon for DaemonDummy {
         input: u8,
         value: u16,
     ) -> Result<(), String> {
-        let mut keymap = self.board(board)?.keymap.borrow_mut();
-        keymap.insert((layer, output, input), value);
+        self.control.error("keymap_set")?;
+        let mut state = self.board(board)?.state.borrow_mut();
+        state.keymap.insert((layer, output, input), value);
+        drop(state);
+        self.save_state();
         Ok(())
     }
 
-    fn matrix_get(&self, _board: BoardId) -> Result<Matrix, String> {
-        Ok(Matrix::new(0, 0, Vec::new().into_boxed_slice()))
+    fn matrix_get(&self, board: BoardId) -> Result<Matrix, String> {
+        self.control.error("matrix_get")?;
+        let (rows, cols) = self.board(board)?.matrix_size;
+        let mut data = vec![0; (rows * cols + 7) / 8];
+        for row in 0..rows {
+            for col in 0..cols {
+                if self.control.pressed(board.0 as usize, row as u8, col as u8) {
+                    let i = row * cols + col;
+                    data[i / 8] |= 1 << (i % 8);
+                }
+            }
+        }
+        Ok(Matrix::new(rows, cols, data.into_boxed_slice()))
     }
 
     fn color(&self, board: BoardId, index: u8) -> Result<(u8, u8, u8), String> {
+        self.control.error("color")?;
         let board = self.board(board)?;
         if !board.valid_index(index, true) {
             return Err(format!("Can't get color index {} {}", index, board.name));
         }
-        Ok(*board.colors.borrow_mut().entry(index).or_default())
+        let frame_colors = board.frame_colors.borrow();
+        let state = board.state.borrow();
+        Ok(frame_colors
+            .get(&index)
+            .or_else(|| state.colors.get(&index))
+            .copied()
+            .unwrap_or_default())
     }
 
     fn set_color(&self, board: BoardId, index: u8, color: (u8, u8, u8)) -> Result<(), String> {
+        self.control.error("set_color")?;
         let board = self.board(board)?;
         if !board.valid_index(index, true) {
             return Err(format!("Can't set color index {}", index));
         }
-        board.colors.borrow_mut().insert(index, color);
+        board.frame_colors.borrow_mut().remove(&index);
+        board.state.borrow_mut().colors.insert(index, color);
+        self.save_state();
+        Ok(())
+    }
+
+    fn set_colors_frame(&self, board: BoardId, frame: LedFrame) -> Result<(), String> {
+        self.control.error("set_colors_frame")?;
+        let board = self.board(board)?;
+        if let Some((index, _)) = frame
+            .iter()
+            .find(|(index, _)| !board.valid_index(*index, true))
+        {
+            return Err(format!("Can't set color index {}", index));
+        }
+        board.frame_colors.borrow_mut().extend(frame.iter());
         Ok(())
     }
 
-    fn max_brightness(&self, _board: BoardId) -> Result<i32, String> {
-        Ok(100)
+    fn max_brightness(&self, board: BoardId) -> Result<i32, String> {
+        self.control.error("max_brightness")?;
+        // Launch uses the full range of a byte, like the real firmware
+        if self.board(board)?.layout.meta.has_per_layer {
+            Ok(255)
+        } else {
+            Ok(100)
+        }
     }
 
     fn brightness(&self, board: BoardId, index: u8) -> Result<i32, String> {
+        self.control.error("brightness")?;
         let board = self.board(board)?;
         if !board.valid_index(index, false) {
             return Err(format!("Can't get brightness index {}", index));
         }
-        Ok(*board.brightnesses.borrow_mut().entry(index).or_default())
+        let state = board.state.borrow();
+        Ok(state.brightnesses.get(&index).copied().unwrap_or_default())
     }
 
     fn set_brightness(&self, board: BoardId, index: u8, brightness: i32) -> Result<(), String> {
+        self.control.error("set_brightness")?;
         let board = self.board(board)?;
         if !board.valid_index(index, false) {
             return Err(format!("Can't set brightness index {}", index));
         }
-        board.brightnesses.borrow_mut().insert(index, brightness);
+        board
+            .state
+            .borrow_mut()
+            .brightnesses
+            .insert(index, brightness);
+        self.save_state();
         Ok(())
     }
 
     fn mode(&self, board: BoardId, layer: u8) -> Result<(u8, u8), String> {
+        self.control.error("mode")?;
         let index = layer + 0xf0;
         let board = self.board(board)?;
         if !board.valid_index(index, false) {
             return Err(format!("Can't get mode index {}", index));
         }
-        Ok(*board.modes.borrow_mut().entry(index).or_default())
+        let state = board.state.borrow();
+        Ok(state.modes.get(&index).copied().unwrap_or_default())
     }
 
     fn set_mode(&self, board: BoardId, layer: u8, mode: u8, speed: u8) -> Result<(), String> {
+        self.control.error("set_mode")?;
         let index = layer + 0xf0;
         let board = self.board(board)?;
         if !board.valid_index(index, false) {
             return Err(format!("Can't get mode index {}", index));
         }
-        board.modes.borrow_mut().insert(index, (mode, speed));
+        board.state.borrow_mut().modes.insert(index, (mode, speed));
+        self.save_state();
         Ok(())
     }
 
     fn led_save(&self, board: BoardId) -> Result<(), String> {
+        self.control.error("led_save")?;
         self.board(board)?;
         Ok(())
     }
 
+    fn macro_get(&self, board: BoardId, index: u8) -> Result<Vec<u8>, String> {
+        self.control.error("macro_get")?;
+        let board = self.board(board)?;
+        if index >= board.num_macros() {
+            return Err(format!("Can't get macro {}", index));
+        }
+        let state = board.state.borrow();
+        Ok(state.macros.get(&index).cloned().unwrap_or_else(|| vec![0]))
+    }
+
+    fn macro_set(&self, board: BoardId, index: u8, data: Vec<u8>) -> Result<(), String> {
+        self.control.error("macro_set")?;
+        let board = self.board(board)?;
+        if index >= board.num_macros() {
+            return Err(format!("Can't set macro {}", index));
+        }
+        if data.len() > DUMMY_MACRO_SIZE as usize {
+            return Err(format!("Macro of {} bytes is too long", data.len()));
+        }
+        board.state.borrow_mut().macros.insert(index, data);
+        self.save_state();
+        Ok(())
+    }
+
+    fn enter_bootloader(&self, board: BoardId) -> Result<(), String> {
+        self.control.error("enter_bootloader")?;
+        self.board(board)?;
+        Ok(())
+    }
+
+    fn bootloader_open(&self) -> Result<(usize, usize), String> {
+        self.control.error("bootloader_open")?;
+        *self.bootloader.borrow_mut() = Some(vec![0xff; DUMMY_FLASH_SIZE]);
+        Ok((DUMMY_PAGE_SIZE, DUMMY_FLASH_SIZE))
+    }
+
+    fn bootloader_erase(&self) -> Result<(), String> {
+        self.control.error("bootloader_erase")?;
+        let mut bootloader = self.bootloader.borrow_mut();
+        let flash = bootloader.as_mut().ok_or("bootloader not open")?;
+        for byte in flash.iter_mut() {
+            *byte = 0xff;
+        }
+        Ok(())
+    }
+
+    fn bootloader_write(&self, address: usize, data: Vec<u8>) -> Result<(), String> {
+        self.control.error("bootloader_write")?;
+        let mut bootloader = self.bootloader.borrow_mut();
+        let flash = bootloader.as_mut().ok_or("bootloader not open")?;
+        flash
+            .get_mut(address..address + data.len())
+            .ok_or_else(|| format!("Can't write address {:#x}", address))?
+            .copy_from_slice(&data);
+        Ok(())
+    }
+
+    fn bootloader_read(&self, address: usize, len: usize) -> Result<Vec<u8>, String> {
+        self.control.error("bootloader_read")?;
+        let bootloader = self.bootloader.borrow();
+        let flash = bootloader.as_ref().ok_or("bootloader not open")?;
+        flash
+            .get(address..address + len)
+            .map(<[u8]>::to_vec)
+            .ok_or_else(|| format!("Can't read address {:#x}", address))
+    }
+
+    fn bootloader_reset(&self) -> Result<(), String> {
+        self.control.error("bootloader_reset")?;
+        self.bootloader
+            .borrow_mut()
+            .take()
+            .ok_or("bootloader not open")?;
+        Ok(())
+    }
+
     fn refresh(&self) -> Result<(), String> {
+        self.control.error("refresh")?;
         Ok(())
     }
 
@@ -167,3 +582,79 @@ impl Daemon for DaemonDummy {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn launch() -> DaemonDummy {
+        DaemonDummy::new(vec!["system76/launch_1".to_string()]).unwrap()
+    }
+
+    #[test]
+    fn seeded_from_default() {
+        let daemon = launch();
+        let board = daemon.boards().unwrap()[0];
+        let layout = Layout::from_board("system76/launch_1").unwrap();
+        let (output, input) = layout.layout["K00"];
+        let esc = layout.scancode_from_name("ESC").unwrap();
+        assert_eq!(daemon.keymap_get(board, 0, output, input).unwrap(), esc);
+        assert_eq!(daemon.brightness(board, 0xf0).unwrap(), 176);
+        assert_eq!(daemon.mode(board, 0).unwrap(), (7, 127));
+    }
+
+    #[test]
+    fn macros() {
+        let daemon = launch();
+        let board = daemon.boards().unwrap()[0];
+        assert_eq!(
+            daemon.capabilities(board).unwrap().num_macros,
+            DUMMY_NUM_MACROS
+        );
+        assert_eq!(daemon.macro_get(board, 0).unwrap(), [0]);
+        daemon.macro_set(board, 1, vec![2, 4, 3, 4, 0]).unwrap();
+        assert_eq!(daemon.macro_get(board, 1).unwrap(), [2, 4, 3, 4, 0]);
+        assert!(daemon.macro_get(board, DUMMY_NUM_MACROS).is_err());
+        assert!(daemon
+            .macro_set(board, 0, vec![0; DUMMY_MACRO_SIZE as usize + 1])
+            .is_err());
+    }
+
+    #[test]
+    fn state_file() {
+        let path = std::env::temp_dir().join(format!(
+            "keyboard-configurator-dummy-{}.json",
+            std::process::id()
+        ));
+        let _ = fs::remove_file(&path);
+
+        let daemon = launch().with_state_file(&path).unwrap();
+        let board = daemon.boards().unwrap()[0];
+        daemon.keymap_set(board, 1, 2, 3, 0x1234).unwrap();
+        daemon.set_brightness(board, 0xf1, 42).unwrap();
+        drop(daemon);
+
+        let daemon = launch().with_state_file(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(daemon.keymap_get(board, 1, 2, 3).unwrap(), 0x1234);
+        assert_eq!(daemon.brightness(board, 0xf1).unwrap(), 42);
+    }
+
+    #[test]
+    fn control() {
+        let daemon = launch();
+        let control = daemon.control();
+        let board = daemon.boards().unwrap()[0];
+
+        control.press(0, 1, 2);
+        assert_eq!(daemon.matrix_get(board).unwrap().get(1, 2), Some(true));
+        control.release(0, 1, 2);
+        assert_eq!(daemon.matrix_get(board).unwrap().get(1, 2), Some(false));
+
+        control.set_error("led_save", Some(DummyError::Unsupported));
+        assert!(daemon.led_save(board).is_err());
+        assert!(daemon.keymap_get(board, 0, 0, 0).is_ok());
+        control.set_error("led_save", None);
+        assert!(daemon.led_save(board).is_ok());
+    }
+}
@@ -1,8 +1,13 @@
-use serde::{Deserialize, Serialize};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt::Write;
+
+use crate::version_date;
 
 mod client;
 mod daemon_thread;
 mod dummy;
+mod record;
+mod replay;
 mod server;
 
 #[cfg(target_os = "linux")]
@@ -10,11 +15,88 @@ mod s76power;
 #[cfg(target_os = "linux")]
 pub use self::s76power::*;
 
-pub use self::{client::*, daemon_thread::*, dummy::*, server::*};
+#[cfg(all(test, target_os = "linux"))]
+mod virtual_ec;
+
+pub use self::{client::*, daemon_thread::*, dummy::*, record::*, replay::*, server::*};
+
+/// Version of the JSON protocol spoken between `DaemonClient` and `DaemonServer`
+///
+/// Must be incremented whenever an existing command changes its arguments or
+/// return type. Adding a new command only requires it to be listed in the
+/// capabilities of the `DaemonHello`.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// First line written by `DaemonServer::run`, before any command is read
+#[derive(Debug, Deserialize, Serialize)]
+pub struct DaemonHello {
+    /// `PROTOCOL_VERSION` of the daemon
+    pub version: u32,
+    /// Names of the `DaemonCommand`s the daemon can handle
+    pub capabilities: Vec<String>,
+}
+
+impl DaemonHello {
+    pub fn new() -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            capabilities: DaemonCommand::names()
+                .iter()
+                .map(|x| x.to_string())
+                .collect(),
+        }
+    }
+}
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
 pub struct BoardId(u128);
 
+/// Features supported by the firmware of a board
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct BoardCapabilities {
+    /// Keymap can be read and changed
+    pub keymap: bool,
+    /// Key matrix can be read, to show which keys are pressed
+    pub matrix: bool,
+    /// LED settings are only kept across power loss after `led_save`
+    pub led_save: bool,
+    /// Number of keymap layers in the firmware, if it could be determined
+    #[serde(default)]
+    pub num_layers: Option<u8>,
+    /// Number of macros that can be stored, or 0 if macros are unsupported
+    #[serde(default)]
+    pub num_macros: u8,
+    /// Size of the encoded form of each macro, in bytes
+    #[serde(default)]
+    pub macro_size: u16,
+}
+
+/// Build date of the first EC firmware with the keymap and matrix commands
+const KEYMAP_MATRIX_SINCE: (u16, u8, u8) = (2020, 8, 1);
+
+impl BoardCapabilities {
+    /// Capabilities of EC firmware with `version`, known from the date it
+    /// was built, or `None` if the version isn't dated and the firmware has
+    /// to be probed instead
+    ///
+    /// The number of layers isn't part of the version, so the layout's is
+    /// used.
+    pub fn from_version(version: &str, is_usb: bool) -> Option<Self> {
+        let has_keymap = version_date(version)? >= KEYMAP_MATRIX_SINCE;
+        Some(Self {
+            keymap: has_keymap,
+            matrix: has_keymap,
+            // Only the Launch firmware waits for a request to save LED
+            // settings, which laptop ECs don't store at all
+            led_save: is_usb,
+            num_layers: None,
+            // The EC protocol has no commands for macros yet
+            num_macros: 0,
+            macro_size: 0,
+        })
+    }
+}
+
 #[derive(Deserialize, Serialize, Default, PartialEq, Clone)]
 pub struct Matrix {
     rows: usize,
@@ -47,8 +129,82 @@ impl Matrix {
     }
 }
 
+/// Colors of many LEDs of a board, set at once by `set_colors_frame`
+///
+/// Serialized as a string of hex digits, with 4 bytes (index, red, green and
+/// blue) for each LED. This is a fraction of the size of json arrays, and
+/// can't break the line based protocol.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LedFrame(Vec<(u8, (u8, u8, u8))>);
+
+impl LedFrame {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, index: u8, color: (u8, u8, u8)) {
+        self.0.push((index, color));
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (u8, (u8, u8, u8))> + '_ {
+        self.0.iter().copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl Serialize for LedFrame {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut hex = String::with_capacity(self.0.len() * 8);
+        for (index, (r, g, b)) in self.iter() {
+            write!(hex, "{:02x}{:02x}{:02x}{:02x}", index, r, g, b).unwrap();
+        }
+        serializer.serialize_str(&hex)
+    }
+}
+
+impl<'de> Deserialize<'de> for LedFrame {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let hex = String::deserialize(deserializer)?;
+        if hex.len() % 8 != 0 || !hex.is_ascii() {
+            return Err(de::Error::custom("invalid length of LED frame"));
+        }
+        let byte = |i: usize| {
+            u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)
+                .map_err(|_| de::Error::custom("invalid hex in LED frame"))
+        };
+        let mut frame = Self::new();
+        for i in (0..hex.len() / 2).step_by(4) {
+            frame.push(byte(i)?, (byte(i + 1)?, byte(i + 2)?, byte(i + 3)?));
+        }
+        Ok(frame)
+    }
+}
+
 pub trait DaemonClientTrait: Send + 'static {
     fn send_command(&self, command: DaemonCommand) -> Result<DaemonResponse, String>;
+
+    fn is_fake(&self) -> bool {
+        false
+    }
+
+    fn leds_may_have_changed(&self) -> bool {
+        true
+    }
+
+    fn died(&self) -> Option<String> {
+        None
+    }
+
+    fn restart(&self) -> Result<(), String> {
+        Err("Daemon can not be restarted".to_string())
+    }
 }
 
 // Define Daemon trait, DaemonCommand enum, and DaemonResponse enum
@@ -63,6 +219,23 @@ macro_rules! commands {
                 false
             }
 
+            /// `false` if LED settings are known to not have changed outside of
+            /// this daemon since the last call, so they don't need to be read
+            fn leds_may_have_changed(&self) -> bool {
+                true
+            }
+
+            /// Reason the daemon stopped, if it is no longer usable
+            fn died(&self) -> Option<String> {
+                None
+            }
+
+            /// Replace a daemon that has died. Existing `BoardId`s are not
+            /// preserved, so boards need to be enumerated again.
+            fn restart(&self) -> Result<(), String> {
+                Err("Daemon can not be restarted".to_string())
+            }
+
             fn dispatch_command_to_method(&self, command: DaemonCommand) -> Result<DaemonResponse, String> {
                 match command {
                 $(
@@ -75,7 +248,7 @@ macro_rules! commands {
         }
 
         #[allow(non_camel_case_types)]
-        #[derive(Deserialize, Serialize)]
+        #[derive(Clone, Deserialize, Serialize)]
         #[serde(tag = "t", content = "c")]
         pub enum DaemonCommand {
         $(
@@ -83,8 +256,24 @@ macro_rules! commands {
         ),*
         }
 
+        impl DaemonCommand {
+            /// Name of the command, as used in the `t` field of its json
+            pub fn name(&self) -> &'static str {
+                match self {
+                $(
+                    DaemonCommand::$func{..} => stringify!($func),
+                )*
+                }
+            }
+
+            /// Names of all commands known to this build
+            pub fn names() -> &'static [&'static str] {
+                &[$( stringify!($func) ),*]
+            }
+        }
+
         #[allow(non_camel_case_types)]
-        #[derive(Deserialize, Serialize)]
+        #[derive(Clone, Deserialize, Serialize)]
         #[serde(tag = "t", content = "c")]
         pub enum DaemonResponse {
         $(
@@ -103,6 +292,22 @@ macro_rules! commands {
                 }
             }
         )*
+
+            fn is_fake(&self) -> bool {
+                DaemonClientTrait::is_fake(self)
+            }
+
+            fn leds_may_have_changed(&self) -> bool {
+                DaemonClientTrait::leds_may_have_changed(self)
+            }
+
+            fn died(&self) -> Option<String> {
+                DaemonClientTrait::died(self)
+            }
+
+            fn restart(&self) -> Result<(), String> {
+                DaemonClientTrait::restart(self)
+            }
         }
     };
 }
@@ -111,21 +316,67 @@ commands! {
     fn boards(&self) -> Result<Vec<BoardId>, String>;
     fn model(&self, board: BoardId) -> Result<String, String>;
     fn version(&self, board: BoardId) -> Result<String, String>;
+    fn capabilities(&self, board: BoardId) -> Result<BoardCapabilities, String>;
     fn refresh(&self) -> Result<(), String>;
     fn keymap_get(&self, board: BoardId, layer: u8, output: u8, input: u8) -> Result<u16, String>;
     fn keymap_set(&self, board: BoardId, layer: u8, output: u8, input: u8, value: u16) -> Result<(), String>;
     fn matrix_get(&self, board: BoardId) -> Result<Matrix, String>;
     fn color(&self, board: BoardId, index: u8) -> Result<(u8, u8, u8), String>;
     fn set_color(&self, board: BoardId, index: u8, color: (u8, u8, u8)) -> Result<(), String>;
+    // The EC has no command for setting many LEDs at once, so the server
+    // sets each LED of the frame with its own command. Frames of a full
+    // keyboard may be written at less than the rate they are sent.
+    fn set_colors_frame(&self, board: BoardId, frame: LedFrame) -> Result<(), String>;
     fn max_brightness(&self, board: BoardId) -> Result<i32, String>;
     fn brightness(&self, board: BoardId, index: u8) -> Result<i32, String>;
     fn set_brightness(&self, board: BoardId, index: u8, brightness: i32) -> Result<(), String>;
     fn mode(&self, board: BoardId, layer: u8) -> Result<(u8, u8), String>;
     fn set_mode(&self, board: BoardId, layer: u8, mode: u8, speed: u8) -> Result<(), String>;
     fn led_save(&self, board: BoardId) -> Result<(), String>;
+    fn macro_get(&self, board: BoardId, index: u8) -> Result<Vec<u8>, String>;
+    fn macro_set(&self, board: BoardId, index: u8, data: Vec<u8>) -> Result<(), String>;
+    // Flashing firmware needs root, so it is done by the daemon.
+    // `enter_bootloader` resets a board into its bootloader, which replaces
+    // the board until it is flashed through the `bootloader_` commands.
+    fn enter_bootloader(&self, board: BoardId) -> Result<(), String>;
+    fn bootloader_open(&self) -> Result<(usize, usize), String>;
+    fn bootloader_erase(&self) -> Result<(), String>;
+    fn bootloader_write(&self, address: usize, data: Vec<u8>) -> Result<(), String>;
+    fn bootloader_read(&self, address: usize, len: usize) -> Result<Vec<u8>, String>;
+    fn bootloader_reset(&self) -> Result<(), String>;
     fn exit(&self) -> Result<(), String>;
 }
 
 fn err_str<E: std::fmt::Debug>(err: E) -> String {
     format!("{:?}", err)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn capabilities_from_version() {
+        let capabilities = BoardCapabilities::from_version("2021-03-10_1a2b3c4", true).unwrap();
+        assert!(capabilities.keymap && capabilities.matrix && capabilities.led_save);
+        assert_eq!(capabilities.num_layers, None);
+
+        let capabilities = BoardCapabilities::from_version("2020-01-01_1a2b3c4", false).unwrap();
+        assert!(!capabilities.keymap && !capabilities.matrix && !capabilities.led_save);
+
+        assert!(BoardCapabilities::from_version("1.2.3", true).is_none());
+        assert!(BoardCapabilities::from_version("", true).is_none());
+    }
+
+    #[test]
+    fn led_frame_json() {
+        let mut frame = LedFrame::new();
+        frame.push(0, (255, 0, 16));
+        frame.push(0xf0, (1, 2, 3));
+        let json = serde_json::to_string(&frame).unwrap();
+        assert_eq!(json, "\"00ff0010f0010203\"");
+        assert_eq!(serde_json::from_str::<LedFrame>(&json).unwrap(), frame);
+        assert!(serde_json::from_str::<LedFrame>("\"00ff00\"").is_err());
+        assert!(serde_json::from_str::<LedFrame>("\"00ff00zz\"").is_err());
+    }
+}
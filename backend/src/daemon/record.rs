@@ -0,0 +1,83 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fs::File,
+    io::{LineWriter, Write},
+    path::Path,
+    time::Instant,
+};
+
+use super::{Daemon, DaemonClientTrait, DaemonCommand, DaemonResponse, SessionEntry};
+
+/// Wraps a daemon, logging every command so the session can be played back
+/// with `DaemonReplay`
+///
+/// Each command is appended to the file as it is run, so the session is kept
+/// if the configurator crashes or is killed. Repeated commands with the same
+/// result, like the LED and matrix polls, are only logged the first time.
+pub struct DaemonRecorder {
+    inner: Box<dyn Daemon>,
+    start: Instant,
+    file: RefCell<LineWriter<File>>,
+    /// Last result logged for each command, as json
+    last_responses: RefCell<HashMap<String, String>>,
+}
+
+impl DaemonRecorder {
+    pub fn new<P: AsRef<Path>>(inner: Box<dyn Daemon>, path: P) -> Result<Self, String> {
+        let path = path.as_ref();
+        let file = File::create(path)
+            .map_err(|err| format!("Failed to create session '{}': {}", path.display(), err))?;
+        Ok(Self {
+            inner,
+            start: Instant::now(),
+            file: RefCell::new(LineWriter::new(file)),
+            last_responses: RefCell::new(HashMap::new()),
+        })
+    }
+
+    fn log(&self, entry: SessionEntry) -> Result<(), String> {
+        let command = serde_json::to_string(&entry.command).map_err(|err| err.to_string())?;
+        let response = serde_json::to_string(&entry.response).map_err(|err| err.to_string())?;
+        let mut last_responses = self.last_responses.borrow_mut();
+        if last_responses.get(&command) == Some(&response) {
+            return Ok(());
+        }
+        let line = serde_json::to_string(&entry).map_err(|err| err.to_string())?;
+        writeln!(self.file.borrow_mut(), "{}", line)
+            .map_err(|err| format!("Failed to write session: {}", err))?;
+        last_responses.insert(command, response);
+        Ok(())
+    }
+}
+
+impl DaemonClientTrait for DaemonRecorder {
+    fn send_command(&self, command: DaemonCommand) -> Result<DaemonResponse, String> {
+        let response = self.inner.dispatch_command_to_method(command.clone());
+        let entry = SessionEntry {
+            time: self.start.elapsed().as_millis() as u64,
+            command,
+            response: response.clone(),
+        };
+        if let Err(err) = self.log(entry) {
+            error!("{}", err);
+        }
+        response
+    }
+
+    fn is_fake(&self) -> bool {
+        self.inner.is_fake()
+    }
+
+    fn leds_may_have_changed(&self) -> bool {
+        self.inner.leds_may_have_changed()
+    }
+
+    fn died(&self) -> Option<String> {
+        self.inner.died()
+    }
+
+    fn restart(&self) -> Result<(), String> {
+        self.inner.restart()
+    }
+}
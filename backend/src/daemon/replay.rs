@@ -0,0 +1,602 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, BTreeSet, HashMap, VecDeque},
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::Path,
+    time::Instant,
+};
+
+use super::{BoardCapabilities, BoardId, DaemonClientTrait, DaemonCommand, DaemonResponse, Matrix};
+use crate::fl;
+
+/// Serialize a map with non-string keys as a list of `[key, value]` pairs
+pub(super) mod entries {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::collections::BTreeMap;
+
+    pub fn serialize<K, V, S>(map: &BTreeMap<K, V>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        K: Serialize,
+        V: Serialize,
+        S: Serializer,
+    {
+        serializer.collect_seq(map.iter())
+    }
+
+    pub fn deserialize<'de, K, V, D>(deserializer: D) -> Result<BTreeMap<K, V>, D::Error>
+    where
+        K: Deserialize<'de> + Ord,
+        V: Deserialize<'de>,
+        D: Deserializer<'de>,
+    {
+        Ok(Vec::<(K, V)>::deserialize(deserializer)?
+            .into_iter()
+            .collect())
+    }
+}
+
+/// State of the matrix, `time` milliseconds after the session started
+#[derive(Clone, Deserialize, Serialize)]
+pub struct MatrixFrame {
+    pub time: u64,
+    pub matrix: Matrix,
+}
+
+/// A board, as it was before any setting was changed during the session
+#[derive(Clone, Deserialize, Serialize)]
+pub struct SessionBoard {
+    pub id: BoardId,
+    #[serde(default)]
+    pub model: String,
+    #[serde(default)]
+    pub version: String,
+    #[serde(default)]
+    pub capabilities: BoardCapabilities,
+    #[serde(default)]
+    pub max_brightness: i32,
+    /// Scancodes by `(layer, output, input)`
+    #[serde(default, with = "entries")]
+    pub keymap: BTreeMap<(u8, u8, u8), u16>,
+    /// Colors by LED index
+    #[serde(default, with = "entries")]
+    pub colors: BTreeMap<u8, (u8, u8, u8)>,
+    /// Brightnesses by LED index
+    #[serde(default, with = "entries")]
+    pub brightnesses: BTreeMap<u8, i32>,
+    /// `(mode, speed)` by layer
+    #[serde(default, with = "entries")]
+    pub modes: BTreeMap<u8, (u8, u8)>,
+    /// Encoded macros by index
+    #[serde(default, with = "entries")]
+    pub macros: BTreeMap<u8, Vec<u8>>,
+    /// Every distinct matrix state read, in order
+    #[serde(default)]
+    pub matrix: Vec<MatrixFrame>,
+}
+
+impl SessionBoard {
+    pub fn new(id: BoardId) -> Self {
+        Self {
+            id,
+            model: String::new(),
+            version: String::new(),
+            capabilities: BoardCapabilities::default(),
+            max_brightness: 0,
+            keymap: BTreeMap::new(),
+            colors: BTreeMap::new(),
+            brightnesses: BTreeMap::new(),
+            modes: BTreeMap::new(),
+            macros: BTreeMap::new(),
+            matrix: Vec::new(),
+        }
+    }
+}
+
+/// A command sent to a daemon, `time` milliseconds after the session started
+#[derive(Clone, Deserialize, Serialize)]
+pub struct SessionEntry {
+    pub time: u64,
+    pub command: DaemonCommand,
+    pub response: Result<DaemonResponse, String>,
+}
+
+/// Recorded interaction with a daemon, written by `DaemonRecorder` and
+/// played back by `DaemonReplay`
+///
+/// Saved as one `SessionEntry` of json per line. Only the log is saved, and
+/// `boards` are built from it again when loaded.
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub struct Session {
+    pub boards: Vec<SessionBoard>,
+    pub log: Vec<SessionEntry>,
+}
+
+/// Setting that may be written during a session
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Setting {
+    Keymap(u8, u8, u8),
+    Color(u8),
+    Brightness(u8),
+    Mode(u8),
+    Macro(u8),
+}
+
+impl Session {
+    /// Build a session from a command log, taking the first value read for
+    /// each setting as its initial state
+    ///
+    /// Values read after the setting was written are not part of the initial
+    /// state, since the replay daemon applies writes itself.
+    pub fn from_log(log: Vec<SessionEntry>) -> Self {
+        let mut boards = Vec::<SessionBoard>::new();
+        let mut written = BTreeSet::new();
+
+        fn board_mut(boards: &mut Vec<SessionBoard>, id: BoardId) -> &mut SessionBoard {
+            match boards.iter().position(|i| i.id == id) {
+                Some(i) => &mut boards[i],
+                None => {
+                    boards.push(SessionBoard::new(id));
+                    boards.last_mut().unwrap()
+                }
+            }
+        }
+
+        for entry in &log {
+            let response = match &entry.response {
+                Ok(response) => response,
+                Err(_) => continue,
+            };
+            match (&entry.command, response) {
+                (DaemonCommand::boards {}, DaemonResponse::boards(ids)) => {
+                    for id in ids {
+                        board_mut(&mut boards, *id);
+                    }
+                }
+                (DaemonCommand::model { board }, DaemonResponse::model(model)) => {
+                    board_mut(&mut boards, *board).model = model.clone();
+                }
+                (DaemonCommand::version { board }, DaemonResponse::version(version)) => {
+                    board_mut(&mut boards, *board).version = version.clone();
+                }
+                (
+                    DaemonCommand::capabilities { board },
+                    DaemonResponse::capabilities(capabilities),
+                ) => {
+                    board_mut(&mut boards, *board).capabilities = *capabilities;
+                }
+                (DaemonCommand::max_brightness { board }, DaemonResponse::max_brightness(max)) => {
+                    board_mut(&mut boards, *board).max_brightness = *max;
+                }
+                (
+                    DaemonCommand::keymap_get {
+                        board,
+                        layer,
+                        output,
+                        input,
+                    },
+                    DaemonResponse::keymap_get(value),
+                ) => {
+                    if !written.contains(&(*board, Setting::Keymap(*layer, *output, *input))) {
+                        let board = board_mut(&mut boards, *board);
+                        board
+                            .keymap
+                            .entry((*layer, *output, *input))
+                            .or_insert(*value);
+                    }
+                }
+                (DaemonCommand::color { board, index }, DaemonResponse::color(color)) => {
+                    if !written.contains(&(*board, Setting::Color(*index))) {
+                        let board = board_mut(&mut boards, *board);
+                        board.colors.entry(*index).or_insert(*color);
+                    }
+                }
+                (
+                    DaemonCommand::brightness { board, index },
+                    DaemonResponse::brightness(brightness),
+                ) => {
+                    if !written.contains(&(*board, Setting::Brightness(*index))) {
+                        let board = board_mut(&mut boards, *board);
+                        board.brightnesses.entry(*index).or_insert(*brightness);
+                    }
+                }
+                (DaemonCommand::mode { board, layer }, DaemonResponse::mode(mode)) => {
+                    if !written.contains(&(*board, Setting::Mode(*layer))) {
+                        let board = board_mut(&mut boards, *board);
+                        board.modes.entry(*layer).or_insert(*mode);
+                    }
+                }
+                (DaemonCommand::macro_get { board, index }, DaemonResponse::macro_get(data)) => {
+                    if !written.contains(&(*board, Setting::Macro(*index))) {
+                        let board = board_mut(&mut boards, *board);
+                        board.macros.entry(*index).or_insert_with(|| data.clone());
+                    }
+                }
+                (DaemonCommand::matrix_get { board }, DaemonResponse::matrix_get(matrix)) => {
+                    let board = board_mut(&mut boards, *board);
+                    if board.matrix.last().map_or(true, |i| &i.matrix != matrix) {
+                        board.matrix.push(MatrixFrame {
+                            time: entry.time,
+                            matrix: matrix.clone(),
+                        });
+                    }
+                }
+                (
+                    DaemonCommand::keymap_set {
+                        board,
+                        layer,
+                        output,
+                        input,
+                        ..
+                    },
+                    _,
+                ) => {
+                    written.insert((*board, Setting::Keymap(*layer, *output, *input)));
+                }
+                (DaemonCommand::set_color { board, index, .. }, _) => {
+                    written.insert((*board, Setting::Color(*index)));
+                }
+                (DaemonCommand::set_colors_frame { board, frame }, _) => {
+                    for (index, _) in frame.iter() {
+                        written.insert((*board, Setting::Color(index)));
+                    }
+                }
+                (DaemonCommand::set_brightness { board, index, .. }, _) => {
+                    written.insert((*board, Setting::Brightness(*index)));
+                }
+                (DaemonCommand::set_mode { board, layer, .. }, _) => {
+                    written.insert((*board, Setting::Mode(*layer)));
+                }
+                (DaemonCommand::macro_set { board, index, .. }, _) => {
+                    written.insert((*board, Setting::Macro(*index)));
+                }
+                _ => {}
+            }
+        }
+
+        Self { boards, log }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)
+            .map_err(|err| format!("Failed to open session '{}': {}", path.display(), err))?;
+        let lines = contents
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .collect::<Vec<_>>();
+        let mut log = Vec::new();
+        for (i, line) in lines.iter().enumerate() {
+            match serde_json::from_str(line) {
+                Ok(entry) => log.push(entry),
+                // Cut off by the recording being killed
+                Err(err) if i == lines.len() - 1 => {
+                    warn!("Ignoring incomplete last line of session: {}", err);
+                }
+                Err(err) => {
+                    return Err(format!(
+                        "Failed to parse session '{}' line {}: {}",
+                        path.display(),
+                        i + 1,
+                        err
+                    ));
+                }
+            }
+        }
+        Ok(Self::from_log(log))
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        let path = path.as_ref();
+        let write_err =
+            |err: String| format!("Failed to write session '{}': {}", path.display(), err);
+        let file = File::create(path)
+            .map_err(|err| format!("Failed to create session '{}': {}", path.display(), err))?;
+        let mut writer = BufWriter::new(file);
+        for entry in &self.log {
+            let line = serde_json::to_string(entry).map_err(|err| write_err(err.to_string()))?;
+            writeln!(writer, "{}", line).map_err(|err| write_err(err.to_string()))?;
+        }
+        writer.flush().map_err(|err| write_err(err.to_string()))
+    }
+}
+
+/// How `DaemonReplay` advances through recorded matrix frames
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReplayTiming {
+    /// Return the frame recorded at the same time since the start of the session
+    Realtime,
+    /// Return the next frame on each `matrix_get`, repeating the last one
+    Step,
+}
+
+/// Daemon playing back a `Session`
+///
+/// Boards start in the recorded state, and writes change that state as they
+/// would on a real board. Each call of a command gets the error or success
+/// of the next recorded call of the same command, repeating the last one
+/// once they run out.
+pub struct DaemonReplay {
+    boards: Vec<RefCell<SessionBoard>>,
+    /// Recorded errors of each command, as json, in order, with `None` for
+    /// successes
+    results: RefCell<HashMap<String, VecDeque<Option<String>>>>,
+    timing: ReplayTiming,
+    start: Instant,
+    steps: RefCell<HashMap<BoardId, usize>>,
+}
+
+impl DaemonReplay {
+    pub fn new(session: Session, timing: ReplayTiming) -> Self {
+        let mut results = HashMap::<_, VecDeque<_>>::new();
+        for entry in &session.log {
+            let command = match serde_json::to_string(&entry.command) {
+                Ok(command) => command,
+                Err(_) => continue,
+            };
+            let error = entry.response.as_ref().err().cloned();
+            results.entry(command).or_default().push_back(error);
+        }
+
+        Self {
+            boards: session.boards.into_iter().map(RefCell::new).collect(),
+            results: RefCell::new(results),
+            timing,
+            start: Instant::now(),
+            steps: RefCell::new(HashMap::new()),
+        }
+    }
+
+    fn board(&self, board: BoardId) -> Result<&RefCell<SessionBoard>, String> {
+        self.boards
+            .iter()
+            .find(|i| i.borrow().id == board)
+            .ok_or_else(|| fl!("no-board"))
+    }
+
+    fn matrix(&self, board: &SessionBoard) -> Matrix {
+        let frames = &board.matrix;
+        let frame = match self.timing {
+            ReplayTiming::Realtime => {
+                let elapsed = self.start.elapsed().as_millis() as u64;
+                frames.iter().rev().find(|i| i.time <= elapsed)
+            }
+            ReplayTiming::Step => {
+                let mut steps = self.steps.borrow_mut();
+                let step = steps.entry(board.id).or_insert(0);
+                let frame = frames.get(*step).or_else(|| frames.last());
+                *step += 1;
+                frame
+            }
+        };
+        frame.map(|i| i.matrix.clone()).unwrap_or_default()
+    }
+}
+
+impl DaemonClientTrait for DaemonReplay {
+    fn send_command(&self, command: DaemonCommand) -> Result<DaemonResponse, String> {
+        if let Ok(json) = serde_json::to_string(&command) {
+            let mut results = self.results.borrow_mut();
+            if let Some(results) = results.get_mut(&json) {
+                let error = if results.len() > 1 {
+                    results.pop_front().flatten()
+                } else {
+                    results.front().cloned().flatten()
+                };
+                if let Some(err) = error {
+                    return Err(err);
+                }
+            }
+        }
+
+        Ok(match command {
+            DaemonCommand::boards {} => {
+                DaemonResponse::boards(self.boards.iter().map(|i| i.borrow().id).collect())
+            }
+            DaemonCommand::model { board } => {
+                DaemonResponse::model(self.board(board)?.borrow().model.clone())
+            }
+            DaemonCommand::version { board } => {
+                DaemonResponse::version(self.board(board)?.borrow().version.clone())
+            }
+            DaemonCommand::capabilities { board } => {
+                DaemonResponse::capabilities(self.board(board)?.borrow().capabilities)
+            }
+            DaemonCommand::refresh {} => DaemonResponse::refresh(()),
+            DaemonCommand::keymap_get {
+                board,
+                layer,
+                output,
+                input,
+            } => {
+                let board = self.board(board)?.borrow();
+                let value = board.keymap.get(&(layer, output, input)).copied();
+                DaemonResponse::keymap_get(value.unwrap_or(0))
+            }
+            DaemonCommand::keymap_set {
+                board,
+                layer,
+                output,
+                input,
+                value,
+            } => {
+                let mut board = self.board(board)?.borrow_mut();
+                board.keymap.insert((layer, output, input), value);
+                DaemonResponse::keymap_set(())
+            }
+            DaemonCommand::matrix_get { board } => {
+                DaemonResponse::matrix_get(self.matrix(&self.board(board)?.borrow()))
+            }
+            DaemonCommand::color { board, index } => {
+                let board = self.board(board)?.borrow();
+                DaemonResponse::color(board.colors.get(&index).copied().unwrap_or_default())
+            }
+            DaemonCommand::set_color {
+                board,
+                index,
+                color,
+            } => {
+                self.board(board)?.borrow_mut().colors.insert(index, color);
+                DaemonResponse::set_color(())
+            }
+            DaemonCommand::set_colors_frame { board, frame } => {
+                let mut board = self.board(board)?.borrow_mut();
+                board.colors.extend(frame.iter());
+                DaemonResponse::set_colors_frame(())
+            }
+            DaemonCommand::max_brightness { board } => {
+                DaemonResponse::max_brightness(self.board(board)?.borrow().max_brightness)
+            }
+            DaemonCommand::brightness { board, index } => {
+                let board = self.board(board)?.borrow();
+                let brightness = board.brightnesses.get(&index).copied();
+                DaemonResponse::brightness(brightness.unwrap_or_default())
+            }
+            DaemonCommand::set_brightness {
+                board,
+                index,
+                brightness,
+            } => {
+                let mut board = self.board(board)?.borrow_mut();
+                board.brightnesses.insert(index, brightness);
+                DaemonResponse::set_brightness(())
+            }
+            DaemonCommand::mode { board, layer } => {
+                let board = self.board(board)?.borrow();
+                DaemonResponse::mode(board.modes.get(&layer).copied().unwrap_or_default())
+            }
+            DaemonCommand::set_mode {
+                board,
+                layer,
+                mode,
+                speed,
+            } => {
+                let mut board = self.board(board)?.borrow_mut();
+                board.modes.insert(layer, (mode, speed));
+                DaemonResponse::set_mode(())
+            }
+            DaemonCommand::led_save { board } => {
+                self.board(board)?;
+                DaemonResponse::led_save(())
+            }
+            DaemonCommand::macro_get { board, index } => {
+                let board = self.board(board)?.borrow();
+                let data = board.macros.get(&index).cloned();
+                DaemonResponse::macro_get(data.unwrap_or_else(|| vec![0]))
+            }
+            DaemonCommand::macro_set { board, index, data } => {
+                self.board(board)?.borrow_mut().macros.insert(index, data);
+                DaemonResponse::macro_set(())
+            }
+            DaemonCommand::enter_bootloader { .. }
+            | DaemonCommand::bootloader_open {}
+            | DaemonCommand::bootloader_erase {}
+            | DaemonCommand::bootloader_write { .. }
+            | DaemonCommand::bootloader_read { .. }
+            | DaemonCommand::bootloader_reset {} => {
+                return Err("Firmware can't be flashed in a replayed session".to_string());
+            }
+            DaemonCommand::exit {} => DaemonResponse::exit(()),
+        })
+    }
+
+    fn is_fake(&self) -> bool {
+        true
+    }
+
+    fn leds_may_have_changed(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::daemon::{Daemon, DaemonDummy, DaemonRecorder};
+
+    #[test]
+    fn record_and_replay() {
+        let path = std::env::temp_dir().join(format!(
+            "keyboard-configurator-session-{}.json",
+            std::process::id()
+        ));
+
+        let recorder = DaemonRecorder::new(
+            Box::new(DaemonDummy::new(vec!["system76/launch_1".to_string()]).unwrap()),
+            &path,
+        )
+        .unwrap();
+        let board = recorder.boards().unwrap()[0];
+        recorder.keymap_set(board, 0, 1, 2, 0x1234).unwrap();
+        assert_eq!(recorder.keymap_get(board, 0, 1, 2).unwrap(), 0x1234);
+        assert_eq!(recorder.keymap_get(board, 0, 2, 2).unwrap(), 0);
+        recorder.set_brightness(board, 0xf0, 100).unwrap();
+        assert!(recorder.set_brightness(board, 0x01, 100).is_err());
+        // Repeated with the same result, so only logged once
+        for _ in 0..3 {
+            recorder.brightness(board, 0xf0).unwrap();
+        }
+
+        // Loaded while still recording, as after a crash
+        let session = Session::load(&path).unwrap();
+        drop(recorder);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(session.log.len(), 7);
+        assert_eq!(session.boards.len(), 1);
+        // Written before it was read, so not part of the initial state
+        assert_eq!(session.boards[0].keymap.get(&(0, 1, 2)), None);
+        assert_eq!(session.boards[0].keymap.get(&(0, 2, 2)), Some(&0));
+
+        let replay = DaemonReplay::new(session, ReplayTiming::Step);
+        assert_eq!(replay.boards().unwrap(), vec![board]);
+        assert_eq!(replay.model(board).unwrap(), "system76/launch_1");
+        assert_eq!(replay.keymap_get(board, 0, 1, 2).unwrap(), 0);
+        replay.keymap_set(board, 0, 1, 2, 0x1234).unwrap();
+        assert_eq!(replay.keymap_get(board, 0, 1, 2).unwrap(), 0x1234);
+        assert!(replay.set_brightness(board, 0x01, 100).is_err());
+    }
+
+    #[test]
+    fn replay_errors_in_order() {
+        let board = BoardId(0);
+        let entry = |response| SessionEntry {
+            time: 0,
+            command: DaemonCommand::led_save { board },
+            response,
+        };
+        let mut session = Session::from_log(vec![
+            entry(Ok(DaemonResponse::led_save(()))),
+            entry(Err("failed".to_string())),
+            entry(Ok(DaemonResponse::led_save(()))),
+        ]);
+        session.boards.push(SessionBoard::new(board));
+
+        let replay = DaemonReplay::new(session, ReplayTiming::Step);
+        assert!(replay.led_save(board).is_ok());
+        assert_eq!(replay.led_save(board), Err("failed".to_string()));
+        assert!(replay.led_save(board).is_ok());
+        assert!(replay.led_save(board).is_ok());
+    }
+
+    #[test]
+    fn replay_matrix_steps() {
+        let board = BoardId(0);
+        let frame = |time, data: u8| MatrixFrame {
+            time,
+            matrix: Matrix::new(1, 8, vec![data].into_boxed_slice()),
+        };
+        let mut session = Session::default();
+        session.boards.push(SessionBoard {
+            matrix: vec![frame(0, 0), frame(100, 0b10), frame(200, 0)],
+            ..SessionBoard::new(board)
+        });
+
+        let replay = DaemonReplay::new(session, ReplayTiming::Step);
+        let pressed = |matrix: Matrix| matrix.get(0, 1).unwrap();
+        assert!(!pressed(replay.matrix_get(board).unwrap()));
+        assert!(pressed(replay.matrix_get(board).unwrap()));
+        assert!(!pressed(replay.matrix_get(board).unwrap()));
+        assert!(!pressed(replay.matrix_get(board).unwrap()));
+    }
+}
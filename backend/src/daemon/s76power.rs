@@ -1,14 +1,28 @@
 // Note: Linux only
-// Need to watch properties of each object?
 // TODO: Hotplug detection support
 
-use std::iter::Iterator;
-use zbus::{dbus_proxy, fdo::ObjectManagerProxy, Connection};
+use std::{
+    fs,
+    iter::Iterator,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+};
+use zbus::{
+    dbus_proxy,
+    fdo::{DBusProxy, ObjectManagerProxy},
+    Connection,
+};
 
-use super::{err_str, BoardId, Daemon, Matrix};
+use super::{err_str, BoardCapabilities, BoardId, Daemon, LedFrame, Matrix};
 use crate::{fl, Rgb};
 
 const DBUS_NAME: &str = "com.system76.PowerDaemon";
+const DBUS_PATH: &str = "/com/system76/PowerDaemon";
+const DMI_DIR: &str = "/sys/class/dmi/id";
 
 #[dbus_proxy(interface = "com.system76.PowerDaemon.Keyboard")]
 trait Keyboard {
@@ -30,21 +44,89 @@ trait Keyboard {
 }
 
 struct Keyboard {
+    path: String,
     proxy: KeyboardProxy<'static>,
 }
 
 impl Keyboard {
-    fn new(path: &str) -> Result<Self, String> {
-        let connection = Connection::new_system().map_err(err_str)?;
+    fn new(connection: Connection, path: &str) -> Result<Self, String> {
         let proxy =
             KeyboardProxy::new_for_owned(connection, DBUS_NAME.to_string(), path.to_string())
                 .map_err(err_str)?;
-        Ok(Self { proxy })
+        Ok(Self {
+            path: path.to_string(),
+            proxy,
+        })
+    }
+}
+
+/// Watch for `PropertiesChanged` signals on the keyboard objects, setting the
+/// returned flag when one is received
+///
+/// Uses its own connection, since signals would otherwise be mixed with
+/// method replies.
+fn watch_properties(paths: &[&str]) -> Result<Arc<AtomicBool>, String> {
+    let connection = Connection::new_system().map_err(err_str)?;
+    let dbus = DBusProxy::new(&connection).map_err(err_str)?;
+    for path in paths {
+        let rule = format!(
+            "type='signal',sender='{}',path='{}',interface='org.freedesktop.DBus.Properties',member='PropertiesChanged'",
+            DBUS_NAME, path
+        );
+        dbus.add_match(&rule).map_err(err_str)?;
+    }
+
+    let changed = Arc::new(AtomicBool::new(false));
+    let changed_clone = changed.clone();
+    thread::spawn(move || loop {
+        match connection.receive_message() {
+            Ok(message) => {
+                let member = message
+                    .header()
+                    .ok()
+                    .and_then(|header| header.member().ok().flatten().map(str::to_string));
+                if member.as_deref() == Some("PropertiesChanged") {
+                    changed_clone.store(true, Ordering::SeqCst);
+                }
+            }
+            Err(err) => {
+                error!("Failed to receive keyboard property changes: {}", err);
+                break;
+            }
+        }
+    });
+
+    Ok(changed)
+}
+
+/// Read a value from the DMI table, as exposed in sysfs
+fn dmi_value(dmi_dir: &Path, name: &str) -> Result<String, String> {
+    let path = dmi_dir.join(name);
+    fs::read_to_string(&path)
+        .map(|value| value.trim().to_string())
+        .map_err(|err| format!("Failed to read {}: {}", path.display(), err))
+}
+
+/// Board model of the System76 laptop the daemon is running on, like
+/// `system76/darp6`
+///
+/// The power daemon does not expose the model, so it is read from DMI.
+fn dmi_model(dmi_dir: &Path) -> Result<String, String> {
+    let vendor = dmi_value(dmi_dir, "sys_vendor")?;
+    if vendor != "System76" {
+        return Err(format!("Unsupported system vendor '{}'", vendor));
     }
+    Ok(format!(
+        "system76/{}",
+        dmi_value(dmi_dir, "product_version")?
+    ))
 }
 
 pub struct DaemonS76Power {
     boards: Vec<Keyboard>,
+    dmi_dir: PathBuf,
+    /// Set when a property changes, if properties are being watched
+    changed: Option<Arc<AtomicBool>>,
 }
 
 impl DaemonS76Power {
@@ -57,21 +139,46 @@ impl DaemonS76Power {
 
 impl DaemonS76Power {
     pub fn new() -> Result<Self, String> {
+        let connection = Connection::new_system().map_err(err_str)?;
+        let mut daemon = Self::new_for_connection(connection, DMI_DIR)?;
+
+        let paths = daemon
+            .boards
+            .iter()
+            .map(|x| x.path.as_str())
+            .collect::<Vec<_>>();
+        match watch_properties(&paths) {
+            Ok(changed) => daemon.changed = Some(changed),
+            // Fall back to polling
+            Err(err) => error!("Failed to watch keyboard properties: {}", err),
+        }
+
+        Ok(daemon)
+    }
+
+    fn new_for_connection<P: Into<PathBuf>>(
+        connection: Connection,
+        dmi_dir: P,
+    ) -> Result<Self, String> {
         let mut boards = Vec::new();
 
-        let connection = Connection::new_system().map_err(err_str)?;
         let proxy =
-            ObjectManagerProxy::new_for(&connection, DBUS_NAME, "/com/system76/PowerDaemon")
-                .map_err(err_str)?;
+            ObjectManagerProxy::new_for(&connection, DBUS_NAME, DBUS_PATH).map_err(err_str)?;
         let objects = proxy.get_managed_objects().map_err(err_str)?;
 
-        for path in objects.keys() {
+        let mut paths = objects.keys().collect::<Vec<_>>();
+        paths.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+        for path in paths {
             if path.starts_with("/com/system76/PowerDaemon/keyboard") {
-                boards.push(Keyboard::new(&path)?);
+                boards.push(Keyboard::new(connection.clone(), &path)?);
             }
         }
 
-        Ok(Self { boards })
+        Ok(Self {
+            boards,
+            dmi_dir: dmi_dir.into(),
+            changed: None,
+        })
     }
 }
 
@@ -80,13 +187,28 @@ impl Daemon for DaemonS76Power {
         Ok((0..self.boards.len() as u128).map(BoardId).collect())
     }
 
-    fn model(&self, _board: BoardId) -> Result<String, String> {
-        // XXX
-        Ok("system76/darp6".to_string())
+    fn leds_may_have_changed(&self) -> bool {
+        self.changed
+            .as_ref()
+            .map_or(true, |changed| changed.swap(false, Ordering::SeqCst))
     }
 
-    fn version(&self, _board: BoardId) -> Result<String, String> {
-        Err("Unimplemented".to_string())
+    fn model(&self, board: BoardId) -> Result<String, String> {
+        self.board(board)?;
+        dmi_model(&self.dmi_dir)
+    }
+
+    fn version(&self, board: BoardId) -> Result<String, String> {
+        // The keyboard is controlled by the EC, which is part of the
+        // System76 Open Firmware reported as the BIOS version
+        self.board(board)?;
+        dmi_value(&self.dmi_dir, "bios_version")
+    }
+
+    fn capabilities(&self, board: BoardId) -> Result<BoardCapabilities, String> {
+        // Only the backlight is exposed by system76-power
+        self.board(board)?;
+        Ok(BoardCapabilities::default())
     }
 
     fn keymap_get(
@@ -132,6 +254,14 @@ impl Daemon for DaemonS76Power {
             .map_err(err_str)
     }
 
+    fn set_colors_frame(&self, board: BoardId, frame: LedFrame) -> Result<(), String> {
+        // Only has the color of the whole keyboard
+        for (index, color) in frame.iter() {
+            self.set_color(board, index, color)?;
+        }
+        Ok(())
+    }
+
     fn max_brightness(&self, board: BoardId) -> Result<i32, String> {
         Ok(self.board(board)?.proxy.max_brightness().map_err(err_str)?)
     }
@@ -165,6 +295,38 @@ impl Daemon for DaemonS76Power {
         Err("Unimplemented".to_string())
     }
 
+    fn macro_get(&self, _board: BoardId, _index: u8) -> Result<Vec<u8>, String> {
+        Err("Unimplemented".to_string())
+    }
+
+    fn macro_set(&self, _board: BoardId, _index: u8, _data: Vec<u8>) -> Result<(), String> {
+        Err("Unimplemented".to_string())
+    }
+
+    fn enter_bootloader(&self, _board: BoardId) -> Result<(), String> {
+        Err("Unimplemented".to_string())
+    }
+
+    fn bootloader_open(&self) -> Result<(usize, usize), String> {
+        Err("Unimplemented".to_string())
+    }
+
+    fn bootloader_erase(&self) -> Result<(), String> {
+        Err("Unimplemented".to_string())
+    }
+
+    fn bootloader_write(&self, _address: usize, _data: Vec<u8>) -> Result<(), String> {
+        Err("Unimplemented".to_string())
+    }
+
+    fn bootloader_read(&self, _address: usize, _len: usize) -> Result<Vec<u8>, String> {
+        Err("Unimplemented".to_string())
+    }
+
+    fn bootloader_reset(&self) -> Result<(), String> {
+        Err("Unimplemented".to_string())
+    }
+
     fn refresh(&self) -> Result<(), String> {
         Ok(())
     }
@@ -173,3 +335,172 @@ impl Daemon for DaemonS76Power {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap, convert::TryFrom, env, os::unix::net::UnixStream, process,
+        sync::atomic, thread,
+    };
+    use zbus::{
+        dbus_interface,
+        zvariant::{OwnedObjectPath, OwnedValue},
+        Guid, ObjectServer,
+    };
+
+    use super::*;
+
+    /// Mock of the `ObjectManager` of system76-power
+    struct MockObjectManager {
+        keyboards: Vec<String>,
+    }
+
+    #[dbus_interface(name = "org.freedesktop.DBus.ObjectManager")]
+    impl MockObjectManager {
+        fn get_managed_objects(
+            &self,
+        ) -> HashMap<OwnedObjectPath, HashMap<String, HashMap<String, OwnedValue>>> {
+            self.keyboards
+                .iter()
+                .map(|path| {
+                    let interfaces = vec![(
+                        "com.system76.PowerDaemon.Keyboard".to_string(),
+                        HashMap::new(),
+                    )]
+                    .into_iter()
+                    .collect();
+                    (
+                        OwnedObjectPath::try_from(path.as_str()).unwrap(),
+                        interfaces,
+                    )
+                })
+                .collect()
+        }
+    }
+
+    /// Mock of a keyboard exposed by system76-power
+    struct MockKeyboard {
+        brightness: i32,
+        color: String,
+    }
+
+    #[dbus_interface(name = "com.system76.PowerDaemon.Keyboard")]
+    impl MockKeyboard {
+        #[dbus_interface(property, name = "brightness")]
+        fn brightness(&self) -> i32 {
+            self.brightness
+        }
+
+        #[dbus_interface(property, name = "brightness")]
+        fn set_brightness(&mut self, value: i32) {
+            self.brightness = value;
+        }
+
+        #[dbus_interface(property, name = "color")]
+        fn color(&self) -> String {
+            self.color.clone()
+        }
+
+        #[dbus_interface(property, name = "color")]
+        fn set_color(&mut self, value: String) {
+            self.color = value;
+        }
+
+        #[dbus_interface(property, name = "max_brightness")]
+        fn max_brightness(&self) -> i32 {
+            255
+        }
+
+        #[dbus_interface(property, name = "name")]
+        fn name(&self) -> String {
+            "system76_acpi::kbd_backlight".to_string()
+        }
+    }
+
+    /// Serve a mock power daemon over a peer-to-peer connection, returning the
+    /// client end
+    fn mock_connection() -> Connection {
+        let (server_stream, client_stream) = UnixStream::pair().unwrap();
+
+        thread::spawn(move || {
+            let guid = Guid::generate();
+            let connection = Connection::new_unix_server(server_stream, &guid).unwrap();
+            let mut object_server = ObjectServer::new(&connection);
+            let keyboard = format!("{}/keyboard0", DBUS_PATH);
+            object_server
+                .at(
+                    DBUS_PATH,
+                    MockObjectManager {
+                        keyboards: vec![keyboard.clone()],
+                    },
+                )
+                .unwrap();
+            object_server
+                .at(
+                    keyboard.as_str(),
+                    MockKeyboard {
+                        brightness: 48,
+                        color: "#ff8000".to_string(),
+                    },
+                )
+                .unwrap();
+            // Ends with an error when the client disconnects
+            while object_server.try_handle_next().is_ok() {}
+        });
+
+        Connection::new_unix_client(client_stream, false).unwrap()
+    }
+
+    /// Create a fake sysfs DMI directory
+    fn mock_dmi(values: &[(&str, &str)]) -> PathBuf {
+        static COUNT: atomic::AtomicUsize = atomic::AtomicUsize::new(0);
+        let dir = env::temp_dir().join(format!(
+            "s76power-dmi-{}-{}",
+            process::id(),
+            COUNT.fetch_add(1, atomic::Ordering::SeqCst)
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        for (name, value) in values {
+            fs::write(dir.join(name), format!("{}\n", value)).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn s76power_properties() {
+        let dmi_dir = mock_dmi(&[
+            ("sys_vendor", "System76"),
+            ("product_version", "oryp6"),
+            ("bios_version", "2021-03-04_abc1234"),
+        ]);
+        let daemon = DaemonS76Power::new_for_connection(mock_connection(), &dmi_dir).unwrap();
+
+        let boards = daemon.boards().unwrap();
+        assert_eq!(boards.len(), 1);
+        let board = boards[0];
+
+        assert_eq!(daemon.model(board).unwrap(), "system76/oryp6");
+        assert_eq!(daemon.version(board).unwrap(), "2021-03-04_abc1234");
+        assert_eq!(daemon.max_brightness(board).unwrap(), 255);
+
+        assert_eq!(daemon.brightness(board, 0xff).unwrap(), 48);
+        daemon.set_brightness(board, 0xff, 100).unwrap();
+        assert_eq!(daemon.brightness(board, 0xff).unwrap(), 100);
+        assert!(daemon.brightness(board, 0xf0).is_err());
+
+        assert_eq!(daemon.color(board, 0xff).unwrap(), (0xff, 0x80, 0x00));
+        daemon.set_color(board, 0xff, (0x12, 0x34, 0x56)).unwrap();
+        assert_eq!(daemon.color(board, 0xff).unwrap(), (0x12, 0x34, 0x56));
+
+        assert!(daemon.model(BoardId(1)).is_err());
+
+        fs::remove_dir_all(dmi_dir).unwrap();
+    }
+
+    #[test]
+    fn dmi_model_not_system76() {
+        let dmi_dir = mock_dmi(&[("sys_vendor", "Other"), ("product_version", "1.0")]);
+        assert!(dmi_model(&dmi_dir).is_err());
+        fs::remove_dir_all(dmi_dir).unwrap();
+    }
+}
@@ -11,8 +11,14 @@ use std::{
 };
 use uuid::Uuid;
 
-use super::{err_str, BoardId, Daemon, DaemonCommand};
-use crate::Matrix;
+use super::{
+    err_str, BoardCapabilities, BoardId, Daemon, DaemonCommand, DaemonHello, DaemonResponse,
+    LedFrame,
+};
+use crate::{Bootloader, Matrix};
+
+/// Most keymap layers probed for, which is more than any firmware has
+const MAX_LAYERS: u8 = 16;
 
 pub struct DaemonServer<R: Read + Send + 'static, W: Write + Send + 'static> {
     hidapi: RefCell<Option<HidApi>>,
@@ -21,6 +27,7 @@ pub struct DaemonServer<R: Read + Send + 'static, W: Write + Send + 'static> {
     write: W,
     boards: RefCell<HashMap<BoardId, (Ec<Box<dyn Access>>, Option<DeviceInfo>)>>,
     board_ids: RefCell<Vec<BoardId>>,
+    bootloader: RefCell<Option<Box<dyn Bootloader + Send>>>,
 }
 
 impl DaemonServer<io::Stdin, io::Stdout> {
@@ -68,9 +75,32 @@ impl<R: Read + Send + 'static, W: Write + Send + 'static> DaemonServer<R, W> {
             write,
             boards: RefCell::new(boards),
             board_ids: RefCell::new(board_ids),
+            bootloader: RefCell::new(None),
         })
     }
 
+    /// Serve the given ECs instead of probing for hardware
+    #[cfg(test)]
+    pub fn new_with_ecs(read: R, write: W, ecs: Vec<Ec<Box<dyn Access>>>) -> Self {
+        let mut boards = HashMap::new();
+        let mut board_ids = Vec::new();
+        for ec in ecs {
+            let id = BoardId(Uuid::new_v4().as_u128());
+            boards.insert(id, (ec, None));
+            board_ids.push(id);
+        }
+
+        Self {
+            hidapi: RefCell::new(None),
+            running: Cell::new(true),
+            read: BufReader::new(read),
+            write,
+            boards: RefCell::new(boards),
+            board_ids: RefCell::new(board_ids),
+            bootloader: RefCell::new(None),
+        }
+    }
+
     fn have_device(&self, info: &DeviceInfo) -> bool {
         for (_, i) in self.boards.borrow().values() {
             if let Some(i) = i {
@@ -85,21 +115,34 @@ impl<R: Read + Send + 'static, W: Write + Send + 'static> DaemonServer<R, W> {
     }
 
     pub fn run(mut self) -> io::Result<()> {
-        println!("Daemon started");
+        let mut hello_json = serde_json::to_string(&DaemonHello::new())?;
+        hello_json.push('\n');
+        self.write.write_all(hello_json.as_bytes())?;
+        self.write.flush()?;
 
         while self.running.get() {
             let mut command_json = String::new();
-            self.read.read_line(&mut command_json)?;
+            if self.read.read_line(&mut command_json)? == 0 {
+                // Client closed its end of the pipe
+                break;
+            }
 
-            let command = serde_json::from_str::<DaemonCommand>(&command_json)
-                .expect("failed to deserialize command");
-            let response = self.dispatch_command_to_method(command);
+            let response = match parse_command(&command_json) {
+                Ok(command) => self.dispatch_command_to_method(command),
+                Err(err) => {
+                    error!("{}", err);
+                    Err(err)
+                }
+            };
 
-            //TODO: what to do if we fail to serialize result?
-            let mut result_json =
-                serde_json::to_string(&response).expect("failed to serialize result");
+            let mut result_json = serde_json::to_string(&response).unwrap_or_else(|err| {
+                let response: Result<DaemonResponse, String> =
+                    Err(format!("failed to serialize result: {}", err));
+                serde_json::to_string(&response).unwrap()
+            });
             result_json.push('\n');
             self.write.write_all(result_json.as_bytes())?;
+            self.write.flush()?;
         }
 
         Ok(())
@@ -113,6 +156,42 @@ impl<R: Read + Send + 'static, W: Write + Send + 'static> DaemonServer<R, W> {
             Err("failed to find board".to_string())
         }
     }
+
+    fn bootloader(&self) -> Result<RefMut<Box<dyn Bootloader + Send>>, String> {
+        let bootloader = self.bootloader.borrow_mut();
+        if bootloader.is_some() {
+            Ok(RefMut::map(bootloader, |x| x.as_mut().unwrap()))
+        } else {
+            Err("bootloader not open".to_string())
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn open_bootloader() -> Result<Box<dyn Bootloader + Send>, String> {
+    Ok(Box::new(crate::dfu::AtmelDfu::open()?))
+}
+
+/// Only the Linux daemon can talk to the DFU bootloader
+#[cfg(not(target_os = "linux"))]
+fn open_bootloader() -> Result<Box<dyn Bootloader + Send>, String> {
+    Err("flashing firmware is only supported on Linux".to_string())
+}
+
+/// Parse a command, distinguishing commands unknown to this daemon (sent by a
+/// newer client) from malformed json
+fn parse_command(command_json: &str) -> Result<DaemonCommand, String> {
+    serde_json::from_str::<DaemonCommand>(command_json).map_err(|err| {
+        let name = serde_json::from_str::<serde_json::Value>(command_json)
+            .ok()
+            .and_then(|value| Some(value.get("t")?.as_str()?.to_string()));
+        match name {
+            Some(name) if !DaemonCommand::names().contains(&name.as_str()) => {
+                format!("unknown command '{}'", name)
+            }
+            _ => format!("failed to deserialize command: {}", err),
+        }
+    })
 }
 
 impl<R: Read + Send + 'static, W: Write + Send + 'static> Daemon for DaemonServer<R, W> {
@@ -138,6 +217,35 @@ impl<R: Read + Send + 'static, W: Write + Send + 'static> Daemon for DaemonServe
         Ok(version.to_string())
     }
 
+    fn capabilities(&self, board: BoardId) -> Result<BoardCapabilities, String> {
+        let is_hid = unsafe { self.board(board)?.access().is::<AccessHid>() };
+        let version = self.version(board)?;
+        if let Some(capabilities) = BoardCapabilities::from_version(&version, is_hid) {
+            return Ok(capabilities);
+        }
+
+        // Fallback for old firmware without a dated version, which can only
+        // be probed by trying its commands
+        info!("Probing capabilities of firmware '{}'", version);
+        // The firmware rejects reads past its last layer
+        let num_layers = (0..MAX_LAYERS)
+            .take_while(|layer| self.keymap_get(board, *layer, 0, 0).is_ok())
+            .count() as u8;
+        Ok(BoardCapabilities {
+            // Reading is harmless, so these are tested directly
+            keymap: num_layers > 0,
+            matrix: self.matrix_get(board).is_ok(),
+            led_save: is_hid,
+            num_layers: if num_layers > 0 {
+                Some(num_layers)
+            } else {
+                None
+            },
+            num_macros: 0,
+            macro_size: 0,
+        })
+    }
+
     fn keymap_get(&self, board: BoardId, layer: u8, output: u8, input: u8) -> Result<u16, String> {
         let mut ec = self.board(board)?;
         unsafe { ec.keymap_get(layer, output, input).map_err(err_str) }
@@ -180,6 +288,14 @@ impl<R: Read + Send + 'static, W: Write + Send + 'static> Daemon for DaemonServe
         }
     }
 
+    fn set_colors_frame(&self, board: BoardId, frame: LedFrame) -> Result<(), String> {
+        let mut ec = self.board(board)?;
+        for (index, (r, g, b)) in frame.iter() {
+            unsafe { ec.led_set_color(index, r, g, b).map_err(err_str)? }
+        }
+        Ok(())
+    }
+
     fn max_brightness(&self, board: BoardId) -> Result<i32, String> {
         let mut ec = self.board(board)?;
         let index = if unsafe { ec.access().is::<AccessHid>() } {
@@ -217,6 +333,57 @@ impl<R: Read + Send + 'static, W: Write + Send + 'static> Daemon for DaemonServe
         unsafe { ec.led_save().map_err(err_str) }
     }
 
+    fn macro_get(&self, board: BoardId, _index: u8) -> Result<Vec<u8>, String> {
+        self.board(board)?;
+        Err("Macros are not supported by the EC protocol".to_string())
+    }
+
+    fn macro_set(&self, board: BoardId, _index: u8, _data: Vec<u8>) -> Result<(), String> {
+        self.board(board)?;
+        Err("Macros are not supported by the EC protocol".to_string())
+    }
+
+    fn enter_bootloader(&self, board: BoardId) -> Result<(), String> {
+        let mut ec = self.board(board)?;
+        if !unsafe { ec.access().is::<AccessHid>() } {
+            return Err("Only USB keyboards can be flashed".to_string());
+        }
+        // The firmware may refuse, if the bootloader is locked
+        unsafe { ec.reset().map_err(err_str)? };
+        drop(ec);
+        // The board is gone until it is flashed and reset
+        self.boards.borrow_mut().remove(&board);
+        self.board_ids.borrow_mut().retain(|i| *i != board);
+        Ok(())
+    }
+
+    fn bootloader_open(&self) -> Result<(usize, usize), String> {
+        let bootloader = open_bootloader()?;
+        let sizes = (bootloader.page_size(), bootloader.flash_size());
+        *self.bootloader.borrow_mut() = Some(bootloader);
+        Ok(sizes)
+    }
+
+    fn bootloader_erase(&self) -> Result<(), String> {
+        self.bootloader()?.erase()
+    }
+
+    fn bootloader_write(&self, address: usize, data: Vec<u8>) -> Result<(), String> {
+        self.bootloader()?.write(address, &data)
+    }
+
+    fn bootloader_read(&self, address: usize, len: usize) -> Result<Vec<u8>, String> {
+        let mut data = vec![0; len];
+        self.bootloader()?.read(address, &mut data)?;
+        Ok(data)
+    }
+
+    fn bootloader_reset(&self) -> Result<(), String> {
+        self.bootloader()?.reset()?;
+        self.bootloader.borrow_mut().take();
+        Ok(())
+    }
+
     fn refresh(&self) -> Result<(), String> {
         if let Some(api) = &mut *self.hidapi.borrow_mut() {
             // Remove USB boards that are no longer attached
@@ -285,3 +452,27 @@ impl<R: Read + Send + 'static, W: Write + Send + 'static> Daemon for DaemonServe
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_known_command() {
+        let json = serde_json::to_string(&DaemonCommand::boards {}).unwrap();
+        assert_eq!(parse_command(&json).unwrap().name(), "boards");
+    }
+
+    #[test]
+    fn parse_unknown_command() {
+        let err = parse_command(r#"{"t":"frobnicate","c":{}}"#).err().unwrap();
+        assert_eq!(err, "unknown command 'frobnicate'");
+    }
+
+    #[test]
+    fn parse_malformed_command() {
+        let err = parse_command(r#"{"t":"model","c":{}}"#).err().unwrap();
+        assert!(err.starts_with("failed to deserialize command"));
+        assert!(parse_command("not json").is_err());
+    }
+}
@@ -0,0 +1,330 @@
+//! Emulated EC, for testing `DaemonServer` and everything above it without
+//! hardware
+
+use ectool::{Access, Error};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use crate::Layout;
+
+// Command numbers and results of the System76 EC protocol
+const CMD_PROBE: u8 = 1;
+const CMD_BOARD: u8 = 2;
+const CMD_VERSION: u8 = 3;
+const CMD_KEYMAP_GET: u8 = 9;
+const CMD_KEYMAP_SET: u8 = 10;
+const CMD_LED_GET_VALUE: u8 = 11;
+const CMD_LED_SET_VALUE: u8 = 12;
+const CMD_LED_GET_COLOR: u8 = 13;
+const CMD_LED_SET_COLOR: u8 = 14;
+const CMD_LED_GET_MODE: u8 = 15;
+const CMD_LED_SET_MODE: u8 = 16;
+const CMD_MATRIX_GET: u8 = 17;
+const CMD_LED_SAVE: u8 = 18;
+
+const RES_OK: u8 = 0;
+const RES_ERR: u8 = 1;
+
+/// Same as the USB HID transport of Launch
+const DATA_SIZE: usize = 30;
+
+/// Contents of the emulated EC's memory
+pub struct VirtualEcState {
+    pub board: String,
+    pub version: String,
+    pub rows: u8,
+    pub cols: u8,
+    pub layers: u8,
+    /// Scancodes by `(layer, output, input)`
+    pub keymap: HashMap<(u8, u8, u8), u16>,
+    /// `(value, color)` by LED index
+    pub leds: HashMap<u8, (u8, (u8, u8, u8))>,
+    pub max_brightness: u8,
+    /// `(mode, speed)` by layer
+    pub modes: HashMap<u8, (u8, u8)>,
+    /// Pressed keys, as `(output, input)`
+    pub pressed: HashSet<(u8, u8)>,
+    /// Number of times `led_save` was called
+    pub led_saves: usize,
+}
+
+impl VirtualEcState {
+    fn valid_led(&self, index: u8) -> bool {
+        index == 0xff
+            || (index >= 0xf0 && index < 0xf0 + self.layers)
+            || self.leds.contains_key(&index)
+    }
+
+    fn command(&mut self, cmd: u8, data: &mut [u8]) -> u8 {
+        match cmd {
+            CMD_PROBE if data.len() >= 3 => {
+                data[..3].copy_from_slice(&[0x76, 0xEC, 1]);
+            }
+            CMD_BOARD => write_str(data, &self.board),
+            CMD_VERSION => write_str(data, &self.version),
+            CMD_KEYMAP_GET if data.len() >= 5 => {
+                let (layer, output, input) = (data[0], data[1], data[2]);
+                if layer >= self.layers || output >= self.rows || input >= self.cols {
+                    return RES_ERR;
+                }
+                let value = self.keymap.get(&(layer, output, input)).copied();
+                let value = value.unwrap_or(0);
+                data[3] = value as u8;
+                data[4] = (value >> 8) as u8;
+            }
+            CMD_KEYMAP_SET if data.len() >= 5 => {
+                let (layer, output, input) = (data[0], data[1], data[2]);
+                if layer >= self.layers || output >= self.rows || input >= self.cols {
+                    return RES_ERR;
+                }
+                let value = (data[3] as u16) | ((data[4] as u16) << 8);
+                self.keymap.insert((layer, output, input), value);
+            }
+            CMD_LED_GET_VALUE if data.len() >= 3 => {
+                if !self.valid_led(data[0]) {
+                    return RES_ERR;
+                }
+                let (value, _) = self.leds.get(&data[0]).copied().unwrap_or_default();
+                data[1] = value;
+                data[2] = self.max_brightness;
+            }
+            CMD_LED_SET_VALUE if data.len() >= 2 => {
+                if !self.valid_led(data[0]) {
+                    return RES_ERR;
+                }
+                self.leds.entry(data[0]).or_default().0 = data[1].min(self.max_brightness);
+            }
+            CMD_LED_GET_COLOR if data.len() >= 4 => {
+                if !self.valid_led(data[0]) {
+                    return RES_ERR;
+                }
+                let (_, (r, g, b)) = self.leds.get(&data[0]).copied().unwrap_or_default();
+                data[1..4].copy_from_slice(&[r, g, b]);
+            }
+            CMD_LED_SET_COLOR if data.len() >= 4 => {
+                if !self.valid_led(data[0]) {
+                    return RES_ERR;
+                }
+                self.leds.entry(data[0]).or_default().1 = (data[1], data[2], data[3]);
+            }
+            CMD_LED_GET_MODE if data.len() >= 3 => {
+                if data[0] >= self.layers {
+                    return RES_ERR;
+                }
+                let (mode, speed) = self.modes.get(&data[0]).copied().unwrap_or_default();
+                data[1] = mode;
+                data[2] = speed;
+            }
+            CMD_LED_SET_MODE if data.len() >= 3 => {
+                if data[0] >= self.layers {
+                    return RES_ERR;
+                }
+                self.modes.insert(data[0], (data[1], data[2]));
+            }
+            CMD_MATRIX_GET if data.len() >= 2 => {
+                let (rows, cols) = (self.rows as usize, self.cols as usize);
+                if data.len() < 2 + (rows * cols + 7) / 8 {
+                    return RES_ERR;
+                }
+                data[0] = self.rows;
+                data[1] = self.cols;
+                for i in data[2..].iter_mut() {
+                    *i = 0;
+                }
+                for (output, input) in &self.pressed {
+                    let i = *output as usize * cols + *input as usize;
+                    data[2 + i / 8] |= 1 << (i % 8);
+                }
+            }
+            CMD_LED_SAVE => {
+                self.led_saves += 1;
+            }
+            _ => return RES_ERR,
+        }
+        RES_OK
+    }
+}
+
+/// Write a nul terminated string, truncating it if needed
+fn write_str(data: &mut [u8], s: &str) {
+    let len = s.len().min(data.len().saturating_sub(1));
+    data[..len].copy_from_slice(&s.as_bytes()[..len]);
+    for i in data[len..].iter_mut() {
+        *i = 0;
+    }
+}
+
+/// Emulated EC, with state shared between clones so tests can inspect and
+/// modify it while an `Ec` owns one
+#[derive(Clone)]
+pub struct VirtualEc(Arc<Mutex<VirtualEcState>>);
+
+impl VirtualEc {
+    /// Create an EC with the matrix, layers, and LEDs of a board's layout
+    pub fn new(board: &str) -> Self {
+        let layout = Layout::from_board(board).unwrap();
+        let (rows, cols) = layout
+            .layout
+            .values()
+            .fold((0, 0), |(rows, cols), (output, input)| {
+                (rows.max(*output + 1), cols.max(*input + 1))
+            });
+        let leds = layout
+            .leds
+            .values()
+            .flatten()
+            .map(|index| (*index, Default::default()))
+            .collect();
+        Self(Arc::new(Mutex::new(VirtualEcState {
+            board: board.to_string(),
+            version: "2021-01-01_0123456".to_string(),
+            rows,
+            cols,
+            layers: layout.meta.num_layers,
+            keymap: HashMap::new(),
+            leds,
+            max_brightness: 255,
+            modes: HashMap::new(),
+            pressed: HashSet::new(),
+            led_saves: 0,
+        })))
+    }
+
+    pub fn state(&self) -> MutexGuard<VirtualEcState> {
+        self.0.lock().unwrap()
+    }
+}
+
+impl Access for VirtualEc {
+    unsafe fn command(&mut self, cmd: u8, data: &mut [u8]) -> Result<u8, Error> {
+        Ok(self.state().command(cmd, data))
+    }
+
+    fn data_size(&self) -> usize {
+        DATA_SIZE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ectool::Ec;
+    use futures::executor::block_on;
+    use std::{cell::RefCell, os::unix::net::UnixStream, rc::Rc, thread};
+
+    use super::*;
+    use crate::daemon::{Daemon, DaemonClient, DaemonServer, ThreadClient, ThreadResponse};
+
+    /// Run a `DaemonServer` for `ec` in a thread, connected to a `DaemonClient`
+    fn connect(ec: &VirtualEc) -> (DaemonClient, thread::JoinHandle<()>) {
+        let (client_stream, server_stream) = UnixStream::pair().unwrap();
+        let ec = ec.clone();
+        let server = thread::spawn(move || {
+            let ec = unsafe { Ec::new(ec) }.unwrap().into_dyn();
+            let read = server_stream.try_clone().unwrap();
+            DaemonServer::new_with_ecs(read, server_stream, vec![ec])
+                .run()
+                .unwrap();
+        });
+        let read = client_stream.try_clone().unwrap();
+        let client = DaemonClient::new_streams(read, client_stream).unwrap();
+        (client, server)
+    }
+
+    #[test]
+    fn daemon_client() {
+        let ec = VirtualEc::new("system76/launch_1");
+        let (client, server) = connect(&ec);
+
+        let board = client.boards().unwrap()[0];
+        assert_eq!(client.model(board).unwrap(), "system76/launch_1");
+        assert_eq!(client.version(board).unwrap(), "2021-01-01_0123456");
+        let capabilities = client.capabilities(board).unwrap();
+        assert!(capabilities.keymap && capabilities.matrix);
+        assert_eq!(capabilities.num_layers, None);
+
+        client.keymap_set(board, 1, 2, 3, 0x1234).unwrap();
+        assert_eq!(client.keymap_get(board, 1, 2, 3).unwrap(), 0x1234);
+        assert_eq!(ec.state().keymap[&(1, 2, 3)], 0x1234);
+        assert!(client.keymap_get(board, 4, 0, 0).is_err());
+
+        client.set_color(board, 0xf1, (1, 2, 3)).unwrap();
+        assert_eq!(client.color(board, 0xf1).unwrap(), (1, 2, 3));
+        client.set_brightness(board, 0xf1, 100).unwrap();
+        assert_eq!(client.brightness(board, 0xf1).unwrap(), 100);
+        assert_eq!(client.max_brightness(board).unwrap(), 255);
+        client.set_mode(board, 1, 4, 5).unwrap();
+        assert_eq!(client.mode(board, 1).unwrap(), (4, 5));
+        client.led_save(board).unwrap();
+        assert_eq!(ec.state().led_saves, 1);
+
+        ec.state().pressed.insert((2, 3));
+        let matrix = client.matrix_get(board).unwrap();
+        assert_eq!(matrix.get(2, 3), Some(true));
+        assert_eq!(matrix.get(3, 2), Some(false));
+
+        drop(client);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn probe_capabilities() {
+        let ec = VirtualEc::new("system76/launch_1");
+        ec.state().version = "unknown".to_string();
+        let (client, server) = connect(&ec);
+
+        let board = client.boards().unwrap()[0];
+        let capabilities = client.capabilities(board).unwrap();
+        assert!(capabilities.keymap && capabilities.matrix);
+        assert_eq!(capabilities.num_layers, Some(ec.state().layers));
+
+        drop(client);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn thread_client() {
+        let context = glib::MainContext::new();
+        context.with_thread_default(|| {
+            let ec = VirtualEc::new("system76/launch_1");
+            let (client, server) = connect(&ec);
+
+            let boards = Rc::new(RefCell::new(Vec::new()));
+            let thread_client = ThreadClient::new(Box::new(client), {
+                let boards = boards.clone();
+                move |response| {
+                    if let ThreadResponse::BoardAdded(board) = response {
+                        boards.borrow_mut().push(board);
+                    }
+                }
+            });
+
+            context.block_on(thread_client.refresh()).unwrap();
+            while context.pending() {
+                context.iteration(false);
+            }
+            let board = boards.borrow()[0].clone();
+            assert_eq!(board.model(), "system76/launch_1");
+
+            let key = &board.keys()[0];
+            block_on(key.set_scancode(0, "A")).unwrap();
+            let a = board.layout().scancode_from_name("A").unwrap();
+            let (output, input) = key.electrical;
+            assert_eq!(ec.state().keymap[&(0, output, input)], a);
+
+            // The EC has no macro storage
+            assert!(board.macros().is_empty());
+            assert!(block_on(key.set_scancode(0, "MACRO(1)")).is_err());
+            assert_eq!(ec.state().keymap[&(0, output, input)], a);
+
+            block_on(board.layers()[0].set_brightness(42)).unwrap();
+            assert_eq!(ec.state().leds[&0xf0].0, 42);
+
+            drop(board);
+            boards.borrow_mut().clear();
+            thread_client.close();
+            server.join().unwrap();
+        });
+    }
+}
@@ -0,0 +1,177 @@
+use rusb::{DeviceHandle, Direction, GlobalContext, Recipient, RequestType};
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::Bootloader;
+
+// Atmel DFU bootloader of the AT90USB646 used by the Launch
+const VENDOR_ID: u16 = 0x03eb;
+const PRODUCT_ID: u16 = 0x2ff9;
+
+/// Flash below the 4 KiB bootloader section
+const FLASH_SIZE: usize = 0xf000;
+const PAGE_SIZE: usize = 256;
+
+/// Bytes before the data of a write, with the command in the first 6
+const WRITE_HEADER_SIZE: usize = 32;
+/// Bytes after the data of a write, which the bootloader ignores
+const WRITE_FOOTER_SIZE: usize = 16;
+
+const DFU_DNLOAD: u8 = 1;
+const DFU_UPLOAD: u8 = 2;
+const DFU_GETSTATUS: u8 = 3;
+const DFU_CLRSTATUS: u8 = 4;
+
+const STATE_DNBUSY: u8 = 4;
+
+/// How long the bootloader takes to appear after the firmware resets into it
+const OPEN_TIMEOUT: Duration = Duration::from_secs(10);
+const TRANSFER_TIMEOUT: Duration = Duration::from_secs(5);
+
+fn usb_err(err: rusb::Error) -> String {
+    format!("USB error: {}", err)
+}
+
+/// Atmel DFU bootloader, which the Launch firmware resets into with the EC
+/// reset command
+///
+/// Talks to the bootloader over USB control transfers, the same way as
+/// `dfu-programmer`, so it needs to be run as root.
+pub struct AtmelDfu {
+    handle: DeviceHandle<GlobalContext>,
+}
+
+impl AtmelDfu {
+    /// Open the bootloader, waiting for it to appear after a reset
+    pub fn open() -> Result<Self, String> {
+        let start = Instant::now();
+        let mut handle = loop {
+            if let Some(handle) = rusb::open_device_with_vid_pid(VENDOR_ID, PRODUCT_ID) {
+                break handle;
+            }
+            if start.elapsed() > OPEN_TIMEOUT {
+                return Err("Keyboard bootloader not found".to_string());
+            }
+            thread::sleep(Duration::from_millis(100));
+        };
+        handle.claim_interface(0).map_err(usb_err)?;
+        let dfu = Self { handle };
+        // Clear an error left by an earlier, interrupted attempt
+        dfu.clear_status()?;
+        Ok(dfu)
+    }
+
+    fn download(&self, data: &[u8]) -> Result<(), String> {
+        let request_type =
+            rusb::request_type(Direction::Out, RequestType::Class, Recipient::Interface);
+        self.handle
+            .write_control(request_type, DFU_DNLOAD, 0, 0, data, TRANSFER_TIMEOUT)
+            .map_err(usb_err)?;
+        Ok(())
+    }
+
+    fn upload(&self, data: &mut [u8]) -> Result<(), String> {
+        let request_type =
+            rusb::request_type(Direction::In, RequestType::Class, Recipient::Interface);
+        let len = self
+            .handle
+            .read_control(request_type, DFU_UPLOAD, 0, 0, data, TRANSFER_TIMEOUT)
+            .map_err(usb_err)?;
+        if len != data.len() {
+            return Err(format!("Read {} bytes, expected {}", len, data.len()));
+        }
+        Ok(())
+    }
+
+    fn clear_status(&self) -> Result<(), String> {
+        let request_type =
+            rusb::request_type(Direction::Out, RequestType::Class, Recipient::Interface);
+        self.handle
+            .write_control(request_type, DFU_CLRSTATUS, 0, 0, &[], TRANSFER_TIMEOUT)
+            .map_err(usb_err)?;
+        Ok(())
+    }
+
+    /// Wait for the last download to be handled, returning an error if it
+    /// failed
+    fn wait(&self) -> Result<(), String> {
+        let request_type =
+            rusb::request_type(Direction::In, RequestType::Class, Recipient::Interface);
+        loop {
+            // Status, poll timeout, state and string index
+            let mut status = [0; 6];
+            self.handle
+                .read_control(
+                    request_type,
+                    DFU_GETSTATUS,
+                    0,
+                    0,
+                    &mut status,
+                    TRANSFER_TIMEOUT,
+                )
+                .map_err(usb_err)?;
+            if status[0] != 0 {
+                self.clear_status()?;
+                return Err(format!("Bootloader error {:#x}", status[0]));
+            }
+            if status[4] != STATE_DNBUSY {
+                return Ok(());
+            }
+            let poll_timeout = u32::from_le_bytes([status[1], status[2], status[3], 0]);
+            thread::sleep(Duration::from_millis(poll_timeout.max(1).into()));
+        }
+    }
+
+    /// Command selecting the flash between `address` and `address + len`
+    fn range_command(command: u8, address: usize, len: usize) -> [u8; 6] {
+        let start = address as u16;
+        let end = (address + len - 1) as u16;
+        let [start_high, start_low] = start.to_be_bytes();
+        let [end_high, end_low] = end.to_be_bytes();
+        [command, 0x00, start_high, start_low, end_high, end_low]
+    }
+}
+
+impl Bootloader for AtmelDfu {
+    fn page_size(&self) -> usize {
+        PAGE_SIZE
+    }
+
+    fn flash_size(&self) -> usize {
+        FLASH_SIZE
+    }
+
+    fn erase(&mut self) -> Result<(), String> {
+        self.download(&[0x04, 0x00, 0xff])?;
+        self.wait()
+    }
+
+    fn write(&mut self, address: usize, data: &[u8]) -> Result<(), String> {
+        let mut message = vec![0; WRITE_HEADER_SIZE + data.len() + WRITE_FOOTER_SIZE];
+        message[..6].copy_from_slice(&Self::range_command(0x01, address, data.len()));
+        message[WRITE_HEADER_SIZE..WRITE_HEADER_SIZE + data.len()].copy_from_slice(data);
+        // DFU suffix, with the signature and length reversed
+        message[WRITE_HEADER_SIZE + data.len()..].copy_from_slice(&[
+            0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x10, 0x01, b'U', b'F', b'D', 16, 0, 0, 0, 0,
+        ]);
+        self.download(&message)?;
+        self.wait()
+    }
+
+    fn read(&mut self, address: usize, data: &mut [u8]) -> Result<(), String> {
+        self.download(&Self::range_command(0x03, address, data.len()))?;
+        self.wait()?;
+        self.upload(data)
+    }
+
+    fn reset(&mut self) -> Result<(), String> {
+        self.download(&[0x04, 0x03, 0x00])?;
+        self.wait()?;
+        // The application starts on an empty download, so the device is gone
+        // before it can respond
+        let _ = self.download(&[]);
+        Ok(())
+    }
+}
@@ -0,0 +1,426 @@
+//! LED effects computed by the host and streamed to the keyboard, for
+//! animations the firmware's `Mode`s can't show
+//!
+//! Custom effects implement `Effect`:
+//!
+//! ```
+//! use system76_keyboard_configurator_backend::{Effect, EffectKey, Rgb};
+//!
+//! /// Lights the left half of the keyboard red
+//! struct LeftHalf;
+//!
+//! impl Effect for LeftHalf {
+//!     fn render(&mut self, _time: f64, keys: &[EffectKey]) -> Vec<Rgb> {
+//!         keys.iter()
+//!             .map(|key| if key.x < 0.5 { Rgb::new(255, 0, 0) } else { Rgb::new(0, 0, 0) })
+//!             .collect()
+//!     }
+//! }
+//! ```
+
+use futures::{
+    channel::oneshot,
+    future::{AbortHandle, Abortable},
+};
+use futures_timer::Delay;
+use std::{
+    cell::Cell,
+    f64::consts::PI,
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+use crate::{Board, Hs, LedFrame, Rect, Rgb};
+
+/// Rate at which frames are rendered and sent
+const FRAMES_PER_SECOND: u32 = 30;
+
+/// Position of the center of a key, scaled so the keyboard spans 0.0 to 1.0
+/// on each axis, with `y` increasing downwards
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EffectKey {
+    pub x: f64,
+    pub y: f64,
+}
+
+/// An animation computing the color of each key for every frame
+pub trait Effect {
+    /// Colors of `keys`, in the same order, at `time` seconds since the effect
+    /// started
+    fn render(&mut self, time: f64, keys: &[EffectKey]) -> Vec<Rgb>;
+}
+
+/// `color` dimmed to `level`, from 0.0 to 1.0
+pub(crate) fn scale(color: Rgb, level: f64) -> Rgb {
+    let (r, g, b) = color.to_floats();
+    let level = level.max(0.).min(1.);
+    Rgb::from_floats(r * level, g * level, b * level)
+}
+
+/// Rainbow moving from left to right
+pub struct GradientSweep {
+    /// Sweeps per second
+    pub speed: f64,
+}
+
+impl Default for GradientSweep {
+    fn default() -> Self {
+        Self { speed: 0.25 }
+    }
+}
+
+impl Effect for GradientSweep {
+    fn render(&mut self, time: f64, keys: &[EffectKey]) -> Vec<Rgb> {
+        keys.iter()
+            .map(|key| {
+                let h = (key.x - time * self.speed).rem_euclid(1.) * 2. * PI;
+                Hs::new(h, 1.).to_rgb()
+            })
+            .collect()
+    }
+}
+
+/// All keys fading in and out together
+pub struct Breathing {
+    pub color: Hs,
+    /// Seconds per breath
+    pub period: f64,
+}
+
+impl Breathing {
+    pub fn new(color: Hs) -> Self {
+        Self { color, period: 4. }
+    }
+}
+
+impl Effect for Breathing {
+    fn render(&mut self, time: f64, keys: &[EffectKey]) -> Vec<Rgb> {
+        let level = (1. - (2. * PI * time / self.period).cos()) / 2.;
+        vec![scale(self.color.to_rgb(), level); keys.len()]
+    }
+}
+
+/// Bands of light moving from left to right
+pub struct Wave {
+    pub color: Hs,
+    /// Waves passing a key per second
+    pub speed: f64,
+    /// Distance between waves, as a fraction of the keyboard's width
+    pub wavelength: f64,
+}
+
+impl Wave {
+    pub fn new(color: Hs) -> Self {
+        Self {
+            color,
+            speed: 1.,
+            wavelength: 0.5,
+        }
+    }
+}
+
+impl Effect for Wave {
+    fn render(&mut self, time: f64, keys: &[EffectKey]) -> Vec<Rgb> {
+        let color = self.color.to_rgb();
+        keys.iter()
+            .map(|key| {
+                let phase = key.x / self.wavelength - time * self.speed;
+                scale(color, (1. + (2. * PI * phase).sin()) / 2.)
+            })
+            .collect()
+    }
+}
+
+/// Pseudorandom numbers, using xorshift. Good enough for animations, and
+/// repeatable.
+pub(crate) struct Random(u32);
+
+impl Random {
+    /// `seed` must not be 0
+    pub fn new(seed: u32) -> Self {
+        Self(seed)
+    }
+
+    /// Random number from 0.0 to 1.0
+    pub fn next_f64(&mut self) -> f64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        f64::from(self.0) / f64::from(u32::MAX)
+    }
+}
+
+/// Keys lighting up at random and fading out
+pub struct Starfield {
+    pub color: Hs,
+    /// Stars appearing on each key per second
+    pub density: f64,
+    /// Seconds for a star to fade out
+    pub fade: f64,
+    levels: Vec<f64>,
+    last_time: f64,
+    random: Random,
+}
+
+impl Starfield {
+    pub fn new(color: Hs) -> Self {
+        Self {
+            color,
+            density: 0.1,
+            fade: 1.,
+            levels: Vec::new(),
+            last_time: 0.,
+            random: Random::new(0x2545_f491),
+        }
+    }
+}
+
+impl Effect for Starfield {
+    fn render(&mut self, time: f64, keys: &[EffectKey]) -> Vec<Rgb> {
+        let elapsed = (time - self.last_time).max(0.);
+        self.last_time = time;
+        self.levels.resize(keys.len(), 0.);
+        for i in 0..self.levels.len() {
+            let level = self.levels[i] - elapsed / self.fade;
+            self.levels[i] = if self.random.next_f64() < self.density * elapsed {
+                1.
+            } else {
+                level.max(0.)
+            };
+        }
+        let color = self.color.to_rgb();
+        self.levels
+            .iter()
+            .map(|level| scale(color, *level))
+            .collect()
+    }
+}
+
+/// Send frames of `effect` to the keys of `board` with LEDs, until aborted
+/// or the keyboard fails
+async fn play(board: Board, mut effect: Box<dyn Effect>, abort: AbortHandle) {
+    let keys = board
+        .keys()
+        .iter()
+        .filter(|key| !key.leds.is_empty())
+        .collect::<Vec<_>>();
+    let positions = effect_keys(&keys.iter().map(|key| key.physical).collect::<Vec<_>>());
+    let frame_duration = Duration::from_secs(1) / FRAMES_PER_SECOND;
+    let start = Instant::now();
+
+    loop {
+        let frame_start = Instant::now();
+        let colors = effect.render((frame_start - start).as_secs_f64(), &positions);
+        let mut frame = LedFrame::new();
+        let calibration = board.calibration();
+        for (key, color) in keys.iter().zip(colors) {
+            let color = calibration.apply(color);
+            for index in &key.leds {
+                frame.push(*index, (color.r, color.g, color.b));
+            }
+        }
+        // Not waiting for the frame to be sent, so frames are dropped if the
+        // keyboard is slower than the frame rate
+        let board = board.clone();
+        let abort = abort.clone();
+        glib::MainContext::default().spawn_local(async move {
+            if let Err(err) = board.set_colors_frame(frame).await {
+                if !err.is_superseded() {
+                    error!("Failed to show effect: {}", err);
+                    abort.abort();
+                }
+            }
+        });
+        let deadline = frame_start + frame_duration;
+        Delay::new(deadline.saturating_duration_since(Instant::now())).await;
+    }
+}
+
+/// Plays an `Effect` on the per-key LEDs of a board, which are visible when
+/// the layer's mode is `PER_KEY`
+///
+/// The effect stops when this is dropped, and the colors set for each key
+/// are restored.
+pub struct EffectPlayer {
+    board: Board,
+    abort: AbortHandle,
+    /// Whether to restore the key colors once stopped
+    restore: Rc<Cell<bool>>,
+    /// Completes when the effect has stopped, and colors are restored
+    done: Option<oneshot::Receiver<()>>,
+}
+
+impl EffectPlayer {
+    pub fn new(board: &Board, effect: Box<dyn Effect>) -> Self {
+        Self::start(board, effect, None)
+    }
+
+    /// Stop this effect and play `effect` instead. The new effect starts once
+    /// the old one has stopped, without restoring the key colors in between,
+    /// so the two don't flicker over each other.
+    pub fn replace(mut self, effect: Box<dyn Effect>) -> Self {
+        self.restore.set(false);
+        self.abort.abort();
+        let previous = self.done.take();
+        Self::start(&self.board, effect, previous)
+    }
+
+    fn start(
+        board: &Board,
+        effect: Box<dyn Effect>,
+        previous: Option<oneshot::Receiver<()>>,
+    ) -> Self {
+        let (abort, registration) = AbortHandle::new_pair();
+        let restore = Rc::new(Cell::new(true));
+        let (done_sender, done) = oneshot::channel();
+        let frames = Abortable::new(play(board.clone(), effect, abort.clone()), registration);
+        let board_clone = board.clone();
+        let restore_clone = restore.clone();
+        glib::MainContext::default().spawn_local(async move {
+            if let Some(previous) = previous {
+                let _ = previous.await;
+            }
+            let _ = frames.await;
+            if restore_clone.get() {
+                for key in board_clone.keys() {
+                    if key.leds.is_empty() {
+                        continue;
+                    }
+                    if let Err(err) = key.set_color(key.color()).await {
+                        error!("Failed to restore key color: {}", err);
+                    }
+                }
+            }
+            let _ = done_sender.send(());
+        });
+        Self {
+            board: board.clone(),
+            abort,
+            restore,
+            done: Some(done),
+        }
+    }
+
+    pub fn stop(&self) {
+        self.abort.abort();
+    }
+}
+
+impl Drop for EffectPlayer {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Edges of `rect` as `(left, top, right, bottom)`, with `y` increasing
+/// downwards
+pub(crate) fn rect_bounds(rect: &Rect) -> (f64, f64, f64, f64) {
+    // Physical layouts have `y` increasing upwards, from the top of the key
+    (rect.x, -rect.y, rect.x + rect.w, rect.h - rect.y)
+}
+
+/// Positions of the centers of `rects`, scaled to fit from 0.0 to 1.0
+pub fn effect_keys(rects: &[Rect]) -> Vec<EffectKey> {
+    let centers = rects
+        .iter()
+        .map(|rect| {
+            let (left, top, right, bottom) = rect_bounds(rect);
+            ((left + right) / 2., (top + bottom) / 2.)
+        })
+        .collect::<Vec<_>>();
+    let min_x = centers.iter().map(|c| c.0).fold(f64::INFINITY, f64::min);
+    let max_x = centers
+        .iter()
+        .map(|c| c.0)
+        .fold(f64::NEG_INFINITY, f64::max);
+    let min_y = centers.iter().map(|c| c.1).fold(f64::INFINITY, f64::min);
+    let max_y = centers
+        .iter()
+        .map(|c| c.1)
+        .fold(f64::NEG_INFINITY, f64::max);
+    let scaled = |value: f64, min: f64, max: f64| {
+        if max > min {
+            (value - min) / (max - min)
+        } else {
+            0.
+        }
+    };
+    centers
+        .into_iter()
+        .map(|(x, y)| EffectKey {
+            x: scaled(x, min_x, max_x),
+            y: scaled(y, min_y, max_y),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys() -> Vec<EffectKey> {
+        effect_keys(&[
+            Rect::new(0., 1., 1., 1.),
+            Rect::new(1., 1., 1., 1.),
+            Rect::new(2., 0., 2., 1.),
+        ])
+    }
+
+    #[test]
+    fn positions() {
+        let keys = keys();
+        assert_eq!(keys[0], EffectKey { x: 0., y: 0. });
+        assert_eq!(keys[1], EffectKey { x: 0.4, y: 0. });
+        assert_eq!(keys[2], EffectKey { x: 1., y: 1. });
+    }
+
+    #[test]
+    fn effects() {
+        let keys = keys();
+        let color = Hs::new(0., 1.);
+        let effects: Vec<Box<dyn Effect>> = vec![
+            Box::new(GradientSweep::default()),
+            Box::new(Breathing::new(color)),
+            Box::new(Wave::new(color)),
+            Box::new(Starfield::new(color)),
+        ];
+        for mut effect in effects {
+            for frame in 0..10 {
+                assert_eq!(effect.render(frame as f64 / 10., &keys).len(), keys.len());
+            }
+        }
+    }
+
+    #[test]
+    fn gradient_sweep() {
+        let mut effect = GradientSweep::default();
+        let keys = keys();
+        let color = |effect: &mut GradientSweep, time, i| effect.render(time, &keys)[i].to_string();
+        // Red at the left, green-cyan at 0.4 of the way across
+        assert_eq!(color(&mut effect, 0., 0), "#ff0000");
+        assert_ne!(color(&mut effect, 0., 1), "#ff0000");
+        // Moves over time, and repeats after a full sweep
+        assert_ne!(color(&mut effect, 1., 0), "#ff0000");
+        assert_eq!(color(&mut effect, 4., 0), "#ff0000");
+    }
+
+    #[test]
+    fn wave() {
+        let mut effect = Wave::new(Hs::new(0., 1.));
+        let keys = keys();
+        assert_eq!(effect.render(0.25, &keys)[0].r, 0);
+        assert_eq!(effect.render(0.75, &keys)[0].r, 255);
+        // Keys a whole number of wavelengths apart are lit the same
+        let colors = effect.render(0.6, &keys);
+        assert_eq!(colors[0].r, colors[2].r);
+        assert_ne!(colors[0].r, colors[1].r);
+    }
+
+    #[test]
+    fn breathing() {
+        let mut effect = Breathing::new(Hs::new(0., 1.));
+        let keys = keys();
+        assert_eq!(effect.render(0., &keys)[0].r, 0);
+        assert_eq!(effect.render(2., &keys)[0].r, 255);
+    }
+}
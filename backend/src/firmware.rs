@@ -0,0 +1,357 @@
+use serde::Deserialize;
+use std::{
+    cmp::Ordering,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::daemon::Daemon;
+
+/// Directory searched for firmware images, in a subdirectory named after the
+/// board model
+pub const FIRMWARE_DIR: &str = "/usr/share/system76-keyboard-configurator/firmware";
+
+/// Contents of a firmware directory's `metadata.json`
+#[derive(Clone, Debug, Deserialize)]
+pub struct FirmwareMetadata {
+    /// Board model the image is built for, like `system76/launch_1`
+    pub model: String,
+    /// Version string, as reported by `Board::version` once flashed
+    pub version: String,
+    /// File name of the raw image, relative to the metadata
+    pub image: String,
+}
+
+/// A firmware image that can be flashed to a board
+#[derive(Clone, Debug)]
+pub struct Firmware {
+    pub metadata: FirmwareMetadata,
+    pub image: Vec<u8>,
+}
+
+impl Firmware {
+    /// Load `metadata.json` and the image it refers to from `dir`
+    pub fn from_dir<P: AsRef<Path>>(dir: P) -> Result<Self, String> {
+        let dir = dir.as_ref();
+        let metadata_path = dir.join("metadata.json");
+        let metadata_json = fs::read_to_string(&metadata_path)
+            .map_err(|err| format!("Failed to read '{}': {}", metadata_path.display(), err))?;
+        let metadata: FirmwareMetadata = serde_json::from_str(&metadata_json)
+            .map_err(|err| format!("Failed to parse '{}': {}", metadata_path.display(), err))?;
+        let image_path = dir.join(&metadata.image);
+        let image = fs::read(&image_path)
+            .map_err(|err| format!("Failed to read '{}': {}", image_path.display(), err))?;
+        if image.is_empty() {
+            return Err(format!(
+                "Firmware image '{}' is empty",
+                image_path.display()
+            ));
+        }
+        Ok(Self { metadata, image })
+    }
+
+    /// Find the firmware for `model` in `FIRMWARE_DIR`, if there is one
+    pub fn find(model: &str) -> Option<Self> {
+        let dir = PathBuf::from(FIRMWARE_DIR).join(model);
+        if !dir.exists() {
+            return None;
+        }
+        match Self::from_dir(&dir) {
+            Ok(firmware) if firmware.metadata.model == model => Some(firmware),
+            Ok(firmware) => {
+                error!(
+                    "Firmware in '{}' is for {}, not {}",
+                    dir.display(),
+                    firmware.metadata.model,
+                    model
+                );
+                None
+            }
+            Err(err) => {
+                error!("{}", err);
+                None
+            }
+        }
+    }
+
+    /// Test if this is newer firmware for a board with the given model and
+    /// current version
+    pub fn is_update_for(&self, model: &str, version: &str) -> bool {
+        self.metadata.model == model
+            && compare_versions(&self.metadata.version, version) == Some(Ordering::Greater)
+    }
+}
+
+/// Date a version was built, as `(year, month, day)`
+///
+/// EC versions start with the date, followed by the commit, like
+/// `2021-03-10_1a2b3c4`.
+pub(crate) fn version_date(version: &str) -> Option<(u16, u8, u8)> {
+    let mut parts = version.get(..10)?.split('-');
+    let year = parts.next()?.parse().ok()?;
+    let month = parts.next()?.parse().ok()?;
+    let day = parts.next()?.parse().ok()?;
+    Some((year, month, day))
+}
+
+/// Compare firmware versions by date. Different versions built the same day
+/// can't be ordered, and give `None`.
+pub fn compare_versions(a: &str, b: &str) -> Option<Ordering> {
+    if a == b {
+        return Some(Ordering::Equal);
+    }
+    match version_date(a)?.cmp(&version_date(b)?) {
+        Ordering::Equal => None,
+        ordering => Some(ordering),
+    }
+}
+
+/// Stage of `flash`, reported as it progresses
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FlashProgress {
+    Erasing,
+    /// Bytes written so far, and total
+    Writing(usize, usize),
+    /// Bytes verified so far, and total
+    Verifying(usize, usize),
+    Done,
+}
+
+/// Flash memory of a board in its bootloader
+pub trait Bootloader {
+    /// Size of the blocks `write` and `read` operate on
+    fn page_size(&self) -> usize;
+
+    /// Size of the application area of the flash, in bytes
+    fn flash_size(&self) -> usize;
+
+    fn erase(&mut self) -> Result<(), String>;
+
+    /// Write a page at `address`, which is a multiple of `page_size`
+    fn write(&mut self, address: usize, data: &[u8]) -> Result<(), String>;
+
+    /// Read a page at `address`, which is a multiple of `page_size`
+    fn read(&mut self, address: usize, data: &mut [u8]) -> Result<(), String>;
+
+    /// Leave the bootloader, starting the newly flashed firmware
+    fn reset(&mut self) -> Result<(), String>;
+}
+
+/// Erase, write, and verify `image`, then reset into it
+///
+/// If verification fails, the bootloader is not reset, so the board stays in
+/// its bootloader and flashing can be retried.
+pub fn flash<F: FnMut(FlashProgress)>(
+    bootloader: &mut dyn Bootloader,
+    image: &[u8],
+    mut progress: F,
+) -> Result<(), String> {
+    if image.len() > bootloader.flash_size() {
+        return Err(format!(
+            "Firmware image of {} bytes does not fit in {} bytes of flash",
+            image.len(),
+            bootloader.flash_size()
+        ));
+    }
+    let page_size = bootloader.page_size();
+
+    progress(FlashProgress::Erasing);
+    bootloader.erase()?;
+
+    let mut page = vec![0xff; page_size];
+    for (i, chunk) in image.chunks(page_size).enumerate() {
+        let address = i * page_size;
+        // Pad the last page with the value of erased flash
+        page[..chunk.len()].copy_from_slice(chunk);
+        for byte in &mut page[chunk.len()..] {
+            *byte = 0xff;
+        }
+        bootloader
+            .write(address, &page)
+            .map_err(|err| format!("Failed to write address {:#x}: {}", address, err))?;
+        progress(FlashProgress::Writing(address + chunk.len(), image.len()));
+    }
+
+    for (i, chunk) in image.chunks(page_size).enumerate() {
+        let address = i * page_size;
+        bootloader
+            .read(address, &mut page)
+            .map_err(|err| format!("Failed to read address {:#x}: {}", address, err))?;
+        if &page[..chunk.len()] != chunk {
+            return Err(format!("Verification failed at address {:#x}", address));
+        }
+        progress(FlashProgress::Verifying(address + chunk.len(), image.len()));
+    }
+
+    bootloader.reset()?;
+    progress(FlashProgress::Done);
+    Ok(())
+}
+
+/// Bootloader reached through the `bootloader_` commands of a daemon, which
+/// has the permissions to use it
+pub(crate) struct DaemonBootloader<'a> {
+    daemon: &'a dyn Daemon,
+    page_size: usize,
+    flash_size: usize,
+}
+
+impl<'a> DaemonBootloader<'a> {
+    /// Open the bootloader of a board reset with `Daemon::enter_bootloader`
+    pub fn open(daemon: &'a dyn Daemon) -> Result<Self, String> {
+        let (page_size, flash_size) = daemon.bootloader_open()?;
+        Ok(Self {
+            daemon,
+            page_size,
+            flash_size,
+        })
+    }
+}
+
+impl<'a> Bootloader for DaemonBootloader<'a> {
+    fn page_size(&self) -> usize {
+        self.page_size
+    }
+
+    fn flash_size(&self) -> usize {
+        self.flash_size
+    }
+
+    fn erase(&mut self) -> Result<(), String> {
+        self.daemon.bootloader_erase()
+    }
+
+    fn write(&mut self, address: usize, data: &[u8]) -> Result<(), String> {
+        self.daemon.bootloader_write(address, data.to_vec())
+    }
+
+    fn read(&mut self, address: usize, data: &mut [u8]) -> Result<(), String> {
+        let read = self.daemon.bootloader_read(address, data.len())?;
+        if read.len() != data.len() {
+            return Err(format!(
+                "Read {} bytes, expected {}",
+                read.len(),
+                data.len()
+            ));
+        }
+        data.copy_from_slice(&read);
+        Ok(())
+    }
+
+    fn reset(&mut self) -> Result<(), String> {
+        self.daemon.bootloader_reset()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Bootloader backed by memory, optionally corrupting one address
+    struct EmulatedBootloader {
+        flash: Vec<u8>,
+        corrupt: Option<usize>,
+        reset: bool,
+    }
+
+    impl EmulatedBootloader {
+        fn new() -> Self {
+            Self {
+                flash: vec![0; 1024],
+                corrupt: None,
+                reset: false,
+            }
+        }
+    }
+
+    impl Bootloader for EmulatedBootloader {
+        fn page_size(&self) -> usize {
+            128
+        }
+
+        fn flash_size(&self) -> usize {
+            self.flash.len()
+        }
+
+        fn erase(&mut self) -> Result<(), String> {
+            for byte in &mut self.flash {
+                *byte = 0xff;
+            }
+            Ok(())
+        }
+
+        fn write(&mut self, address: usize, data: &[u8]) -> Result<(), String> {
+            assert_eq!(address % self.page_size(), 0);
+            assert_eq!(data.len(), self.page_size());
+            self.flash[address..address + data.len()].copy_from_slice(data);
+            if let Some(corrupt) = self.corrupt {
+                if corrupt >= address && corrupt < address + data.len() {
+                    self.flash[corrupt] ^= 1;
+                }
+            }
+            Ok(())
+        }
+
+        fn read(&mut self, address: usize, data: &mut [u8]) -> Result<(), String> {
+            data.copy_from_slice(&self.flash[address..address + data.len()]);
+            Ok(())
+        }
+
+        fn reset(&mut self) -> Result<(), String> {
+            self.reset = true;
+            Ok(())
+        }
+    }
+
+    fn image(len: usize) -> Vec<u8> {
+        (0..len).map(|i| i as u8).collect()
+    }
+
+    #[test]
+    fn flash_image() {
+        let mut bootloader = EmulatedBootloader::new();
+        let image = image(300);
+        let mut stages = Vec::new();
+        flash(&mut bootloader, &image, |progress| stages.push(progress)).unwrap();
+        assert_eq!(&bootloader.flash[..300], &image[..]);
+        assert!(bootloader.flash[300..].iter().all(|i| *i == 0xff));
+        assert!(bootloader.reset);
+        assert_eq!(stages.first(), Some(&FlashProgress::Erasing));
+        assert!(stages.contains(&FlashProgress::Writing(300, 300)));
+        assert!(stages.contains(&FlashProgress::Verifying(300, 300)));
+        assert_eq!(stages.last(), Some(&FlashProgress::Done));
+    }
+
+    #[test]
+    fn flash_verify_fails() {
+        let mut bootloader = EmulatedBootloader::new();
+        bootloader.corrupt = Some(200);
+        let err = flash(&mut bootloader, &image(300), |_| {}).unwrap_err();
+        assert_eq!(err, "Verification failed at address 0x80");
+        assert!(!bootloader.reset);
+    }
+
+    #[test]
+    fn flash_too_large() {
+        let mut bootloader = EmulatedBootloader::new();
+        assert!(flash(&mut bootloader, &image(2000), |_| {}).is_err());
+        assert!(!bootloader.reset);
+    }
+
+    #[test]
+    fn versions() {
+        let firmware = Firmware {
+            metadata: FirmwareMetadata {
+                model: "system76/launch_1".to_string(),
+                version: "2021-03-10_1a2b3c4".to_string(),
+                image: "firmware.rom".to_string(),
+            },
+            image: image(1),
+        };
+        assert!(firmware.is_update_for("system76/launch_1", "2021-01-01_0123456"));
+        assert!(!firmware.is_update_for("system76/launch_1", "2021-03-10_1a2b3c4"));
+        assert!(!firmware.is_update_for("system76/launch_1", "2021-04-01_0123456"));
+        assert!(!firmware.is_update_for("system76/launch_1", "unknown"));
+        assert!(!firmware.is_update_for("system76/launch_alpha_2", "2021-01-01_0123456"));
+    }
+}
@@ -0,0 +1,304 @@
+use glib::{prelude::*, SignalHandlerId};
+use serde::{Deserialize, Serialize};
+use std::{
+    cell::{Cell, RefCell},
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+use crate::config::{load_config, save_config};
+use crate::{Board, SetError};
+
+const IDLE_FILE: &str = "idle.json";
+
+/// Source of how long the user has been away
+pub trait IdleSource {
+    /// Time since the last key press, or `None` if it isn't known
+    fn idle_time(&self) -> Option<Duration>;
+}
+
+/// Key presses from the matrix of keyboards, which is only read when
+/// `Backend::set_matrix_get_rate` is set
+#[derive(Clone)]
+pub struct MatrixIdleSource {
+    last_press: Rc<Cell<Instant>>,
+    /// Watched boards, which can all report key presses
+    boards: Rc<RefCell<Vec<glib::WeakRef<Board>>>>,
+}
+
+impl MatrixIdleSource {
+    pub fn new() -> Self {
+        Self {
+            last_press: Rc::new(Cell::new(Instant::now())),
+            boards: Rc::new(RefCell::new(Vec::new())),
+        }
+    }
+
+    /// Count presses of the keys of `board` as activity, if its firmware can
+    /// report them. Connecting the board also counts as activity.
+    pub fn watch(&self, board: &Board) -> Option<SignalHandlerId> {
+        if !board.capabilities().matrix {
+            return None;
+        }
+        self.boards.borrow_mut().push(board.downgrade());
+        self.last_press.set(Instant::now());
+
+        let last_press = self.last_press.clone();
+        let weak_board = board.downgrade();
+        Some(board.connect_matrix_changed(move || {
+            if let Some(board) = weak_board.upgrade() {
+                if board.keys().iter().any(|key| key.pressed()) {
+                    last_press.set(Instant::now());
+                }
+            }
+        }))
+    }
+}
+
+impl Default for MatrixIdleSource {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl IdleSource for MatrixIdleSource {
+    fn idle_time(&self) -> Option<Duration> {
+        // Without a keyboard reporting presses, the user would seem to be
+        // away forever
+        let mut boards = self.boards.borrow_mut();
+        boards.retain(|board| board.upgrade().is_some());
+        if boards.is_empty() {
+            return None;
+        }
+        Some(self.last_press.get().elapsed())
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod session {
+    use std::time::Duration;
+    use zbus::{dbus_proxy, Connection};
+
+    use super::IdleSource;
+
+    #[dbus_proxy(interface = "org.gnome.Mutter.IdleMonitor")]
+    trait IdleMonitor {
+        fn get_idletime(&self) -> zbus::Result<u64>;
+    }
+
+    /// Idle time of the desktop session, from the Mutter idle monitor. This
+    /// also counts other input, like the mouse.
+    pub struct SessionIdleSource {
+        proxy: IdleMonitorProxy<'static>,
+    }
+
+    impl SessionIdleSource {
+        pub fn new() -> Result<Self, String> {
+            let connection = Connection::new_session().map_err(|err| err.to_string())?;
+            let proxy = IdleMonitorProxy::new_for_owned(
+                connection,
+                "org.gnome.Mutter.IdleMonitor".to_string(),
+                "/org/gnome/Mutter/IdleMonitor/Core".to_string(),
+            )
+            .map_err(|err| err.to_string())?;
+            // Fail now if there is no idle monitor, rather than on every call
+            proxy.get_idletime().map_err(|err| err.to_string())?;
+            Ok(Self { proxy })
+        }
+    }
+
+    impl IdleSource for SessionIdleSource {
+        fn idle_time(&self) -> Option<Duration> {
+            match self.proxy.get_idletime() {
+                Ok(millis) => Some(Duration::from_millis(millis)),
+                Err(err) => {
+                    error!("Failed to get idle time: {}", err);
+                    None
+                }
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub use session::SessionIdleSource;
+
+/// Settings for dimming the backlight when idle, saved in the user's config
+/// directory
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct IdleConfig {
+    /// Seconds without key presses before dimming, or 0 to never dim
+    #[serde(default)]
+    pub timeout: u64,
+    /// Percent of the normal brightness used while idle
+    #[serde(default)]
+    pub brightness: f64,
+}
+
+impl IdleConfig {
+    pub fn load() -> Self {
+        load_config(IDLE_FILE)
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        save_config(IDLE_FILE, self)
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.timeout > 0
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IdleChange {
+    Dim,
+    Restore,
+}
+
+/// Decides when to dim and restore the backlight, as reported by an
+/// `IdleSource`
+pub struct IdleDimmer {
+    config: IdleConfig,
+    source: Box<dyn IdleSource>,
+    dimmed: bool,
+}
+
+impl IdleDimmer {
+    pub fn new(config: IdleConfig, source: Box<dyn IdleSource>) -> Self {
+        Self {
+            config,
+            source,
+            dimmed: false,
+        }
+    }
+
+    pub fn config(&self) -> &IdleConfig {
+        &self.config
+    }
+
+    pub fn is_dimmed(&self) -> bool {
+        self.dimmed
+    }
+
+    /// Change needed since the last call, if any
+    pub fn poll(&mut self) -> Option<IdleChange> {
+        if !self.config.is_enabled() {
+            return None;
+        }
+        let idle_time = self.source.idle_time()?;
+        let idle = idle_time >= Duration::from_secs(self.config.timeout);
+        if idle == self.dimmed {
+            None
+        } else {
+            self.dimmed = idle;
+            Some(if idle {
+                IdleChange::Dim
+            } else {
+                IdleChange::Restore
+            })
+        }
+    }
+}
+
+/// Brightness of each layer of `board`, for restoring after dimming
+pub fn layer_brightness(board: &Board) -> Vec<i32> {
+    board
+        .layers()
+        .iter()
+        .map(|layer| layer.brightness())
+        .collect()
+}
+
+/// Set each layer of `board` to `percent` of the brightness from
+/// `layer_brightness`
+pub async fn set_layer_brightness(
+    board: &Board,
+    brightness: &[i32],
+    percent: f64,
+) -> Result<(), SetError> {
+    for (layer, brightness) in board.layers().iter().zip(brightness) {
+        let value = (f64::from(*brightness) * percent.max(0.).min(100.) / 100.).round();
+        layer.set_brightness(value as i32).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Backend;
+    use glib::clone;
+
+    struct TestSource(Rc<Cell<Option<Duration>>>);
+
+    impl IdleSource for TestSource {
+        fn idle_time(&self) -> Option<Duration> {
+            self.0.get()
+        }
+    }
+
+    fn dimmer(timeout: u64) -> (IdleDimmer, Rc<Cell<Option<Duration>>>) {
+        let idle_time = Rc::new(Cell::new(Some(Duration::from_secs(0))));
+        let config = IdleConfig {
+            timeout,
+            brightness: 10.,
+        };
+        let dimmer = IdleDimmer::new(config, Box::new(TestSource(idle_time.clone())));
+        (dimmer, idle_time)
+    }
+
+    #[test]
+    fn dim_and_restore() {
+        let (mut dimmer, idle_time) = dimmer(60);
+        assert_eq!(dimmer.poll(), None);
+
+        idle_time.set(Some(Duration::from_secs(59)));
+        assert_eq!(dimmer.poll(), None);
+
+        idle_time.set(Some(Duration::from_secs(60)));
+        assert_eq!(dimmer.poll(), Some(IdleChange::Dim));
+        assert!(dimmer.is_dimmed());
+
+        idle_time.set(Some(Duration::from_secs(120)));
+        assert_eq!(dimmer.poll(), None);
+
+        // Unknown idle time changes nothing
+        idle_time.set(None);
+        assert_eq!(dimmer.poll(), None);
+        assert!(dimmer.is_dimmed());
+
+        idle_time.set(Some(Duration::from_millis(100)));
+        assert_eq!(dimmer.poll(), Some(IdleChange::Restore));
+        assert!(!dimmer.is_dimmed());
+    }
+
+    #[test]
+    fn matrix_source() {
+        let context = glib::MainContext::new();
+        context.with_thread_default(|| {
+            let source = MatrixIdleSource::new();
+            assert_eq!(source.idle_time(), None);
+
+            let backend = Backend::new_dummy(vec!["system76/launch_1".to_string()]).unwrap();
+            let board = Rc::new(RefCell::new(None));
+            backend.connect_board_added(clone!(@strong board => move |added| {
+                board.replace(Some(added));
+            }));
+            backend.refresh();
+            while board.borrow().is_none() {
+                context.iteration(true);
+            }
+
+            let added = board.take().unwrap();
+            assert!(source.watch(&added).is_some());
+            assert!(source.idle_time().unwrap() < Duration::from_secs(1));
+        });
+    }
+
+    #[test]
+    fn disabled() {
+        let (mut dimmer, idle_time) = dimmer(0);
+        idle_time.set(Some(Duration::from_secs(3600)));
+        assert_eq!(dimmer.poll(), None);
+    }
+}
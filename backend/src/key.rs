@@ -1,7 +1,7 @@
 use glib::clone::Downgrade;
 use std::cell::Cell;
 
-use crate::{Board, Daemon, Hs, PhysicalLayoutKey, Rect, Rgb};
+use crate::{Board, Daemon, Hsv, Keycode, PhysicalLayoutKey, Rect, Rgb, SetError};
 
 #[derive(Debug)]
 pub struct Key {
@@ -22,7 +22,7 @@ pub struct Key {
     pub leds: Vec<u8>,
     /// LED name
     pub led_name: String,
-    led_color: Cell<Option<Hs>>,
+    led_color: Cell<Option<Hsv>>,
     /// Key is currently pressed
     pub(crate) pressed: Cell<bool>,
     /// Currently loaded scancodes and their names
@@ -72,7 +72,7 @@ impl Key {
         }
 
         let mut scancodes = Vec::new();
-        for layer in 0..board.layout().meta.num_layers {
+        for layer in 0..board.num_layers() as u8 {
             debug!("  Layer {}", layer);
             let scancode = match daemon.keymap_get(board.board(), layer, electrical.0, electrical.1)
             {
@@ -95,7 +95,10 @@ impl Key {
         if board.layout().meta.has_mode && leds.len() > 0 {
             match daemon.color(board.board(), leds[0]) {
                 Ok((0, 0, 0)) => {}
-                Ok((r, g, b)) => led_color = Some(Rgb::new(r, g, b).to_hs_lossy()),
+                Ok((r, g, b)) => {
+                    let rgb = board.calibration().invert(Rgb::new(r, g, b));
+                    led_color = Some(rgb.to_hsv());
+                }
                 Err(err) => error!("error getting key color: {}", err),
             }
         }
@@ -125,13 +128,14 @@ impl Key {
         self.pressed.get()
     }
 
-    pub fn color(&self) -> Option<Hs> {
+    pub fn color(&self) -> Option<Hsv> {
         self.led_color.get()
     }
 
-    pub async fn set_color(&self, color: Option<Hs>) -> Result<(), String> {
+    pub async fn set_color(&self, color: Option<Hsv>) -> Result<(), SetError> {
         let board = self.board();
-        let Rgb { r, g, b } = color.map_or(Rgb::new(0, 0, 0), Hs::to_rgb);
+        let rgb = color.map_or(Rgb::new(0, 0, 0), Hsv::to_rgb);
+        let Rgb { r, g, b } = board.calibration().apply(rgb);
         for index in &self.leds {
             board
                 .thread_client()
@@ -146,19 +150,26 @@ impl Key {
     pub fn get_scancode(&self, layer: usize) -> Option<(u16, String)> {
         let board = self.board();
         let scancode = self.scancodes.get(layer)?.get();
-        let scancode_name = match board.layout().scancode_to_name(scancode) {
-            Some(some) => some.to_string(),
-            None => String::new(),
-        };
-        Some((scancode, scancode_name))
+        Some((scancode, board.layout().scancode_to_name(scancode)))
     }
 
-    pub async fn set_scancode(&self, layer: usize, scancode_name: &str) -> Result<(), String> {
+    pub async fn set_scancode(&self, layer: usize, scancode_name: &str) -> Result<(), SetError> {
         let board = self.board();
         let scancode = board
             .layout()
             .scancode_from_name(scancode_name)
-            .ok_or_else(|| format!("Unable to find scancode '{}'", scancode_name))?;
+            .ok_or_else(|| {
+                SetError::Failed(format!("Unable to find scancode '{}'", scancode_name))
+            })?;
+        // Firmware without macro storage treats these as legacy action macros
+        if let Some(Keycode::Macro(index)) = board.layout().keycode_from_scancode(scancode) {
+            if index as usize >= board.macros().len() {
+                return Err(SetError::Failed(format!(
+                    "Board doesn't support macro '{}'",
+                    scancode_name
+                )));
+            }
+        }
         board
             .thread_client()
             .keymap_set(
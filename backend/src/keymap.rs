@@ -2,7 +2,7 @@ use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::collections::HashMap;
 use std::io::{Read, Write};
 
-use crate::Hs;
+use crate::{Hs, Hsv, Macro};
 
 mod hs_serde {
     use super::*;
@@ -17,27 +17,48 @@ mod hs_serde {
     }
 }
 
-mod hs_map_serde {
+mod hsv_map_serde {
     use super::*;
 
+    /// Colors at full brightness are stored without a value, as they were
+    /// before keys had one, so older versions can read them
+    #[derive(Serialize, Deserialize)]
+    #[serde(untagged)]
+    enum Color {
+        Hs(u8, u8),
+        Hsv(u8, u8, u8),
+    }
+
     pub fn serialize<S: Serializer>(
-        map: &HashMap<String, Option<Hs>>,
+        map: &HashMap<String, Option<Hsv>>,
         serializer: S,
     ) -> Result<S::Ok, S::Error> {
         let map = map
             .iter()
-            .map(|(k, hs)| (k, hs.map(|hs| hs.to_ints())))
+            .map(|(k, hsv)| {
+                let color = hsv.map(|hsv| match hsv.to_ints() {
+                    (h, s, 255) => Color::Hs(h, s),
+                    (h, s, v) => Color::Hsv(h, s, v),
+                });
+                (k, color)
+            })
             .collect::<HashMap<_, _>>();
         map.serialize(serializer)
     }
 
     pub fn deserialize<'de, D: Deserializer<'de>>(
         deserializer: D,
-    ) -> Result<HashMap<String, Option<Hs>>, D::Error> {
-        let map = <HashMap<String, Option<(u8, u8)>>>::deserialize(deserializer)?;
+    ) -> Result<HashMap<String, Option<Hsv>>, D::Error> {
+        let map = <HashMap<String, Option<Color>>>::deserialize(deserializer)?;
         Ok(map
             .into_iter()
-            .map(|(k, v)| (k, v.map(|(h, s)| Hs::from_ints(h, s))))
+            .map(|(k, v)| {
+                let hsv = v.map(|color| match color {
+                    Color::Hs(h, s) => Hsv::from_ints(h, s, 255),
+                    Color::Hsv(h, s, v) => Hsv::from_ints(h, s, v),
+                });
+                (k, hsv)
+            })
             .collect())
     }
 }
@@ -50,14 +71,26 @@ pub struct KeyMapLayer {
     pub color: Hs,
 }
 
+/// Keymap and LED settings of a board, as exported to a file
+///
+/// The number of layers need not match the board it is imported to. Layers
+/// the board doesn't have are ignored, and layers missing from the keymap are
+/// left unchanged.
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct KeyMap {
     pub model: String,
     pub version: u8,
+    /// Scancode names of each key by layer, in hex like `0x5C12` for
+    /// scancodes without a name
     pub map: HashMap<String, Vec<String>>,
-    #[serde(with = "hs_map_serde")]
-    pub key_leds: HashMap<String, Option<Hs>>,
+    /// Colors of keys with LEDs, as `[hue, saturation]` or, for keys dimmer
+    /// than full brightness, `[hue, saturation, value]`
+    #[serde(with = "hsv_map_serde")]
+    pub key_leds: HashMap<String, Option<Hsv>>,
     pub layers: Vec<KeyMapLayer>,
+    /// Macros, which are played by keys mapped to `MACRO(n)`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub macros: Vec<Macro>,
 }
 
 impl KeyMap {
@@ -80,4 +113,34 @@ impl KeyMap {
     pub fn to_string_pretty(&self) -> String {
         serde_json::to_string_pretty(self).unwrap()
     }
+
+    /// Number of keymap layers, which is the most of any key
+    pub fn num_layers(&self) -> usize {
+        self.map.values().map(Vec::len).max().unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_leds_value() {
+        let json = r#"{
+            "model": "test",
+            "version": 1,
+            "map": {},
+            "key_leds": {"A": [10, 20], "B": [10, 20, 30], "C": null},
+            "layers": []
+        }"#;
+        let keymap = KeyMap::from_str(json).unwrap();
+        assert_eq!(keymap.key_leds["A"], Some(Hsv::from_ints(10, 20, 255)));
+        assert_eq!(keymap.key_leds["B"], Some(Hsv::from_ints(10, 20, 30)));
+        assert_eq!(keymap.key_leds["C"], None);
+
+        let json = keymap.to_string_pretty();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["key_leds"]["A"], serde_json::json!([10, 20]));
+        assert_eq!(value["key_leds"]["B"], serde_json::json!([10, 20, 30]));
+    }
 }
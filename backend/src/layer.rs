@@ -1,7 +1,7 @@
 use glib::clone::Downgrade;
 use std::cell::Cell;
 
-use crate::{Board, Daemon, Hs, Mode, Rgb};
+use crate::{daemon::LayerLeds, Board, Daemon, Hs, Mode, Rgb, SetError};
 
 #[derive(Debug)]
 pub struct Layer {
@@ -37,13 +37,16 @@ impl Layer {
                 error!("error getting layer brightness: {}", err);
                 0
             });
+        let calibration = board.calibration();
         let color = daemon
             .color(board.board(), index)
             .map(|color| {
                 if index == 0xff {
-                    Rgb::new(color.0, color.1, color.2).to_hs_lossy()
+                    calibration
+                        .invert(Rgb::new(color.0, color.1, color.2))
+                        .to_hs_lossy()
                 } else {
-                    Hs::from_ints(color.0, color.1)
+                    calibration.invert_hs(Hs::from_ints(color.0, color.1))
                 }
             })
             .unwrap_or_else(|err| {
@@ -64,13 +67,40 @@ impl Layer {
         self.board.upgrade().unwrap()
     }
 
+    pub(crate) fn layer(&self) -> u8 {
+        self.layer
+    }
+
+    /// LED index used for brightness and color of this layer
+    pub(crate) fn index(&self) -> u8 {
+        self.index
+    }
+
+    /// Update with settings read from the daemon, returning `true` if any
+    /// changed
+    pub(crate) fn update_leds(&self, leds: LayerLeds) -> bool {
+        let calibration = self.board().calibration();
+        let color = if self.index == 0xff {
+            calibration
+                .invert(Rgb::new(leds.color.0, leds.color.1, leds.color.2))
+                .to_hs_lossy()
+        } else {
+            calibration.invert_hs(Hs::from_ints(leds.color.0, leds.color.1))
+        };
+        let mode = leds.mode.or_else(|| self.mode.get());
+        let mode_changed = self.mode.replace(mode) != mode;
+        let brightness_changed = self.brightness.replace(leds.brightness) != leds.brightness;
+        let color_changed = self.color.replace(color) != color;
+        mode_changed || brightness_changed || color_changed
+    }
+
     /// Get the current mode and speed. `None` if not supported by board.
     pub fn mode(&self) -> Option<(&'static Mode, u8)> {
         let (index, speed) = self.mode.get()?;
         Some((Mode::from_index(index)?, speed))
     }
 
-    pub async fn set_mode(&self, mode: &Mode, speed: u8) -> Result<(), String> {
+    pub async fn set_mode(&self, mode: &Mode, speed: u8) -> Result<(), SetError> {
         let board = self.board();
         board
             .thread_client()
@@ -86,7 +116,7 @@ impl Layer {
         self.brightness.get()
     }
 
-    pub async fn set_brightness(&self, brightness: i32) -> Result<(), String> {
+    pub async fn set_brightness(&self, brightness: i32) -> Result<(), SetError> {
         let board = self.board();
         board
             .thread_client()
@@ -102,13 +132,14 @@ impl Layer {
         self.color.get()
     }
 
-    pub async fn set_color(&self, hs: Hs) -> Result<(), String> {
+    pub async fn set_color(&self, hs: Hs) -> Result<(), SetError> {
         let board = self.board();
+        let calibration = board.calibration();
         let color = if self.index == 0xff {
-            let Rgb { r, g, b } = hs.to_rgb();
+            let Rgb { r, g, b } = calibration.apply(hs.to_rgb());
             (r, g, b)
         } else {
-            let (h, s) = hs.to_ints();
+            let (h, s) = calibration.apply_hs(hs).to_ints();
             (h, s, 0)
         };
         board
@@ -0,0 +1,329 @@
+//! QMK keycodes that combine a basic key with modifiers or a layer, so they
+//! can't all be listed in `keymap.json`
+//!
+//! Names are written like the QMK macros, using the names of `keymap.json`:
+//! `MOD(LEFT_CTRL, C)`, `MT(LEFT_CTRL|LEFT_SHIFT, ESC)`, `LT(2, SPACE)` and
+//! `MACRO(1)`. Layers and macros count from 1, as in `LAYER_ACCESS_1`.
+
+use std::{fmt, str::FromStr};
+
+const QK_MODS: u16 = 0x0100;
+const QK_MODS_MAX: u16 = 0x1fff;
+const QK_MACRO: u16 = 0x3000;
+const QK_MACRO_MAX: u16 = 0x30ff;
+const QK_LAYER_TAP: u16 = 0x4000;
+const QK_LAYER_TAP_MAX: u16 = 0x4fff;
+const QK_MOD_TAP: u16 = 0x6000;
+const QK_MOD_TAP_MAX: u16 = 0x7fff;
+
+/// Modifiers, as encoded in the upper byte of QMK keycodes
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Mods {
+    pub ctrl: bool,
+    pub shift: bool,
+    pub alt: bool,
+    pub super_: bool,
+    /// Modifiers are the right hand ones. QMK can't mix left and right.
+    pub right: bool,
+}
+
+impl Mods {
+    pub fn from_bits(bits: u8) -> Self {
+        Self {
+            ctrl: bits & 0x01 != 0,
+            shift: bits & 0x02 != 0,
+            alt: bits & 0x04 != 0,
+            super_: bits & 0x08 != 0,
+            right: bits & 0x10 != 0,
+        }
+    }
+
+    pub fn bits(&self) -> u8 {
+        (self.ctrl as u8)
+            | (self.shift as u8) << 1
+            | (self.alt as u8) << 2
+            | (self.super_ as u8) << 3
+            | (self.right as u8) << 4
+    }
+
+    pub fn is_empty(&self) -> bool {
+        !(self.ctrl || self.shift || self.alt || self.super_)
+    }
+
+    /// Names of the modifier keys in `keymap.json`, like `LEFT_CTRL`
+    pub fn names(&self) -> Vec<&'static str> {
+        let names = if self.right {
+            ["RIGHT_CTRL", "RIGHT_SHIFT", "RIGHT_ALT", "RIGHT_SUPER"]
+        } else {
+            ["LEFT_CTRL", "LEFT_SHIFT", "LEFT_ALT", "LEFT_SUPER"]
+        };
+        let enabled = [self.ctrl, self.shift, self.alt, self.super_];
+        names
+            .iter()
+            .zip(enabled.iter())
+            .filter(|(_, enabled)| **enabled)
+            .map(|(name, _)| *name)
+            .collect()
+    }
+}
+
+impl fmt::Display for Mods {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.names().join("|"))
+    }
+}
+
+impl FromStr for Mods {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        let mut mods = Self::default();
+        let mut sides = Vec::new();
+        for name in s.split('|').map(str::trim) {
+            let (right, key) = if let Some(key) = name.strip_prefix("LEFT_") {
+                (false, key)
+            } else if let Some(key) = name.strip_prefix("RIGHT_") {
+                (true, key)
+            } else {
+                return Err(());
+            };
+            match key {
+                "CTRL" => mods.ctrl = true,
+                "SHIFT" => mods.shift = true,
+                "ALT" => mods.alt = true,
+                "SUPER" => mods.super_ = true,
+                _ => return Err(()),
+            }
+            sides.push(right);
+        }
+        if sides.iter().any(|right| *right != sides[0]) {
+            return Err(());
+        }
+        mods.right = sides[0];
+        Ok(mods)
+    }
+}
+
+/// A keycode combining a basic key with modifiers or a layer
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Keycode {
+    /// Key pressed together with modifiers, like Ctrl+C
+    Mods(Mods, String),
+    /// Modifiers when held, and the key when tapped
+    ModTap(Mods, String),
+    /// Access a layer, counting from 0, when held, and the key when tapped
+    LayerTap(u8, String),
+    /// Play the macro with this index, counting from 0
+    Macro(u8),
+}
+
+impl Keycode {
+    /// Decode `scancode`, using `basic_name` to name the basic key
+    pub fn from_scancode<'a, F: Fn(u16) -> Option<&'a str>>(
+        scancode: u16,
+        basic_name: F,
+    ) -> Option<Self> {
+        if let QK_MACRO..=QK_MACRO_MAX = scancode {
+            return Some(Self::Macro(scancode as u8));
+        }
+        let key = basic_name(scancode & 0xff)?.to_string();
+        match scancode {
+            QK_MODS..=QK_MODS_MAX => {
+                let mods = Mods::from_bits(((scancode >> 8) & 0x1f) as u8);
+                // Only the right hand bit, without any modifiers
+                if mods.is_empty() {
+                    None
+                } else {
+                    Some(Self::Mods(mods, key))
+                }
+            }
+            QK_LAYER_TAP..=QK_LAYER_TAP_MAX => {
+                Some(Self::LayerTap(((scancode >> 8) & 0xf) as u8, key))
+            }
+            QK_MOD_TAP..=QK_MOD_TAP_MAX => {
+                let mods = Mods::from_bits(((scancode >> 8) & 0x1f) as u8);
+                if mods.is_empty() {
+                    None
+                } else {
+                    Some(Self::ModTap(mods, key))
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Encode as a scancode, using `basic_scancode` to look up the basic key
+    pub fn to_scancode<F: Fn(&str) -> Option<u16>>(&self, basic_scancode: F) -> Option<u16> {
+        let (high, key) = match self {
+            Self::Macro(index) => return Some(QK_MACRO | *index as u16),
+            Self::Mods(mods, key) if !mods.is_empty() => ((mods.bits() as u16) << 8, key),
+            Self::ModTap(mods, key) if !mods.is_empty() => {
+                (QK_MOD_TAP | (mods.bits() as u16) << 8, key)
+            }
+            Self::LayerTap(layer, key) if *layer < 16 => (QK_LAYER_TAP | (*layer as u16) << 8, key),
+            _ => return None,
+        };
+        let key = basic_scancode(key).filter(|scancode| *scancode <= 0xff)?;
+        Some(high | key)
+    }
+
+    /// The basic key, sent with the modifiers or when tapped
+    pub fn basic_key(&self) -> Option<&str> {
+        match self {
+            Self::Mods(_, key) | Self::ModTap(_, key) | Self::LayerTap(_, key) => Some(key),
+            Self::Macro(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for Keycode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Mods(mods, key) => write!(f, "MOD({}, {})", mods, key),
+            Self::ModTap(mods, key) => write!(f, "MT({}, {})", mods, key),
+            Self::LayerTap(layer, key) => write!(f, "LT({}, {})", layer + 1, key),
+            Self::Macro(index) => write!(f, "MACRO({})", *index as u16 + 1),
+        }
+    }
+}
+
+impl FromStr for Keycode {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        let (function, args) = if let Some(args) = s.strip_suffix(')') {
+            let mut parts = args.splitn(2, '(');
+            (parts.next().ok_or(())?, parts.next().ok_or(())?)
+        } else {
+            return Err(());
+        };
+        if function == "MACRO" {
+            let index = args.trim().parse::<u16>().map_err(|_| ())?;
+            if index == 0 || index > 256 {
+                return Err(());
+            }
+            return Ok(Self::Macro((index - 1) as u8));
+        }
+        let mut args = args.splitn(2, ',').map(str::trim);
+        let (arg, key) = (args.next().ok_or(())?, args.next().ok_or(())?);
+        if key.is_empty() {
+            return Err(());
+        }
+        match function {
+            "MOD" => Ok(Self::Mods(arg.parse()?, key.to_string())),
+            "MT" => Ok(Self::ModTap(arg.parse()?, key.to_string())),
+            "LT" => {
+                let layer = arg.parse::<u8>().map_err(|_| ())?;
+                if layer == 0 || layer > 16 {
+                    return Err(());
+                }
+                Ok(Self::LayerTap(layer - 1, key.to_string()))
+            }
+            _ => Err(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn basic_name(scancode: u16) -> Option<&'static str> {
+        match scancode {
+            0x06 => Some("C"),
+            0x29 => Some("ESC"),
+            0x2c => Some("SPACE"),
+            _ => None,
+        }
+    }
+
+    fn basic_scancode(name: &str) -> Option<u16> {
+        match name {
+            "C" => Some(0x06),
+            "ESC" => Some(0x29),
+            "SPACE" => Some(0x2c),
+            "LAYER_ACCESS_1" => Some(0x5100),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn mods() {
+        let ctrl_c = Keycode::Mods(
+            Mods {
+                ctrl: true,
+                ..Default::default()
+            },
+            "C".to_string(),
+        );
+        assert_eq!(ctrl_c.to_scancode(basic_scancode), Some(0x0106));
+        assert_eq!(
+            Keycode::from_scancode(0x0106, basic_name),
+            Some(ctrl_c.clone())
+        );
+        assert_eq!(ctrl_c.to_string(), "MOD(LEFT_CTRL, C)");
+        assert_eq!("MOD(LEFT_CTRL, C)".parse(), Ok(ctrl_c));
+
+        let keycode = "MOD(RIGHT_CTRL|RIGHT_SHIFT, ESC)"
+            .parse::<Keycode>()
+            .unwrap();
+        assert_eq!(keycode.to_scancode(basic_scancode), Some(0x1329));
+        assert_eq!(Keycode::from_scancode(0x1329, basic_name), Some(keycode));
+    }
+
+    #[test]
+    fn mod_tap() {
+        let ctrl_esc = Keycode::ModTap(
+            Mods {
+                ctrl: true,
+                ..Default::default()
+            },
+            "ESC".to_string(),
+        );
+        assert_eq!(ctrl_esc.to_scancode(basic_scancode), Some(0x6129));
+        assert_eq!(
+            Keycode::from_scancode(0x6129, basic_name),
+            Some(ctrl_esc.clone())
+        );
+        assert_eq!(ctrl_esc.to_string(), "MT(LEFT_CTRL, ESC)");
+        assert_eq!("MT(LEFT_CTRL, ESC)".parse(), Ok(ctrl_esc));
+
+        let right = "MT(RIGHT_SHIFT|RIGHT_ALT, SPACE)"
+            .parse::<Keycode>()
+            .unwrap();
+        assert_eq!(right.to_scancode(basic_scancode), Some(0x762c));
+        assert!("MT(LEFT_CTRL|RIGHT_ALT, SPACE)".parse::<Keycode>().is_err());
+    }
+
+    #[test]
+    fn layer_tap() {
+        let keycode = "LT(2, SPACE)".parse::<Keycode>().unwrap();
+        assert_eq!(keycode, Keycode::LayerTap(1, "SPACE".to_string()));
+        assert_eq!(keycode.to_scancode(basic_scancode), Some(0x412c));
+        assert_eq!(Keycode::from_scancode(0x412c, basic_name), Some(keycode));
+        assert!("LT(0, SPACE)".parse::<Keycode>().is_err());
+        assert!("LT(17, SPACE)".parse::<Keycode>().is_err());
+    }
+
+    #[test]
+    fn macros() {
+        let keycode = "MACRO(2)".parse::<Keycode>().unwrap();
+        assert_eq!(keycode, Keycode::Macro(1));
+        assert_eq!(keycode.to_scancode(basic_scancode), Some(0x3001));
+        assert_eq!(Keycode::from_scancode(0x3001, basic_name), Some(keycode));
+        assert_eq!(Keycode::Macro(255).to_string(), "MACRO(256)");
+        assert!("MACRO(0)".parse::<Keycode>().is_err());
+    }
+
+    #[test]
+    fn invalid() {
+        // Only basic keys can be tapped
+        let keycode = Keycode::LayerTap(0, "LAYER_ACCESS_1".to_string());
+        assert_eq!(keycode.to_scancode(basic_scancode), None);
+        assert_eq!(Keycode::from_scancode(0x4104, basic_name), None);
+        assert_eq!(Keycode::from_scancode(0x2029, basic_name), None);
+        assert_eq!(Keycode::from_scancode(0x1029, basic_name), None);
+        assert!("ESC".parse::<Keycode>().is_err());
+        assert!("MT(LEFT_CTRL)".parse::<Keycode>().is_err());
+    }
+}
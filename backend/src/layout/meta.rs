@@ -1,4 +1,4 @@
-use crate::Rgb;
+use crate::{Calibration, Rgb};
 use serde::Deserialize;
 
 fn num_layers_default() -> u8 {
@@ -23,5 +23,12 @@ pub struct Meta {
     /// Number or layers; e.g. 2 where layer 2 is used when `Fn` is held
     #[serde(default = "num_layers_default")]
     pub num_layers: u8,
+    /// Firmware is QMK, which supports keycodes like mod-tap and layer-tap
+    #[serde(default)]
+    pub is_qmk: bool,
     pub pressed_color: Rgb,
+    /// Correction of LED colors, for keyboards where they differ from the
+    /// screen
+    #[serde(default)]
+    pub calibration: Calibration,
 }
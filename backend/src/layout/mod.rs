@@ -1,7 +1,9 @@
 use std::{collections::HashMap, fs, path::Path};
 
+mod keycode;
 mod meta;
 mod physical_layout;
+pub use self::keycode::{Keycode, Mods};
 pub use self::meta::Meta;
 pub(crate) use physical_layout::{PhysicalLayout, PhysicalLayoutKey};
 
@@ -122,17 +124,73 @@ impl Layout {
         )
     }
 
-    /// Get the scancode number corresponding to a name
-    pub fn scancode_to_name(&self, scancode: u16) -> Option<&str> {
-        self.scancode_names.get(&scancode).map(String::as_str)
+    /// Get the name corresponding to a scancode number
+    ///
+    /// Scancodes without a name are written in hex, like `0x5C12`, so they
+    /// can still be shown and exported.
+    pub fn scancode_to_name(&self, scancode: u16) -> String {
+        if let Some(name) = self.scancode_names.get(&scancode) {
+            return name.clone();
+        }
+        match self.keycode_from_scancode(scancode) {
+            Some(keycode) => keycode.to_string(),
+            None => raw_scancode_name(scancode),
+        }
     }
 
-    /// Get the name corresponding to a scancode number
+    /// Get the scancode number corresponding to a name
     pub fn scancode_from_name(&self, name: &str) -> Option<u16> {
-        self.keymap.get(name).copied()
+        if let Some(scancode) = self.keymap.get(name) {
+            return Some(*scancode);
+        }
+        if let Some(scancode) = parse_raw_scancode_name(name) {
+            return Some(scancode);
+        }
+        if !self.meta.is_qmk {
+            return None;
+        }
+        name.parse::<Keycode>()
+            .ok()?
+            .to_scancode(|name| self.keymap.get(name).copied())
+    }
+
+    /// Decode a keycode combining a basic key with modifiers or a layer, if
+    /// the firmware supports them
+    pub fn keycode_from_scancode(&self, scancode: u16) -> Option<Keycode> {
+        if !self.meta.is_qmk {
+            return None;
+        }
+        Keycode::from_scancode(scancode, |scancode| {
+            self.scancode_names.get(&scancode).map(String::as_str)
+        })
+    }
+
+    /// Names of the basic keys, which can be combined into a `Keycode`,
+    /// ordered by scancode
+    pub fn basic_scancode_names(&self) -> Vec<&str> {
+        let mut names = self
+            .keymap
+            .iter()
+            .filter(|(_, scancode)| **scancode <= 0xff)
+            .collect::<Vec<_>>();
+        names.sort_by_key(|(name, scancode)| (**scancode, name.as_str()));
+        names.into_iter().map(|(name, _)| name.as_str()).collect()
     }
 }
 
+/// Name of a scancode by its value, like `0x5C12`
+pub fn raw_scancode_name(scancode: u16) -> String {
+    format!("0x{:04X}", scancode)
+}
+
+fn parse_raw_scancode_name(name: &str) -> Option<u16> {
+    let hex = name.strip_prefix("0x")?;
+    if hex.is_empty() || hex.len() > 4 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    u16::from_str_radix(hex, 16).ok()
+}
+
 fn parse_keymap_json(keymap_json: &str) -> (HashMap<String, u16>, HashMap<u16, String>) {
     let mut scancode_names = HashMap::new();
     let keymap: HashMap<String, u16> = serde_json::from_str(keymap_json).unwrap();
@@ -188,6 +246,30 @@ mod tests {
         }
     }
 
+    #[test]
+    fn qmk_keycodes() {
+        let layout = Layout::from_board("system76/launch_1").unwrap();
+        let scancode = layout.scancode_from_name("LT(2, SPACE)").unwrap();
+        assert_eq!(scancode, 0x412c);
+        assert_eq!(layout.scancode_to_name(scancode), "LT(2, SPACE)");
+        assert_eq!(layout.scancode_to_name(0x44), "F11");
+        assert_eq!(layout.scancode_to_name(0x0106), "MOD(LEFT_CTRL, C)");
+
+        let layout_ec = Layout::from_board("system76/darp6").unwrap();
+        assert_eq!(layout_ec.scancode_from_name("LT(2, SPACE)"), None);
+    }
+
+    #[test]
+    fn raw_scancodes() {
+        let layout = Layout::from_board("system76/darp6").unwrap();
+        assert_eq!(layout.scancode_to_name(0x5c12), "0x5C12");
+        assert_eq!(layout.scancode_from_name("0x5C12"), Some(0x5c12));
+        assert_eq!(layout.scancode_from_name("0x5c12"), Some(0x5c12));
+        assert_eq!(layout.scancode_from_name("0x"), None);
+        assert_eq!(layout.scancode_from_name("0x15C12"), None);
+        assert_eq!(layout.scancode_from_name("0x+C12"), None);
+    }
+
     #[test]
     fn has_all_layouts_in_dir() -> io::Result<()> {
         let layouts = layouts();
@@ -17,18 +17,31 @@ extern crate log;
 mod backend;
 mod board;
 mod color;
+mod config;
 mod daemon;
 mod deref_cell;
+#[cfg(target_os = "linux")]
+mod dfu;
+mod effect;
+mod firmware;
+mod idle;
 mod key;
 mod keymap;
 mod layer;
 mod layout;
 mod localize;
+mod macros;
 mod mode;
+mod mode_preview;
+mod paint;
 mod rect;
+mod schedule;
+mod swatch;
 
 use crate::daemon::*;
+pub use crate::daemon::{BoardCapabilities, DummyControl, DummyError, LedFrame, SetError};
 pub use crate::{
-    backend::*, board::*, color::*, deref_cell::*, key::*, keymap::*, layer::*, layout::*,
-    localize::*, mode::*, rect::*,
+    backend::*, board::*, color::*, deref_cell::*, effect::*, firmware::*, idle::*, key::*,
+    keymap::*, layer::*, layout::*, localize::*, macros::*, mode::*, mode_preview::*, paint::*,
+    rect::*, schedule::*, swatch::*,
 };
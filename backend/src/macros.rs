@@ -0,0 +1,240 @@
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
+
+use crate::Layout;
+
+// Encoding of macros in firmware, like the `SEND_STRING` codes of QMK. Each
+// key event is followed by the basic keycode, and delays by milliseconds as a
+// little endian `u16`.
+const MACRO_END: u8 = 0;
+const MACRO_DOWN: u8 = 2;
+const MACRO_UP: u8 = 3;
+const MACRO_DELAY: u8 = 4;
+
+/// Step of a `Macro`, with keys named as in `keymap.json`
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum MacroEvent {
+    Down(String),
+    Up(String),
+    /// Wait, in milliseconds
+    Delay(u16),
+}
+
+/// Sequence of key events played by the firmware when a key bound to
+/// `Keycode::Macro` is pressed
+///
+/// As text, events are separated by whitespace: `+NAME` presses a key,
+/// `-NAME` releases it, `NAME` taps it, and `100ms` waits.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct Macro {
+    pub events: Vec<MacroEvent>,
+}
+
+impl Macro {
+    /// Macro typing `text` on a US layout
+    pub fn from_text(text: &str) -> Result<Self, String> {
+        let mut events = Vec::new();
+        for c in text.chars() {
+            let (name, shift) =
+                char_key(c).ok_or_else(|| format!("Can't type character {:?}", c))?;
+            if shift {
+                events.push(MacroEvent::Down("LEFT_SHIFT".to_string()));
+            }
+            events.push(MacroEvent::Down(name.clone()));
+            events.push(MacroEvent::Up(name));
+            if shift {
+                events.push(MacroEvent::Up("LEFT_SHIFT".to_string()));
+            }
+        }
+        Ok(Self { events })
+    }
+
+    /// Encode for the firmware, which only supports basic keys
+    pub fn to_bytes(&self, layout: &Layout) -> Result<Vec<u8>, String> {
+        let basic = |name: &str| {
+            layout
+                .scancode_from_name(name)
+                .filter(|scancode| *scancode <= 0xff)
+                .map(|scancode| scancode as u8)
+                .ok_or_else(|| format!("Key '{}' can't be used in a macro", name))
+        };
+        let mut data = Vec::new();
+        for event in &self.events {
+            match event {
+                MacroEvent::Down(name) => data.extend_from_slice(&[MACRO_DOWN, basic(name)?]),
+                MacroEvent::Up(name) => data.extend_from_slice(&[MACRO_UP, basic(name)?]),
+                MacroEvent::Delay(ms) => {
+                    data.extend_from_slice(&[MACRO_DELAY, *ms as u8, (*ms >> 8) as u8])
+                }
+            }
+        }
+        data.push(MACRO_END);
+        Ok(data)
+    }
+
+    /// Decode from the firmware, stopping at the end marker
+    pub fn from_bytes(data: &[u8], layout: &Layout) -> Result<Self, String> {
+        let name = |scancode: Option<&u8>| -> Result<String, String> {
+            let scancode = *scancode.ok_or("Truncated macro")?;
+            Ok(layout.scancode_to_name(scancode.into()))
+        };
+        let mut events = Vec::new();
+        let mut bytes = data.iter();
+        while let Some(code) = bytes.next() {
+            events.push(match *code {
+                MACRO_END => break,
+                MACRO_DOWN => MacroEvent::Down(name(bytes.next())?),
+                MACRO_UP => MacroEvent::Up(name(bytes.next())?),
+                MACRO_DELAY => match (bytes.next(), bytes.next()) {
+                    (Some(low), Some(high)) => {
+                        MacroEvent::Delay(u16::from(*low) | u16::from(*high) << 8)
+                    }
+                    _ => return Err("Truncated macro".to_string()),
+                },
+                code => return Err(format!("Unknown macro code {}", code)),
+            });
+        }
+        Ok(Self { events })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+}
+
+/// Key typing `c` on a US layout, and whether shift is needed
+fn char_key(c: char) -> Option<(String, bool)> {
+    const SYMBOLS: &[(char, char, &str)] = &[
+        ('-', '_', "MINUS"),
+        ('=', '+', "EQUALS"),
+        ('[', '{', "BRACE_OPEN"),
+        (']', '}', "BRACE_CLOSE"),
+        ('\\', '|', "BACKSLASH"),
+        (';', ':', "SEMICOLON"),
+        ('\'', '"', "QUOTE"),
+        ('`', '~', "TICK"),
+        (',', '<', "COMMA"),
+        ('.', '>', "PERIOD"),
+        ('/', '?', "SLASH"),
+        ('1', '!', "1"),
+        ('2', '@', "2"),
+        ('3', '#', "3"),
+        ('4', '$', "4"),
+        ('5', '%', "5"),
+        ('6', '^', "6"),
+        ('7', '&', "7"),
+        ('8', '*', "8"),
+        ('9', '(', "9"),
+        ('0', ')', "0"),
+    ];
+
+    match c {
+        'a'..='z' => Some((c.to_ascii_uppercase().to_string(), false)),
+        'A'..='Z' => Some((c.to_string(), true)),
+        ' ' => Some(("SPACE".to_string(), false)),
+        '\n' => Some(("ENTER".to_string(), false)),
+        '\t' => Some(("TAB".to_string(), false)),
+        _ => SYMBOLS.iter().find_map(|(plain, shifted, name)| {
+            if c == *plain {
+                Some((name.to_string(), false))
+            } else if c == *shifted {
+                Some((name.to_string(), true))
+            } else {
+                None
+            }
+        }),
+    }
+}
+
+impl fmt::Display for Macro {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut tokens = Vec::new();
+        let mut events = self.events.iter().peekable();
+        while let Some(event) = events.next() {
+            tokens.push(match event {
+                // A press followed by a release of the same key is a tap
+                MacroEvent::Down(name) if events.peek() == Some(&&MacroEvent::Up(name.clone())) => {
+                    events.next();
+                    name.clone()
+                }
+                MacroEvent::Down(name) => format!("+{}", name),
+                MacroEvent::Up(name) => format!("-{}", name),
+                MacroEvent::Delay(ms) => format!("{}ms", ms),
+            });
+        }
+        write!(f, "{}", tokens.join(" "))
+    }
+}
+
+impl FromStr for Macro {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let mut events = Vec::new();
+        for token in s.split_whitespace() {
+            if let Some(name) = token.strip_prefix('+') {
+                events.push(MacroEvent::Down(name.to_string()));
+            } else if let Some(name) = token.strip_prefix('-') {
+                events.push(MacroEvent::Up(name.to_string()));
+            } else if let Some(ms) = token.strip_suffix("ms") {
+                let ms = ms
+                    .parse()
+                    .map_err(|_| format!("Invalid delay '{}'", token))?;
+                events.push(MacroEvent::Delay(ms));
+            } else {
+                events.push(MacroEvent::Down(token.to_string()));
+                events.push(MacroEvent::Up(token.to_string()));
+            }
+        }
+        Ok(Self { events })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text() {
+        let macro_ = Macro::from_text("Hi!").unwrap();
+        assert_eq!(
+            macro_.to_string(),
+            "+LEFT_SHIFT H -LEFT_SHIFT I +LEFT_SHIFT 1 -LEFT_SHIFT"
+        );
+        assert_eq!(macro_.to_string().parse(), Ok(macro_));
+        assert!(Macro::from_text("é").is_err());
+    }
+
+    #[test]
+    fn parse() {
+        let macro_ = "+LEFT_CTRL C -LEFT_CTRL 100ms".parse::<Macro>().unwrap();
+        assert_eq!(
+            macro_.events,
+            vec![
+                MacroEvent::Down("LEFT_CTRL".to_string()),
+                MacroEvent::Down("C".to_string()),
+                MacroEvent::Up("C".to_string()),
+                MacroEvent::Up("LEFT_CTRL".to_string()),
+                MacroEvent::Delay(100),
+            ]
+        );
+        assert!("10xms".parse::<Macro>().is_err());
+    }
+
+    #[test]
+    fn bytes() {
+        let layout = Layout::from_board("system76/launch_1").unwrap();
+        let macro_ = "+LEFT_CTRL C -LEFT_CTRL 300ms".parse::<Macro>().unwrap();
+        let data = macro_.to_bytes(&layout).unwrap();
+        assert_eq!(data, [2, 0xe0, 2, 0x06, 3, 0x06, 3, 0xe0, 4, 0x2c, 0x01, 0]);
+        assert_eq!(Macro::from_bytes(&data, &layout), Ok(macro_));
+        assert_eq!(Macro::from_bytes(&[0, 2, 4], &layout), Ok(Macro::default()));
+        assert!(Macro::from_bytes(&[4, 1], &layout).is_err());
+        assert!("LAYER_ACCESS_1"
+            .parse::<Macro>()
+            .unwrap()
+            .to_bytes(&layout)
+            .is_err());
+    }
+}
@@ -0,0 +1,227 @@
+use std::f64::consts::PI;
+
+use crate::{
+    effect::{scale, Random},
+    Effect, EffectKey, Hs, Hsv, Mode, Rgb,
+};
+
+/// Seconds between simulated key presses, so modes reacting to keys show
+/// something without a keyboard being used
+const SIMULATED_PRESS_INTERVAL: f64 = 1.;
+/// Seconds a key stays lit in `ACTIVE_KEYS` after being pressed
+const ACTIVE_KEY_FADE: f64 = 0.5;
+/// Splashes shown at once by `MULTISPLASH`
+const MAX_SPLASHES: usize = 8;
+
+/// Approximation of the firmware's LED `Mode`s, for showing them on screen
+/// without the keyboard, or when using a fake keyboard
+///
+/// Settings can be changed between frames without restarting the animation.
+pub struct ModePreview {
+    /// Index of the `Mode`
+    pub mode: u8,
+    pub speed: u8,
+    pub color: Hs,
+    /// Brightness, from 0.0 to 1.0
+    pub brightness: f64,
+    /// Colors of keys in `PER_KEY` mode, in the same order as the keys
+    /// rendered
+    pub key_colors: Vec<Option<Hsv>>,
+    /// Keys currently pressed, in the same order as the keys rendered
+    pub pressed: Vec<bool>,
+    was_pressed: Vec<bool>,
+    /// Key index and time of presses, newest last
+    presses: Vec<(usize, f64)>,
+    /// Hue of each key in `RAINDROPS`
+    drops: Vec<f64>,
+    last_time: f64,
+    random: Random,
+}
+
+impl ModePreview {
+    pub fn new(mode: u8, speed: u8, color: Hs) -> Self {
+        Self {
+            mode,
+            speed,
+            color,
+            brightness: 1.,
+            key_colors: Vec::new(),
+            pressed: Vec::new(),
+            was_pressed: Vec::new(),
+            presses: Vec::new(),
+            drops: Vec::new(),
+            last_time: 0.,
+            random: Random::new(0x1234_5678),
+        }
+    }
+
+    /// Hue cycles per second at `speed`, like QMK's scaling of its timer
+    fn cycles_per_second(&self) -> f64 {
+        (f64::from(self.speed / 8) + 1.) * 1000. / 65536.
+    }
+
+    /// Record new presses, simulating one if no key was pressed for a while
+    fn update_presses(&mut self, time: f64, num_keys: usize) {
+        self.pressed.resize(num_keys, false);
+        self.was_pressed.resize(num_keys, false);
+        for i in 0..num_keys {
+            if self.pressed[i] && !self.was_pressed[i] {
+                self.presses.push((i, time));
+            }
+        }
+        self.was_pressed.copy_from_slice(&self.pressed);
+
+        let last_press = self.presses.last().map_or(0., |(_, time)| *time);
+        if num_keys > 0 && time - last_press > SIMULATED_PRESS_INTERVAL {
+            let i = (self.random.next_f64() * num_keys as f64) as usize % num_keys;
+            self.presses.push((i, time));
+        }
+        if self.presses.len() > MAX_SPLASHES {
+            self.presses.remove(0);
+        }
+    }
+
+    /// Brightness of a key from splashes spreading out from presses
+    fn splash_level(&self, time: f64, keys: &[EffectKey], key: &EffectKey, count: usize) -> f64 {
+        let spread = self.cycles_per_second() * 4.;
+        self.presses
+            .iter()
+            .rev()
+            .take(count)
+            .map(|(i, start)| {
+                let origin = keys[*i];
+                let dist = ((key.x - origin.x).powi(2) + (key.y - origin.y).powi(2)).sqrt();
+                let radius = (time - start) * spread;
+                (1. - (dist - radius).abs() * 8.) * (1. - radius).max(0.)
+            })
+            .fold(0., f64::max)
+    }
+}
+
+fn hue(h: f64) -> Rgb {
+    Hs::new(h.rem_euclid(1.) * 2. * PI, 1.).to_rgb()
+}
+
+impl Effect for ModePreview {
+    fn render(&mut self, time: f64, keys: &[EffectKey]) -> Vec<Rgb> {
+        let elapsed = (time - self.last_time).max(0.);
+        self.last_time = time;
+        self.update_presses(time, keys.len());
+
+        let t = time * self.cycles_per_second();
+        let base_hue = *self.color.h / (2. * PI);
+        let color = self.color.to_rgb();
+        let id = Mode::from_index(self.mode).map_or("", |mode| mode.id);
+
+        if id == "RAINDROPS" {
+            self.drops.resize(keys.len(), base_hue);
+            for _ in 0..keys.len() {
+                if self.random.next_f64() < elapsed {
+                    let i = (self.random.next_f64() * keys.len() as f64) as usize % keys.len();
+                    self.drops[i] = base_hue + (self.random.next_f64() - 0.5) / 4.;
+                }
+            }
+        }
+
+        let colors = keys
+            .iter()
+            .enumerate()
+            .map(|(i, key)| {
+                let (dx, dy) = (key.x - 0.5, key.y - 0.5);
+                let dist = (dx * dx + dy * dy).sqrt();
+                let angle = dy.atan2(dx) / (2. * PI);
+                match id {
+                    "SOLID_COLOR" => color,
+                    "PER_KEY" => self
+                        .key_colors
+                        .get(i)
+                        .copied()
+                        .flatten()
+                        .map_or(Rgb::new(0, 0, 0), Hsv::to_rgb),
+                    "ACTIVE_KEYS" => {
+                        let level = self
+                            .presses
+                            .iter()
+                            .filter(|(pressed, _)| *pressed == i)
+                            .map(|(_, start)| 1. - (time - start) / ACTIVE_KEY_FADE)
+                            .fold(0., f64::max);
+                        let level = if self.pressed[i] { 1. } else { level };
+                        scale(color, level)
+                    }
+                    "CYCLE_ALL" => hue(t),
+                    "CYCLE_LEFT_RIGHT" => hue(key.x - t),
+                    "CYCLE_UP_DOWN" => hue(key.y - t),
+                    "CYCLE_OUT_IN" => hue(dist + t),
+                    "CYCLE_OUT_IN_DUAL" => {
+                        let dx = (key.x - 0.25).abs().min((key.x - 0.75).abs());
+                        hue((dx * dx + dy * dy).sqrt() + t)
+                    }
+                    "RAINBOW_MOVING_CHEVRON" => hue(key.x + dy.abs() / 2. - t),
+                    "CYCLE_PINWHEEL" => hue(angle + t),
+                    "CYCLE_SPIRAL" => hue(angle + dist - t),
+                    "RAINDROPS" => hue(self.drops[i]),
+                    "SPLASH" | "MULTISPLASH" => {
+                        let count = if id == "SPLASH" { 1 } else { MAX_SPLASHES };
+                        let level = self.splash_level(time, keys, key, count).max(0.);
+                        hue(base_hue + level / 4.)
+                    }
+                    _ => Rgb::new(0, 0, 0),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        colors
+            .into_iter()
+            .map(|color| scale(color, self.brightness))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys() -> Vec<EffectKey> {
+        vec![EffectKey { x: 0., y: 0. }, EffectKey { x: 1., y: 1. }]
+    }
+
+    #[test]
+    fn all_modes() {
+        let keys = keys();
+        for mode in Mode::all() {
+            let mut preview = ModePreview::new(mode.index, 128, Hs::new(0., 1.));
+            for frame in 0..20 {
+                let colors = preview.render(frame as f64 / 10., &keys);
+                assert_eq!(colors.len(), keys.len(), "{}", mode.id);
+            }
+        }
+
+        // Hue changes over time
+        let mode = Mode::from_id("CYCLE_ALL").unwrap();
+        let mut preview = ModePreview::new(mode.index, 128, Hs::new(0., 1.));
+        let start = preview.render(0., &keys)[0].to_string();
+        assert_ne!(preview.render(1., &keys)[0].to_string(), start);
+
+        // Only the pressed key is lit
+        let mode = Mode::from_id("ACTIVE_KEYS").unwrap();
+        let mut preview = ModePreview::new(mode.index, 128, Hs::new(0., 1.));
+        preview.pressed = vec![false, true];
+        let colors = preview.render(0., &keys);
+        assert_eq!(colors[0].r, 0);
+        assert_eq!(colors[1].r, 255);
+    }
+
+    #[test]
+    fn solid_and_disabled() {
+        let keys = keys();
+        let mode = Mode::from_id("SOLID_COLOR").unwrap();
+        let mut preview = ModePreview::new(mode.index, 0, Hs::new(0., 1.));
+        assert_eq!(preview.render(0., &keys)[1].r, 255);
+        preview.brightness = 0.;
+        assert_eq!(preview.render(0., &keys)[1].r, 0);
+
+        preview.brightness = 1.;
+        preview.mode = Mode::from_id("DISABLED").unwrap().index;
+        assert_eq!(preview.render(0., &keys)[1].r, 0);
+    }
+}
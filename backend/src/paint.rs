@@ -0,0 +1,254 @@
+//! Painting images and gradients onto per-key LEDs, using the physical
+//! position of each key
+
+use std::f64::consts::FRAC_1_SQRT_2;
+
+use crate::{effect::rect_bounds, effect_keys, Rect, Rgb};
+
+/// Point of a `Gradient` where it is `color`
+#[derive(Clone, Copy, Debug)]
+pub struct GradientStop {
+    /// Position from 0.0 at the start of the gradient to 1.0 at the end
+    pub position: f64,
+    pub color: Rgb,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GradientShape {
+    /// Starting at one side of the keyboard, going in the direction of
+    /// `angle` in radians, where 0.0 is left to right and PI / 2 is top to
+    /// bottom
+    Linear { angle: f64 },
+    /// Starting at the center of the keyboard, ending at the corners
+    Radial,
+}
+
+/// Colors blending between several stops
+#[derive(Clone, Debug)]
+pub struct Gradient {
+    pub shape: GradientShape,
+    pub stops: Vec<GradientStop>,
+}
+
+impl Gradient {
+    /// Color at `position`, from 0.0 to 1.0, mixing the stops on either side
+    pub fn color_at(&self, position: f64) -> Rgb {
+        // Stops at NaN or infinity can't be ordered, and are ignored
+        let mut stops = self.stops.clone();
+        stops.retain(|stop| stop.position.is_finite());
+        stops.sort_by(|a, b| a.position.partial_cmp(&b.position).unwrap());
+        let (first, last) = match (stops.first(), stops.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return Rgb::new(0, 0, 0),
+        };
+        if position <= first.position {
+            return first.color;
+        }
+        for pair in stops.windows(2) {
+            let (a, b) = (&pair[0], &pair[1]);
+            if position <= b.position {
+                let t = if b.position > a.position {
+                    (position - a.position) / (b.position - a.position)
+                } else {
+                    1.
+                };
+                let (a, b) = (a.color.to_floats(), b.color.to_floats());
+                return Rgb::from_floats(
+                    a.0 + (b.0 - a.0) * t,
+                    a.1 + (b.1 - a.1) * t,
+                    a.2 + (b.2 - a.2) * t,
+                );
+            }
+        }
+        last.color
+    }
+}
+
+/// Image to paint, stretched to cover the keyboard
+#[derive(Clone, Debug)]
+pub struct PaintImage {
+    width: usize,
+    height: usize,
+    pixels: Vec<Rgb>,
+}
+
+impl PaintImage {
+    /// Image from rows of `pixels`, starting at the top left
+    pub fn new(width: usize, height: usize, pixels: Vec<Rgb>) -> Result<Self, String> {
+        if width == 0 || height == 0 {
+            return Err("Image is empty".to_string());
+        }
+        if pixels.len() != width * height {
+            return Err(format!(
+                "Image is {}x{}, but has {} pixels",
+                width,
+                height,
+                pixels.len()
+            ));
+        }
+        Ok(Self {
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    /// Average color of the pixels from `x0`, `y0` to `x1`, `y1`, each scaled
+    /// from 0.0 to 1.0
+    fn average(&self, x0: f64, y0: f64, x1: f64, y1: f64) -> Rgb {
+        // Pixels overlapping the area, with at least one on each axis
+        let range = |start: f64, end: f64, len: usize| {
+            let start = ((start * len as f64) as usize).min(len - 1);
+            let end = ((end * len as f64).ceil() as usize).min(len).max(start + 1);
+            start..end
+        };
+        let (mut r, mut g, mut b, mut count) = (0., 0., 0., 0.);
+        for y in range(y0, y1, self.height) {
+            for x in range(x0, x1, self.width) {
+                let pixel = self.pixels[y * self.width + x];
+                r += f64::from(pixel.r);
+                g += f64::from(pixel.g);
+                b += f64::from(pixel.b);
+                count += 1.;
+            }
+        }
+        Rgb::new(
+            (r / count).round() as u8,
+            (g / count).round() as u8,
+            (b / count).round() as u8,
+        )
+    }
+}
+
+/// Something that can be painted onto the keyboard
+#[derive(Clone, Debug)]
+pub enum Paint {
+    Gradient(Gradient),
+    Image(PaintImage),
+}
+
+impl Paint {
+    /// Color of each of `rects`, which are the physical positions of keys
+    pub fn colors(&self, rects: &[Rect]) -> Vec<Rgb> {
+        match self {
+            Self::Gradient(gradient) => effect_keys(rects)
+                .into_iter()
+                .map(|key| {
+                    let (x, y) = (key.x - 0.5, key.y - 0.5);
+                    let position = match gradient.shape {
+                        GradientShape::Linear { angle } => {
+                            let (sin, cos) = angle.sin_cos();
+                            // Scaled so the corners are at 0.0 and 1.0
+                            0.5 + (x * cos + y * sin) / (cos.abs() + sin.abs())
+                        }
+                        GradientShape::Radial => (x * x + y * y).sqrt() / FRAC_1_SQRT_2,
+                    };
+                    gradient.color_at(position)
+                })
+                .collect(),
+            Self::Image(image) => {
+                let bounds = rects.iter().map(rect_bounds).collect::<Vec<_>>();
+                let min_x = bounds.iter().map(|b| b.0).fold(f64::INFINITY, f64::min);
+                let min_y = bounds.iter().map(|b| b.1).fold(f64::INFINITY, f64::min);
+                let max_x = bounds.iter().map(|b| b.2).fold(f64::NEG_INFINITY, f64::max);
+                let max_y = bounds.iter().map(|b| b.3).fold(f64::NEG_INFINITY, f64::max);
+                let (width, height) = ((max_x - min_x).max(1.), (max_y - min_y).max(1.));
+                bounds
+                    .iter()
+                    .map(|(left, top, right, bottom)| {
+                        image.average(
+                            (left - min_x) / width,
+                            (top - min_y) / height,
+                            (right - min_x) / width,
+                            (bottom - min_y) / height,
+                        )
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+
+    fn rects() -> Vec<Rect> {
+        vec![
+            Rect::new(0., 1., 1., 1.),
+            Rect::new(1., 1., 1., 1.),
+            Rect::new(0., 0., 2., 1.),
+        ]
+    }
+
+    fn gradient(shape: GradientShape) -> Paint {
+        Paint::Gradient(Gradient {
+            shape,
+            stops: vec![
+                GradientStop {
+                    position: 0.,
+                    color: Rgb::new(0, 0, 0),
+                },
+                GradientStop {
+                    position: 1.,
+                    color: Rgb::new(200, 100, 0),
+                },
+            ],
+        })
+    }
+
+    fn assert_near(value: u8, expected: u8) {
+        assert!(
+            (i16::from(value) - i16::from(expected)).abs() <= 1,
+            "{} != {}",
+            value,
+            expected
+        );
+    }
+
+    #[test]
+    fn linear_gradient() {
+        let colors = gradient(GradientShape::Linear { angle: 0. }).colors(&rects());
+        assert_near(colors[0].r, 0);
+        assert_near(colors[1].r, 200);
+        assert_near(colors[1].g, 100);
+        assert_near(colors[2].r, 100);
+
+        let colors = gradient(GradientShape::Linear { angle: PI / 2. }).colors(&rects());
+        assert_near(colors[0].r, 0);
+        assert_near(colors[2].r, 200);
+    }
+
+    #[test]
+    fn radial_gradient() {
+        let colors = gradient(GradientShape::Radial).colors(&rects());
+        assert_near(colors[0].r, 200);
+        assert_near(colors[2].r, 141);
+    }
+
+    #[test]
+    fn gradient_nan_stop() {
+        let mut gradient = match gradient(GradientShape::Radial) {
+            Paint::Gradient(gradient) => gradient,
+            _ => unreachable!(),
+        };
+        gradient.stops.push(GradientStop {
+            position: f64::NAN,
+            color: Rgb::new(255, 255, 255),
+        });
+        assert_near(gradient.color_at(0.5).r, 100);
+    }
+
+    #[test]
+    fn image() {
+        let red = Rgb::new(255, 0, 0);
+        let blue = Rgb::new(0, 0, 255);
+        let image = PaintImage::new(2, 2, vec![red, blue, blue, blue]).unwrap();
+        let colors = Paint::Image(image).colors(&rects());
+        assert_eq!((colors[0].r, colors[0].b), (255, 0));
+        assert_eq!((colors[1].r, colors[1].b), (0, 255));
+        assert_eq!((colors[2].r, colors[2].b), (0, 255));
+        assert!(PaintImage::new(2, 2, vec![red]).is_err());
+    }
+}
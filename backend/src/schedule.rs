@@ -0,0 +1,268 @@
+use serde::{de, Deserialize, Serialize};
+use std::fmt;
+
+use crate::config::{load_config, save_config};
+use crate::{Board, Rgb, SetError};
+
+const SCHEDULE_FILE: &str = "schedule.json";
+
+/// Time of day, in local time
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TimeOfDay {
+    pub hour: u8,
+    pub minute: u8,
+}
+
+impl TimeOfDay {
+    pub fn new(hour: u8, minute: u8) -> Option<Self> {
+        if hour < 24 && minute < 60 {
+            Some(Self { hour, minute })
+        } else {
+            None
+        }
+    }
+
+    /// Parse a 24 hour time like `20:00`
+    pub fn parse(s: &str) -> Option<Self> {
+        let mut parts = s.splitn(2, ':');
+        let hour = parts.next()?.parse().ok()?;
+        let minute = parts.next()?.parse().ok()?;
+        Self::new(hour, minute)
+    }
+}
+
+impl fmt::Display for TimeOfDay {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:02}:{:02}", self.hour, self.minute)
+    }
+}
+
+impl Serialize for TimeOfDay {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+struct TimeOfDayVisitor;
+
+impl<'de> de::Visitor<'de> for TimeOfDayVisitor {
+    type Value = TimeOfDay;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a 24 hour time like 20:00")
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<TimeOfDay, E> {
+        TimeOfDay::parse(v).ok_or_else(|| E::invalid_value(de::Unexpected::Str(v), &self))
+    }
+}
+
+impl<'de> Deserialize<'de> for TimeOfDay {
+    fn deserialize<D: de::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_str(TimeOfDayVisitor)
+    }
+}
+
+/// Source of the current time, so schedules can be tested
+pub trait Clock {
+    /// The current time, or `None` if it can't be found
+    fn now(&self) -> Option<TimeOfDay>;
+}
+
+/// The system clock, in the local time zone
+pub struct LocalClock;
+
+impl Clock for LocalClock {
+    fn now(&self) -> Option<TimeOfDay> {
+        // Fails if the local time zone can't be loaded
+        let now = glib::DateTime::new_now_local()?;
+        TimeOfDay::new(now.get_hour() as u8, now.get_minute() as u8)
+    }
+}
+
+/// Backlight settings changed at a time of day. Settings that aren't set are
+/// left as they are.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ScheduleRule {
+    pub time: TimeOfDay,
+    /// Percent of the maximum brightness
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub brightness: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub color: Option<Rgb>,
+    /// Turn off the backlight, the same as a brightness of 0
+    #[serde(default)]
+    pub disable: bool,
+}
+
+impl ScheduleRule {
+    /// Set the brightness and color of every layer of `board`
+    pub async fn apply(&self, board: &Board) -> Result<(), SetError> {
+        let brightness = if self.disable {
+            Some(0)
+        } else {
+            self.brightness.map(|percent| {
+                (percent.max(0.).min(100.) / 100. * f64::from(board.max_brightness())).round()
+                    as i32
+            })
+        };
+        for layer in board.layers() {
+            if let Some(brightness) = brightness {
+                layer.set_brightness(brightness).await?;
+            }
+            if let Some(color) = self.color {
+                if board.layout().meta.has_color {
+                    layer.set_color(color.to_hs_lossy()).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Rules run each day by `Scheduler`, saved in the user's config directory
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Schedule {
+    #[serde(default)]
+    pub rules: Vec<ScheduleRule>,
+}
+
+impl Schedule {
+    pub fn load() -> Self {
+        load_config(SCHEDULE_FILE)
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        save_config(SCHEDULE_FILE, self)
+    }
+
+    /// Index of the rule in effect at `time`, which is the latest one at or
+    /// before it, or else the last one from the day before
+    fn active_index(&self, time: TimeOfDay) -> Option<usize> {
+        let before = self
+            .rules
+            .iter()
+            .enumerate()
+            .filter(|(_, rule)| rule.time <= time)
+            .max_by_key(|(i, rule)| (rule.time, *i));
+        let latest = || {
+            self.rules
+                .iter()
+                .enumerate()
+                .max_by_key(|(i, rule)| (rule.time, *i))
+        };
+        before.or_else(latest).map(|(i, _)| i)
+    }
+}
+
+/// Finds the rule of a `Schedule` to apply as time passes
+pub struct Scheduler<C: Clock> {
+    schedule: Schedule,
+    clock: C,
+    applied: Option<usize>,
+}
+
+impl<C: Clock> Scheduler<C> {
+    pub fn new(schedule: Schedule, clock: C) -> Self {
+        Self {
+            schedule,
+            clock,
+            applied: None,
+        }
+    }
+
+    /// Rule in effect now, for applying to a newly connected board
+    pub fn current(&self) -> Option<&ScheduleRule> {
+        let index = self.schedule.active_index(self.clock.now()?)?;
+        Some(&self.schedule.rules[index])
+    }
+
+    /// Rule that has come into effect since the last call, if any. Nothing
+    /// is applied while the time is unknown.
+    pub fn poll(&mut self) -> Option<&ScheduleRule> {
+        let index = self.schedule.active_index(self.clock.now()?)?;
+        if self.applied == Some(index) {
+            return None;
+        }
+        self.applied = Some(index);
+        Some(&self.schedule.rules[index])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{cell::Cell, rc::Rc};
+
+    struct TestClock(Rc<Cell<Option<TimeOfDay>>>);
+
+    impl Clock for TestClock {
+        fn now(&self) -> Option<TimeOfDay> {
+            self.0.get()
+        }
+    }
+
+    fn time(s: &str) -> TimeOfDay {
+        TimeOfDay::parse(s).unwrap()
+    }
+
+    #[test]
+    fn time_of_day() {
+        assert_eq!(time("07:05"), TimeOfDay::new(7, 5).unwrap());
+        assert_eq!(time("7:05").to_string(), "07:05");
+        assert!(TimeOfDay::parse("24:00").is_none());
+        assert!(TimeOfDay::parse("12").is_none());
+    }
+
+    #[test]
+    fn scheduler() {
+        let schedule: Schedule = serde_json::from_str(
+            r##"{"rules": [
+                {"time": "23:00", "disable": true},
+                {"time": "07:00", "brightness": 100},
+                {"time": "20:00", "brightness": 30, "color": "#ffbf00"}
+            ]}"##,
+        )
+        .unwrap();
+
+        let now = Rc::new(Cell::new(Some(time("12:00"))));
+        let mut scheduler = Scheduler::new(schedule, TestClock(now.clone()));
+        assert_eq!(scheduler.poll().unwrap().brightness, Some(100.));
+        assert!(scheduler.poll().is_none());
+
+        now.set(Some(time("19:59")));
+        assert!(scheduler.poll().is_none());
+
+        now.set(Some(time("20:00")));
+        let rule = scheduler.poll().unwrap();
+        assert_eq!(rule.brightness, Some(30.));
+        assert_eq!(rule.color.unwrap().to_string(), "#ffbf00");
+
+        now.set(Some(time("23:30")));
+        assert!(scheduler.poll().unwrap().disable);
+
+        // Before the first rule of the day, the last rule of the day before
+        // is still in effect
+        now.set(Some(time("03:00")));
+        assert!(scheduler.poll().is_none());
+        assert!(scheduler.current().unwrap().disable);
+
+        now.set(Some(time("07:00")));
+        assert_eq!(scheduler.poll().unwrap().brightness, Some(100.));
+
+        // Nothing changes while the time is unknown
+        now.set(None);
+        assert!(scheduler.poll().is_none());
+        assert!(scheduler.current().is_none());
+        now.set(Some(time("07:30")));
+        assert!(scheduler.poll().is_none());
+    }
+
+    #[test]
+    fn empty() {
+        let now = Rc::new(Cell::new(Some(time("12:00"))));
+        let mut scheduler = Scheduler::new(Schedule::default(), TestClock(now));
+        assert!(scheduler.poll().is_none());
+        assert!(scheduler.current().is_none());
+    }
+}
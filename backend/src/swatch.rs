@@ -0,0 +1,123 @@
+use serde::{Deserialize, Serialize};
+
+use crate::config::{load_config, save_config};
+use crate::Rgb;
+
+const PALETTE_FILE: &str = "palette.json";
+
+/// Recently used colors that are remembered
+const MAX_RECENT: usize = 10;
+
+/// Color saved by the user, or imported from a palette file
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Swatch {
+    pub name: String,
+    pub color: Rgb,
+}
+
+/// Colors remembered for the color picker, saved in the user's config
+/// directory
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Palette {
+    /// Most recently used first
+    #[serde(default)]
+    pub recent: Vec<Rgb>,
+    #[serde(default)]
+    pub swatches: Vec<Swatch>,
+}
+
+impl Palette {
+    /// Load the saved palette, or an empty one if it hasn't been saved
+    pub fn load() -> Self {
+        load_config(PALETTE_FILE)
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        save_config(PALETTE_FILE, self)
+    }
+
+    /// Remember `color` as the most recently used
+    pub fn add_recent(&mut self, color: Rgb) {
+        self.recent
+            .retain(|i| (i.r, i.g, i.b) != (color.r, color.g, color.b));
+        self.recent.insert(0, color);
+        self.recent.truncate(MAX_RECENT);
+    }
+
+    /// Add the colors of a GIMP palette (`.gpl`) file as swatches, returning
+    /// how many were added
+    pub fn import_gpl(&mut self, gpl: &str) -> Result<usize, String> {
+        let swatches = parse_gpl(gpl)?;
+        let count = swatches.len();
+        self.swatches.extend(swatches);
+        Ok(count)
+    }
+}
+
+/// Parse the colors of a GIMP palette file, which has a header followed by a
+/// line per color like `255 128 0 Orange`
+pub fn parse_gpl(gpl: &str) -> Result<Vec<Swatch>, String> {
+    let mut lines = gpl.lines();
+    if lines.next().map(str::trim) != Some("GIMP Palette") {
+        return Err("Not a GIMP palette".to_string());
+    }
+
+    let mut swatches = Vec::new();
+    for (number, line) in lines.enumerate() {
+        let line = line.trim();
+        if line.is_empty()
+            || line.starts_with('#')
+            || line.starts_with("Name:")
+            || line.starts_with("Columns:")
+        {
+            continue;
+        }
+
+        let mut parts = line.split_whitespace();
+        let mut channel = || -> Result<u8, String> {
+            parts
+                .next()
+                .and_then(|part| part.parse().ok())
+                .ok_or_else(|| format!("Invalid color on line {}: '{}'", number + 2, line))
+        };
+        let color = Rgb::new(channel()?, channel()?, channel()?);
+        let name = parts.collect::<Vec<_>>().join(" ");
+        let name = if name.is_empty() || name == "Untitled" {
+            color.to_string()
+        } else {
+            name
+        };
+        swatches.push(Swatch { name, color });
+    }
+    Ok(swatches)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gpl() {
+        let gpl = "GIMP Palette\nName: Brand\nColumns: 4\n#\n255 128   0\tSafety Orange\n  0   0 255\tUntitled\n";
+        let swatches = parse_gpl(gpl).unwrap();
+        assert_eq!(swatches.len(), 2);
+        assert_eq!(swatches[0].name, "Safety Orange");
+        assert_eq!(swatches[0].color.to_string(), "#ff8000");
+        assert_eq!(swatches[1].name, "#0000ff");
+
+        assert!(parse_gpl("255 0 0 Red").is_err());
+        assert!(parse_gpl("GIMP Palette\n255 0 Red").is_err());
+    }
+
+    #[test]
+    fn recent() {
+        let mut palette = Palette::default();
+        for i in 0..20 {
+            palette.add_recent(Rgb::new(i, 0, 0));
+        }
+        palette.add_recent(Rgb::new(15, 0, 0));
+        assert_eq!(palette.recent.len(), MAX_RECENT);
+        assert_eq!(palette.recent[0].r, 15);
+        assert_eq!(palette.recent[1].r, 19);
+    }
+}
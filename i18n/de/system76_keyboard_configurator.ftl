@@ -48,10 +48,7 @@ loading-keyboard = Tastaturbelegung und LEDs für {$keyboard} werden geladen
 
 page-electrical = Elektronisch
 page-keycaps = Tastenkappen
-page-layer1 = Schicht 1
-page-layer2 = Schicht 2
-page-layer3 = Schicht 3
-page-layer4 = Schicht 4
+page-layer = Schicht {$num}
 page-leds = LEDs
 page-logical = Logisch
 
@@ -5,29 +5,91 @@ app-title = System76 {-name}
 
 board-fake = {$model}, fake
 
+button-apply = Apply
+button-assign = Assign
 button-cancel = Cancel
 button-configure = Configure Keyboard
 button-disable = Disable
 button-import = Import
+button-paint = Paint…
+button-save = Save
 button-test = Test
-
+button-type = Type
+button-update = Update
+button-update-firmware = Update Firmware…
+
+calibrate-blue = Blue:
+calibrate-desc = Compare the keyboard to white on the screen, and adjust until they look the same. The gray ramp should get evenly brighter from left to right when the gamma is right; it is only visible when the keys are lit per key. Saved calibration is used for all keyboards of this model. To ship it as the default for the model, add the values below to its layout's meta.json.
+calibrate-gamma = Gamma:
+calibrate-green = Green:
+calibrate-pattern = Test pattern:
+calibrate-ramp = Gray ramp
+calibrate-red = Red:
+calibrate-title = Calibrate Colors
+calibrate-white = White
+
+compound-key-alt = Alt
+compound-key-ctrl = Ctrl
+compound-key-key = Key:
+compound-key-layer-tap = Layer {$num} when held, key when tapped
+compound-key-mod-tap = Modifiers when held, key when tapped
+compound-key-mods = Key with modifiers
+compound-key-right = Right-hand
+compound-key-shift = Shift
+compound-key-super = Super
+compound-key-title = Compound key
+
+dummy-board = Board:
+dummy-command = Command:
+dummy-error = Error:
+dummy-error-none = None
+dummy-error-timeout = Timeout
+dummy-error-unsupported = Unsupported
+dummy-input = Input:
+dummy-output = Output:
+dummy-press = Press Key
+dummy-title = Fake Keyboard Controls
+
+effect-breathing = Breathing
+effect-fps = {$fps} FPS
+effect-gradient-sweep = Gradient Sweep
+effect-none = None
+effect-starfield = Starfield
+effect-wave = Wave
+
+error-calibrate = Failed to calibrate colors
+error-daemon-died = Keyboard daemon stopped
 error-disable-key = Failed to disable key
 error-export-keymap = Failed to export keymap
+error-flash = Failed to update firmware
 error-import-keymap = Failed to import keymap
 error-key-led = Failed to key LED
+error-load-image = Failed to load image
 error-open-file = Failed to open file
+error-paint = Failed to paint keys
+error-save-calibration = Failed to save color calibration
 error-save-leds = Failed to save LEDs
+error-schedule = Failed to apply backlight schedule
 error-set-keyboard-brightness = Error setting brightness
 error-set-keyboard-mode = Error setting keyboard mode
 error-set-keymap = Failed to set keymap
 error-set-layer-brightness = Failed to set layer brightness
 error-set-layer-color = Failed to set layer color
 error-set-layer-mode = Failed to set layer mode
+error-set-macro = Failed to set macro
 error-unsupported-keymap = Unsupported keymap file
 error-unsupported-keymap-desc = Keymap file appears to be from newer Configurator version.
 
+firmware-update-available = Firmware version {$version} is available.
 firmware-version = Firmware version {$version} does not support keymap configuration.
 
+flash-desc = Update the firmware of {$model} to version {$version}? Don't unplug the keyboard until the update is done.
+flash-done = Done
+flash-erasing = Erasing…
+flash-title = Update Firmware
+flash-verifying = Verifying…
+flash-writing = Writing…
+
 keyboard-brightness = Brightness:
 keyboard-color = Color:
 
@@ -39,6 +101,8 @@ layer-all-brightness = Brightness (all layers):
 layer-animation-speed = Layer Animation Speed:
 layer-color = Layer Color:
 layer-color-pattern = Layer Color Pattern:
+layer-effect = Effect:
+layer-preview = Preview on Screen:
 layer-saturation = Layer Saturation:
 
 layout-export = Export Layout
@@ -48,21 +112,37 @@ layout-reset = Reset Layout
 loading = Keyboard(s) detected. Loading...
 loading-keyboard = Loading keymap and LEDs for {$keyboard}
 
+macro-events = Events:
+macro-label = Macro {$num}
+macro-text = Text:
+macro-title = Macros
+macro-unsupported = Macros are not supported by this keyboard's firmware.
+
 page-electrical = Electrical
 page-keycaps = Keycaps
-page-layer1 = Layer 1
-page-layer2 = Layer 2
-page-layer3 = Layer 3
-page-layer4 = Layer 4
+page-layer = Layer {$num}
 page-leds = LEDs
 page-logical = Logical
 
+paint-add-stop = Add Stop
+paint-angle = Angle:
+paint-image = Image
+paint-image-file = Image File:
+paint-image-filter = PNG and JPEG images
+paint-linear-gradient = Linear Gradient
+paint-radial-gradient = Radial Gradient
+paint-shape = Paint:
+paint-title = Paint Keys
+
 no-boards = No keyboard detected
 no-boards-msg = Make sure your built-in keyboard has up to date
  System76 Open Firmware.
  If using an external keyboard, make sure it is
  plugged in properly
 
+raw-key-title = Raw keycode
+raw-key-value = Value:
+
 show-help-overlay = Keyboard Shortcuts
 
 stack-keymap = Keymap
@@ -1,13 +1,28 @@
 button-color = Color
 button-cancel = Cancel
+button-import = Import
 button-save = Save
 
 choose-color = Set Color
 
+error-import-palette = Failed to import palette
+error-save-palette = Failed to save palette
 error-set-color = Failed to set keyboard color
 error-set-brightness = Failed to set keyboard brightness
 
+label-blue = B
+label-green = G
+label-hex = Hex
 label-hue = Hue
+label-red = R
 label-saturation = Saturation
+label-value = Brightness
 
-scale-brightness = Brightness
\ No newline at end of file
+palette-filter = GIMP palette
+palette-import = Import Palette…
+palette-recent = Recent
+palette-save-swatch = Save Swatch
+palette-swatch-name = Swatch name
+palette-swatches = Swatches
+
+scale-brightness = Brightness
@@ -45,10 +45,7 @@ loading-keyboard = Chargement du plan de codage et DELs pour {$keyboard}
 
 page-electrical = Électrique
 page-keycaps = Capuchon de touche
-page-layer1 = Couche 1
-page-layer2 = Couche 2
-page-layer3 = Couche 3
-page-layer4 = Couche 4
+page-layer = Couche {$num}
 page-leds = DELs
 page-logical = Logique
 
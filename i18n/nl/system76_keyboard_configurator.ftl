@@ -50,10 +50,7 @@ loading-keyboard = Bezig met laden van indeling en leds van {$keyboard}…
 
 page-electrical = Electrisch
 page-keycaps = Knoppen
-page-layer1 = Laag 1
-page-layer2 = Laag 2
-page-layer3 = Laag 3
-page-layer4 = Laag 4
+page-layer = Laag {$num}
 page-leds = Ledverlichting
 page-logical = Logisch
 
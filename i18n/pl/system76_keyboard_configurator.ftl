@@ -44,10 +44,7 @@ loading-keyboard = Ładowanie mapowania klawiszy i LED dla {$keyboard}
 
 page-electrical = Elektryczny
 page-keycaps = Klawisze klawiatury
-page-layer1 = Warstwa 1
-page-layer2 = Warstwa 2
-page-layer3 = Warstwa 3
-page-layer4 = Warstwa 4
+page-layer = Warstwa {$num}
 page-leds = LED
 page-logical = Logiczny
 
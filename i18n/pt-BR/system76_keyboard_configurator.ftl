@@ -50,10 +50,7 @@ loading-keyboard = Carregando mapa do teclado e LEDs para {$keyboard}
 
 page-electrical = Elétrico
 page-keycaps = Keycaps
-page-layer1 = Camada 1
-page-layer2 = Camada 2
-page-layer3 = Camada 3
-page-layer4 = Camada 4
+page-layer = Camada {$num}
 page-leds = LEDs
 page-logical = Lógico
 
@@ -50,10 +50,7 @@ loading-keyboard = Tuş haritası ve {$keyboard} için LED ayarları yükleniyor
 
 page-electrical = Elektronik
 page-keycaps = Tuş başlıkları
-page-layer1 = Katman 1
-page-layer2 = Katman 2
-page-layer3 = Katman 3
-page-layer4 = Katman 4
+page-layer = Katman {$num}
 page-leds = LEDler
 page-logical = Mantıksal
 
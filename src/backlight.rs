@@ -1,4 +1,4 @@
-use crate::fl;
+use crate::{fl, PaintEditor};
 use cascade::cascade;
 use futures::{prelude::*, stream::FuturesUnordered};
 use glib::clone;
@@ -7,16 +7,20 @@ use gtk::subclass::prelude::*;
 use once_cell::sync::Lazy;
 use std::cell::{Cell, RefCell};
 
-use backend::{Board, DerefCell, Hs, Mode};
-use widgets::{KeyboardColor, KeyboardColorIndex, SelectedKeys};
+use backend::{
+    Board, Breathing, DerefCell, Effect, EffectPlayer, GradientSweep, Hs, Mode, Starfield, Wave,
+};
+use widgets::{KeyboardColor, KeyboardColorIndex, PaletteView, SelectedKeys};
 
 #[derive(Default)]
 pub struct BacklightInner {
     board: DerefCell<Board>,
     disable_color_button: DerefCell<gtk::Button>,
+    paint_button: DerefCell<gtk::Button>,
     keyboard_color: DerefCell<KeyboardColor>,
     color_label: DerefCell<gtk::Label>,
     color_row: DerefCell<gtk::ListBoxRow>,
+    palette_row: DerefCell<gtk::ListBoxRow>,
     brightness_scale: DerefCell<gtk::Scale>,
     brightness_label: DerefCell<gtk::Label>,
     brightness_row: DerefCell<gtk::ListBoxRow>,
@@ -26,8 +30,16 @@ pub struct BacklightInner {
     mode_row: DerefCell<gtk::ListBoxRow>,
     speed_scale: DerefCell<gtk::Scale>,
     speed_row: DerefCell<gtk::ListBoxRow>,
+    effect_combobox: DerefCell<gtk::ComboBoxText>,
+    effect_fps_label: DerefCell<gtk::Label>,
+    effect_row: DerefCell<gtk::ListBoxRow>,
+    effect_player: RefCell<Option<EffectPlayer>>,
+    preview_switch: DerefCell<gtk::Switch>,
+    preview_row: DerefCell<gtk::ListBoxRow>,
     layer: Cell<usize>,
     do_not_set: Cell<bool>,
+    /// Number of settings being written, during which `leds-changed` is ignored
+    changing: Cell<usize>,
     selected: RefCell<SelectedKeys>,
 }
 
@@ -53,6 +65,26 @@ impl ObjectImpl for BacklightInner {
             mode_combobox.append(Some(mode.id), &mode.name);
         }
 
+        let effect_combobox = cascade! {
+            gtk::ComboBoxText::new();
+            ..append(Some("none"), &fl!("effect-none"));
+            ..append(Some("gradient-sweep"), &fl!("effect-gradient-sweep"));
+            ..append(Some("breathing"), &fl!("effect-breathing"));
+            ..append(Some("wave"), &fl!("effect-wave"));
+            ..append(Some("starfield"), &fl!("effect-starfield"));
+            ..set_active_id(Some("none"));
+            ..connect_changed(clone!(@weak obj => move |_|
+                obj.effect_changed();
+            ));
+        };
+
+        let preview_switch = cascade! {
+            gtk::Switch::new();
+            ..connect_property_active_notify(clone!(@weak obj => move |_|
+                obj.notify("preview");
+            ));
+        };
+
         let speed_scale = cascade! {
             gtk::Scale::with_range(gtk::Orientation::Horizontal, 0., 255., 1.);
             ..set_halign(gtk::Align::Fill);
@@ -125,17 +157,43 @@ impl ObjectImpl for BacklightInner {
             ..connect_clicked(clone!(@weak obj => move |_| obj.disable_color_clicked()));
         };
 
+        let paint_button = cascade! {
+            gtk::Button::with_label(&fl!("button-paint"));
+            ..set_no_show_all(true);
+            ..connect_clicked(clone!(@weak obj => move |_| obj.paint_clicked()));
+        };
+
         let color_label = gtk::Label::new(None);
         let brightness_label = gtk::Label::new(Some(&fl!("layer-all-brightness")));
 
         let mode_row = label_row(&fl!("layer-color-pattern"), &mode_combobox);
         let speed_row = label_row(&fl!("layer-animation-speed"), &speed_scale);
+        let effect_fps_label = gtk::Label::new(None);
+        let effect_row = label_row(
+            &fl!("layer-effect"),
+            &cascade! {
+                gtk::Box::new(gtk::Orientation::Horizontal, 8);
+                ..add(&effect_fps_label);
+                ..add(&effect_combobox);
+            },
+        );
+        let preview_row = label_row(&fl!("layer-preview"), &preview_switch);
         let saturation_row = label_row(&fl!("layer-saturation"), &saturation_scale);
         let color_row = row(&cascade! {
             gtk::Box::new(gtk::Orientation::Horizontal, 8);
             ..add(&color_label);
             ..pack_end(&keyboard_color, false, false, 0);
             ..pack_end(&disable_color_button, false, false, 0);
+            ..pack_end(&paint_button, false, false, 0);
+        });
+        // Clicking a saved color paints it on the selected keys
+        let palette_row = row(&cascade! {
+            PaletteView::new();
+            ..connect_color_activated(clone!(@weak obj => move |rgb| {
+                if !obj.inner().selected.borrow().is_empty() {
+                    obj.inner().keyboard_color.set_hsv(rgb.to_hsv());
+                }
+            }));
         });
         let brightness_row = row(&cascade! {
             gtk::Box::new(gtk::Orientation::Horizontal, 8);
@@ -149,15 +207,20 @@ impl ObjectImpl for BacklightInner {
             ..get_style_context().add_class("frame");
             ..add(&mode_row);
             ..add(&speed_row);
+            ..add(&effect_row);
+            ..add(&preview_row);
             ..add(&saturation_row);
             ..add(&color_row);
+            ..add(&palette_row);
             ..add(&brightness_row);
         };
 
         self.disable_color_button.set(disable_color_button);
+        self.paint_button.set(paint_button);
         self.keyboard_color.set(keyboard_color);
         self.color_label.set(color_label);
         self.color_row.set(color_row);
+        self.palette_row.set(palette_row);
         self.brightness_label.set(brightness_label);
         self.brightness_scale.set(brightness_scale);
         self.brightness_row.set(brightness_row);
@@ -165,11 +228,17 @@ impl ObjectImpl for BacklightInner {
         self.mode_row.set(mode_row);
         self.speed_scale.set(speed_scale);
         self.speed_row.set(speed_row);
+        self.effect_combobox.set(effect_combobox);
+        self.effect_fps_label.set(effect_fps_label);
+        self.effect_row.set(effect_row);
+        self.preview_switch.set(preview_switch);
+        self.preview_row.set(preview_row);
         self.saturation_scale.set(saturation_scale);
         self.saturation_row.set(saturation_row);
     }
 
     fn dispose(&self, obj: &Self::Type) {
+        self.effect_player.replace(None);
         obj.led_save();
     }
 
@@ -191,6 +260,13 @@ impl ObjectImpl for BacklightInner {
                     false,
                     glib::ParamFlags::READABLE,
                 ),
+                glib::ParamSpec::boolean(
+                    "preview",
+                    "preview",
+                    "preview",
+                    false,
+                    glib::ParamFlags::READABLE,
+                ),
             ]
         });
 
@@ -218,6 +294,7 @@ impl ObjectImpl for BacklightInner {
         match pspec.get_name() {
             "mode" => obj.mode().id.to_value(),
             "is-per-key" => obj.mode().is_per_key().to_value(),
+            "preview" => obj.inner().preview_switch.get_active().to_value(),
             _ => unimplemented!(),
         }
     }
@@ -235,12 +312,14 @@ glib::wrapper! {
 impl Backlight {
     pub fn new(board: Board) -> Self {
         let max_brightness = board.max_brightness() as f64;
-        let has_led_save = board.has_led_save();
+        let has_led_save = board.capabilities().led_save;
 
         let obj: Self = glib::Object::new(&[]).unwrap();
         obj.inner().board.set(board.clone());
         obj.inner().keyboard_color.set_board(Some(board));
         obj.inner().brightness_scale.set_range(0.0, max_brightness);
+        // Fake boards have no LEDs, so show their modes on screen
+        obj.inner().preview_switch.set_active(obj.board().is_fake());
         obj.invalidate_filter();
         obj.set_layer(0);
         obj.set_filter_func(Some(Box::new(
@@ -258,6 +337,28 @@ impl Backlight {
                 .set_label(&fl!("keyboard-brightness"));
         }
 
+        // Update when changed by hotkeys or another program
+        obj.board()
+            .connect_leds_changed(clone!(@weak obj => move || {
+                if obj.inner().changing.get() == 0 {
+                    obj.set_layer(obj.inner().layer.get());
+                }
+            }));
+
+        // Show how many frames of the effect reach the keyboard
+        glib::timeout_add_seconds_local(
+            1,
+            clone!(@weak obj => @default-return Continue(false), move || {
+                let label = if obj.inner().effect_player.borrow().is_some() {
+                    fl!("effect-fps", fps = obj.board().frames_per_second())
+                } else {
+                    String::new()
+                };
+                obj.inner().effect_fps_label.set_label(&label);
+                Continue(true)
+            }),
+        );
+
         if has_led_save {
             glib::timeout_add_seconds_local(
                 10,
@@ -306,8 +407,14 @@ impl Backlight {
             layout.meta.has_mode
         } else if row == &*inner.speed_row {
             layout.meta.has_mode && self.mode().has_speed
+        } else if row == &*inner.effect_row {
+            layout.meta.has_mode && self.mode().is_per_key()
+        } else if row == &*inner.preview_row {
+            layout.meta.has_mode
         } else if row == &*inner.color_row {
             layout.meta.has_color && (!layout.meta.has_mode || self.mode().has_hue)
+        } else if row == &*inner.palette_row {
+            layout.meta.has_color && self.mode().is_per_key()
         } else if row == &*inner.saturation_row {
             !self.mode().has_hue && !self.mode().is_disabled()
         } else if row == &*inner.brightness_row {
@@ -338,6 +445,12 @@ impl Backlight {
         self.inner()
             .disable_color_button
             .set_visible(self.mode().is_per_key());
+        self.inner()
+            .paint_button
+            .set_visible(self.mode().is_per_key());
+        if !self.mode().is_per_key() {
+            self.inner().effect_combobox.set_active_id(Some("none"));
+        }
         self.invalidate_filter();
 
         if self.inner().do_not_set.get() {
@@ -348,26 +461,59 @@ impl Backlight {
         let speed = self.inner().speed_scale.get_value();
         let mode = self.mode();
         let layer = self.inner().layer.get() as usize;
+        let self_ = self.clone();
+        self.inner().changing.set(self.inner().changing.get() + 1);
         glib::MainContext::default().spawn_local(async move {
             let layer = &board.layers()[layer];
             if let Err(err) = layer.set_mode(mode, speed as u8).await {
-                error!("{}: {}", fl!("error-set-keyboard-mode"), err);
+                if !err.is_superseded() {
+                    error!("{}: {}", fl!("error-set-keyboard-mode"), err);
+                }
             }
+            self_.inner().changing.set(self_.inner().changing.get() - 1);
         });
     }
 
+    /// Play the effect chosen in the combobox, in the color of the layer
+    fn effect_changed(&self) {
+        // Stop the old effect before starting the new one
+        let old_player = self.inner().effect_player.take();
+
+        let color = self.board().layers()[self.inner().layer.get()].color();
+        let id = self.inner().effect_combobox.get_active_id();
+        let effect: Box<dyn Effect> = match id.as_deref() {
+            Some("gradient-sweep") => Box::new(GradientSweep::default()),
+            Some("breathing") => Box::new(Breathing::new(color)),
+            Some("wave") => Box::new(Wave::new(color)),
+            Some("starfield") => Box::new(Starfield::new(color)),
+            _ => return,
+        };
+        info!("Playing effect {:?}", id);
+        // Wait for the old effect to stop, without restoring colors in between
+        let player = match old_player {
+            Some(old_player) => old_player.replace(effect),
+            None => EffectPlayer::new(self.board(), effect),
+        };
+        self.inner().effect_player.replace(Some(player));
+    }
+
     fn brightness_changed(&self) {
         if self.inner().do_not_set.get() {
             return;
         }
         let value = self.inner().brightness_scale.get_value() as i32;
         let board = self.board().clone();
+        let self_ = self.clone();
+        self.inner().changing.set(self.inner().changing.get() + 1);
         glib::MainContext::default().spawn_local(async move {
             for layer in board.layers() {
                 if let Err(err) = layer.set_brightness(value).await {
-                    error!("{}: {}", fl!("error-set-keyboard-brightness"), err);
+                    if !err.is_superseded() {
+                        error!("{}: {}", fl!("error-set-keyboard-brightness"), err);
+                    }
                 }
             }
+            self_.inner().changing.set(self_.inner().changing.get() - 1);
         });
         debug!("Brightness: {}", value)
     }
@@ -415,6 +561,39 @@ impl Backlight {
             .set_sensitive(!selected.is_empty());
     }
 
+    /// Show a dialog to paint an image or gradient onto the keys
+    fn paint_clicked(&self) {
+        let window = self
+            .get_toplevel()
+            .and_then(|x| x.downcast::<gtk::Window>().ok());
+        let editor = PaintEditor::new(self.board());
+        let dialog = cascade! {
+            gtk::Dialog::with_buttons(Some(&fl!("paint-title")), window.as_ref(), gtk::DialogFlags::MODAL | gtk::DialogFlags::USE_HEADER_BAR, &[(&fl!("button-cancel"), gtk::ResponseType::Cancel), (&fl!("button-apply"), gtk::ResponseType::Apply)]);
+            ..set_default_response(gtk::ResponseType::Apply);
+        };
+        dialog.connect_response(
+            clone!(@weak self as self_, @weak editor => move |dialog, response| {
+                if response == gtk::ResponseType::Apply {
+                    if let Some(paint) = editor.paint() {
+                        let self_ = self_.clone();
+                        glib::MainContext::default().spawn_local(async move {
+                            if let Err(err) = self_.board().apply_paint(&paint).await {
+                                error!("{}: {}", fl!("error-paint"), err);
+                            }
+                            self_.update_per_key();
+                        });
+                    }
+                }
+                dialog.close();
+            }),
+        );
+
+        let content = dialog.get_content_area();
+        content.add(&editor);
+        content.set_property_margin(24);
+        dialog.show_all();
+    }
+
     fn disable_color_clicked(&self) {
         let self_ = self.clone();
         let selected = self.inner().selected.borrow().clone();
@@ -431,7 +610,7 @@ impl Backlight {
     }
 
     fn led_save(&self) {
-        if self.board().has_led_save() {
+        if self.board().capabilities().led_save {
             let board = self.board().clone();
             glib::MainContext::default().spawn_local(async move {
                 if let Err(err) = board.led_save().await {
@@ -0,0 +1,172 @@
+use glib::clone;
+use std::{cell::RefCell, process, rc::Rc, time::Duration};
+
+use crate::fl;
+use backend::{
+    layer_brightness, set_layer_brightness, Backend, Board, IdleChange, IdleConfig, IdleDimmer,
+    IdleSource, LocalClock, MatrixIdleSource, Schedule, ScheduleRule, Scheduler,
+};
+
+/// How often to check for rules coming into effect, and for new keyboards
+const POLL_SECONDS: u32 = 10;
+
+/// How often to check if the user has gone idle, or come back
+const IDLE_POLL_MILLIS: u32 = 250;
+
+/// How often to read the matrix for key presses, without an idle monitor.
+/// Each read is a command to the keyboard, so this is only often enough to
+/// notice typing within a few seconds.
+const MATRIX_POLL_MILLIS: u64 = 1000;
+
+fn apply_rule(rule: ScheduleRule, boards: Vec<Board>) {
+    info!("Applying backlight schedule rule for {}", rule.time);
+    glib::MainContext::default().spawn_local(async move {
+        for board in boards {
+            if let Err(err) = rule.apply(&board).await {
+                error!("{}: {}", fl!("error-schedule"), err);
+            }
+        }
+    });
+}
+
+fn set_brightness(boards: Vec<(Board, Vec<i32>)>, percent: f64) {
+    glib::MainContext::default().spawn_local(async move {
+        for (board, brightness) in boards {
+            if let Err(err) = set_layer_brightness(&board, &brightness, percent).await {
+                error!("{}: {}", fl!("error-set-keyboard-brightness"), err);
+            }
+        }
+    });
+}
+
+/// The agent runs at login, so it only prompts for root with pkexec when
+/// `allow_pkexec` is set. Otherwise it uses the keyboards system76-power can
+/// control, without external keyboards like the Launch.
+#[cfg(target_os = "linux")]
+fn agent_daemon(allow_pkexec: bool) -> Result<Backend, String> {
+    if unsafe { libc::geteuid() == 0 } {
+        info!("Already running as root");
+        Backend::new()
+    } else if allow_pkexec {
+        info!("Not running as root, spawning daemon with pkexec");
+        Backend::new_pkexec()
+    } else {
+        info!("Not running as root, using system76-power");
+        Backend::new_s76power()
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn agent_daemon(_allow_pkexec: bool) -> Result<Backend, String> {
+    Backend::new()
+}
+
+/// The desktop's idle time if there is an idle monitor, or else key presses
+/// read from the keyboards
+fn idle_source() -> Option<Box<dyn IdleSource>> {
+    #[cfg(target_os = "linux")]
+    match backend::SessionIdleSource::new() {
+        Ok(source) => return Some(Box::new(source)),
+        Err(err) => info!("No idle monitor, reading key presses instead: {}", err),
+    }
+    None
+}
+
+/// Apply the rules of the saved `Schedule` to every keyboard as the day
+/// passes, and dim keyboards while idle, without showing a window. Run with
+/// `--backlight-agent`, adding `--pkexec` to run the daemon as root.
+pub fn run_backlight_agent(allow_pkexec: bool) -> ! {
+    let schedule = Schedule::load();
+    let idle_config = IdleConfig::load();
+    if schedule.rules.is_empty() && !idle_config.is_enabled() {
+        info!("No backlight schedule rules or idle timeout");
+        process::exit(0);
+    }
+
+    let mut scheduler = Scheduler::new(schedule, LocalClock);
+    // Boards get the current rule when they are added
+    scheduler.poll();
+    let scheduler = Rc::new(RefCell::new(scheduler));
+
+    let boards = Rc::new(RefCell::new(Vec::<Board>::new()));
+    let backend = match agent_daemon(allow_pkexec) {
+        Ok(backend) => backend,
+        Err(err) => {
+            error!("Failed to start keyboard daemon: {}", err);
+            process::exit(1);
+        }
+    };
+
+    let mut matrix_source = None;
+    let dimmer = if idle_config.is_enabled() {
+        let source = idle_source().unwrap_or_else(|| {
+            let source = MatrixIdleSource::new();
+            backend.set_matrix_get_rate(Some(Duration::from_millis(MATRIX_POLL_MILLIS)));
+            matrix_source = Some(source.clone());
+            Box::new(source)
+        });
+        Some(IdleDimmer::new(idle_config, source))
+    } else {
+        None
+    };
+    let dimmer = Rc::new(RefCell::new(dimmer));
+
+    if dimmer.borrow().is_some() {
+        // Brightness of each board from before it was dimmed
+        let dimmed = RefCell::new(Vec::new());
+        glib::timeout_add_local(
+            IDLE_POLL_MILLIS,
+            clone!(@strong dimmer, @strong boards => move || {
+                let mut dimmer = dimmer.borrow_mut();
+                let dimmer = dimmer.as_mut().unwrap();
+                match dimmer.poll() {
+                    Some(IdleChange::Dim) => {
+                        let brightness = boards
+                            .borrow()
+                            .iter()
+                            .map(|board| (board.clone(), layer_brightness(board)))
+                            .collect::<Vec<_>>();
+                        set_brightness(brightness.clone(), dimmer.config().brightness);
+                        dimmed.replace(brightness);
+                    }
+                    Some(IdleChange::Restore) => set_brightness(dimmed.take(), 100.),
+                    None => {}
+                }
+                glib::Continue(true)
+            }),
+        );
+    }
+
+    backend.connect_board_added(clone!(@strong scheduler, @strong boards => move |board| {
+        if let Some(source) = &matrix_source {
+            source.watch(&board);
+        }
+        if let Some(rule) = scheduler.borrow().current() {
+            apply_rule(rule.clone(), vec![board.clone()]);
+        }
+        boards.borrow_mut().push(board);
+    }));
+    backend.connect_board_removed(clone!(@strong boards => move |board| {
+        boards.borrow_mut().retain(|i| i != &board);
+    }));
+    backend.refresh();
+
+    glib::timeout_add_seconds_local(POLL_SECONDS, move || {
+        backend.refresh();
+        // Rules are applied after the user comes back, so they aren't undone
+        // by restoring the brightness
+        let is_dimmed = dimmer
+            .borrow()
+            .as_ref()
+            .map_or(false, IdleDimmer::is_dimmed);
+        if !is_dimmed {
+            if let Some(rule) = scheduler.borrow_mut().poll() {
+                apply_rule(rule.clone(), boards.borrow().clone());
+            }
+        }
+        glib::Continue(true)
+    });
+
+    glib::MainLoop::new(None, false).run();
+    process::exit(0)
+}
@@ -0,0 +1,196 @@
+use cascade::cascade;
+use glib::clone;
+use gtk::prelude::*;
+use std::{cell::RefCell, rc::Rc};
+
+use crate::fl;
+use backend::{effect_keys, Board, Calibration, Hs, Hsv};
+
+/// Steps of the gray ramp, which look evenly spaced when gamma is correct
+const RAMP_STEPS: f64 = 5.;
+
+/// Colors shown on the keyboard to compare with the screen
+fn show_pattern(board: &Board, pattern: &str) {
+    let board = board.clone();
+    let pattern = pattern.to_string();
+    glib::MainContext::default().spawn_local(async move {
+        let keys = board
+            .keys()
+            .iter()
+            .filter(|key| !key.leds.is_empty())
+            .collect::<Vec<_>>();
+        let positions = effect_keys(&keys.iter().map(|key| key.physical).collect::<Vec<_>>());
+        for layer in board.layers() {
+            if let Err(err) = layer.set_color(Hs::new(0., 0.)).await {
+                error!("{}: {}", fl!("error-set-layer-color"), err);
+            }
+        }
+        for (key, position) in keys.iter().zip(positions) {
+            let value = if pattern == "ramp" {
+                ((position.x * RAMP_STEPS).floor() + 1.).min(RAMP_STEPS) / RAMP_STEPS
+            } else {
+                1.
+            };
+            if let Err(err) = key.set_color(Some(Hsv::new(0., 0., value))).await {
+                error!("{}: {}", fl!("error-key-led"), err);
+            }
+        }
+    });
+}
+
+/// Dialog adjusting the `Calibration` of a board, while showing white or a
+/// gray ramp on the keyboard
+pub fn show_calibration_dialog<W: IsA<gtk::Window>>(parent: &W, board: &Board) {
+    let original_calibration = board.calibration();
+    let original_layers = board
+        .layers()
+        .iter()
+        .map(|layer| layer.color())
+        .collect::<Vec<_>>();
+    let original_keys = board
+        .keys()
+        .iter()
+        .map(|key| key.color())
+        .collect::<Vec<_>>();
+    board.block_led_save();
+
+    let scale = |min: f64, max: f64, step: f64, value: f64| {
+        cascade! {
+            gtk::Scale::with_range(gtk::Orientation::Horizontal, min, max, step);
+            ..set_value(value);
+            ..set_hexpand(true);
+            ..set_value_pos(gtk::PositionType::Right);
+            ..set_size_request(200, 0);
+        }
+    };
+    let gamma_scale = scale(0.5, 3., 0.05, original_calibration.gamma);
+    let (r, g, b) = original_calibration.gains;
+    let red_scale = scale(0., 100., 1., r * 100.);
+    let green_scale = scale(0., 100., 1., g * 100.);
+    let blue_scale = scale(0., 100., 1., b * 100.);
+
+    let json_label = cascade! {
+        gtk::Label::new(None);
+        ..set_selectable(true);
+        ..set_line_wrap(true);
+        ..set_halign(gtk::Align::Start);
+    };
+
+    let calibration = Rc::new(
+        clone!(@weak gamma_scale, @weak red_scale, @weak green_scale, @weak blue_scale => @default-return original_calibration, move || {
+            // Rounded to the steps of the scales, to keep the saved json short
+            let percent = |scale: &gtk::Scale| scale.get_value().round() / 100.;
+            Calibration {
+                gamma: (gamma_scale.get_value() * 100.).round() / 100.,
+                gains: (percent(&red_scale), percent(&green_scale), percent(&blue_scale)),
+            }
+        }),
+    );
+
+    let changed = Rc::new(
+        clone!(@strong board, @strong calibration, @weak json_label => move || {
+            let calibration = calibration();
+            let json = serde_json::to_string(&calibration).unwrap();
+            json_label.set_label(&format!("\"calibration\": {}", json));
+            let board = board.clone();
+            glib::MainContext::default().spawn_local(async move {
+                if let Err(err) = board.set_calibration(calibration).await {
+                    if !err.is_superseded() {
+                        error!("{}: {}", fl!("error-calibrate"), err);
+                    }
+                }
+            });
+        }),
+    );
+    for widget in &[&gamma_scale, &red_scale, &green_scale, &blue_scale] {
+        widget.connect_value_changed(clone!(@strong changed => move |_| changed()));
+    }
+
+    let pattern_combobox = cascade! {
+        gtk::ComboBoxText::new();
+        ..append(Some("white"), &fl!("calibrate-white"));
+        ..append(Some("ramp"), &fl!("calibrate-ramp"));
+        ..set_active_id(Some("white"));
+        ..connect_changed(clone!(@strong board => move |combobox| {
+            if let Some(id) = combobox.get_active_id() {
+                show_pattern(&board, &id);
+            }
+        }));
+    };
+
+    fn row(label: &str, widget: &impl IsA<gtk::Widget>) -> gtk::Box {
+        cascade! {
+            gtk::Box::new(gtk::Orientation::Horizontal, 8);
+            ..add(&gtk::Label::new(Some(label)));
+            ..pack_end(widget, false, false, 0);
+        }
+    }
+
+    let vbox = cascade! {
+        gtk::Box::new(gtk::Orientation::Vertical, 12);
+        ..set_property_margin(24);
+        ..add(&cascade! {
+            gtk::Label::new(Some(&fl!("calibrate-desc")));
+            ..set_line_wrap(true);
+            ..set_max_width_chars(60);
+            ..set_halign(gtk::Align::Start);
+        });
+        ..add(&row(&fl!("calibrate-pattern"), &pattern_combobox));
+        ..add(&row(&fl!("calibrate-gamma"), &gamma_scale));
+        ..add(&row(&fl!("calibrate-red"), &red_scale));
+        ..add(&row(&fl!("calibrate-green"), &green_scale));
+        ..add(&row(&fl!("calibrate-blue"), &blue_scale));
+        ..add(&json_label);
+    };
+
+    let dialog = cascade! {
+        gtk::Dialog::with_buttons(Some(&fl!("calibrate-title")), Some(parent), gtk::DialogFlags::MODAL | gtk::DialogFlags::USE_HEADER_BAR, &[(&fl!("button-cancel"), gtk::ResponseType::Cancel), (&fl!("button-save"), gtk::ResponseType::Ok)]);
+        ..get_content_area().add(&vbox);
+    };
+
+    let board_removed = RefCell::new(Some(
+        board.connect_removed(clone!(@weak dialog => move || dialog.close())),
+    ));
+
+    dialog.connect_response(clone!(@strong board => move |dialog, response| {
+        let calibration = if response == gtk::ResponseType::Ok {
+            let calibration = calibration();
+            if let Err(err) = calibration.save(board.model()) {
+                error!("{}: {}", fl!("error-save-calibration"), err);
+            }
+            calibration
+        } else {
+            original_calibration
+        };
+        let board = board.clone();
+        let original_layers = original_layers.clone();
+        let original_keys = original_keys.clone();
+        glib::MainContext::default().spawn_local(async move {
+            if let Err(err) = board.set_calibration(calibration).await {
+                error!("{}: {}", fl!("error-calibrate"), err);
+            }
+            // Put back the colors replaced by the pattern
+            for (layer, color) in board.layers().iter().zip(original_layers) {
+                if let Err(err) = layer.set_color(color).await {
+                    error!("{}: {}", fl!("error-set-layer-color"), err);
+                }
+            }
+            for (key, color) in board.keys().iter().zip(original_keys) {
+                if !key.leds.is_empty() {
+                    if let Err(err) = key.set_color(color).await {
+                        error!("{}: {}", fl!("error-key-led"), err);
+                    }
+                }
+            }
+            board.unblock_led_save();
+        });
+        if let Some(id) = board_removed.take() {
+            board.disconnect(id);
+        }
+        dialog.close();
+    }));
+
+    changed();
+    show_pattern(board, "white");
+    dialog.show_all();
+}
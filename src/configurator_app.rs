@@ -1,14 +1,20 @@
 use cascade::cascade;
 use gtk::prelude::*;
 use gtk::subclass::prelude::*;
-use std::cell::Cell;
+use std::{
+    cell::Cell,
+    path::{Path, PathBuf},
+};
 
-use crate::{about_dialog, fl, MainWindow, Page};
+use crate::{about_dialog, fl, MainWindow};
 use backend::DerefCell;
 
 #[derive(Default)]
 pub struct ConfiguratorAppInner {
     phony_board_names: DerefCell<Vec<String>>,
+    phony_state_file: DerefCell<Option<PathBuf>>,
+    record_session: DerefCell<Option<PathBuf>>,
+    replay_session: DerefCell<Option<PathBuf>>,
     debug_layers: Cell<bool>,
     launch_test: Cell<bool>,
 }
@@ -34,6 +40,30 @@ impl ObjectImpl for ConfiguratorAppInner {
             "",
             None,
         );
+        app.add_main_option(
+            "fake-keyboard-state",
+            glib::Char::new('\0').unwrap(),
+            glib::OptionFlags::NONE,
+            glib::OptionArg::String,
+            "",
+            None,
+        );
+        app.add_main_option(
+            "record-session",
+            glib::Char::new('\0').unwrap(),
+            glib::OptionFlags::NONE,
+            glib::OptionArg::String,
+            "",
+            None,
+        );
+        app.add_main_option(
+            "replay-session",
+            glib::Char::new('\0').unwrap(),
+            glib::OptionFlags::NONE,
+            glib::OptionArg::String,
+            "",
+            None,
+        );
         app.add_main_option(
             "debug-layers",
             glib::Char::new('\0').unwrap(),
@@ -68,6 +98,21 @@ impl ApplicationImpl for ConfiguratorAppInner {
         };
 
         self.phony_board_names.set(board_names);
+        self.phony_state_file.set(
+            opts.lookup_value("fake-keyboard-state", None)
+                .and_then(|opt| opt.get::<String>())
+                .map(PathBuf::from),
+        );
+        self.record_session.set(
+            opts.lookup_value("record-session", None)
+                .and_then(|opt| opt.get::<String>())
+                .map(PathBuf::from),
+        );
+        self.replay_session.set(
+            opts.lookup_value("replay-session", None)
+                .and_then(|opt| opt.get::<String>())
+                .map(PathBuf::from),
+        );
         self.debug_layers.set(opts.contains("debug-layers"));
         self.launch_test.set(opts.contains("launch-test"));
         -1
@@ -84,7 +129,8 @@ impl ApplicationImpl for ConfiguratorAppInner {
         app.add_action(&about_action);
         app.set_accels_for_action("kbd.import", &["<Primary>o"]);
         app.set_accels_for_action("kbd.export", &["<Primary>e"]);
-        for (i, _) in Page::iter_all().enumerate() {
+        // Only the first nine pages have a shortcut
+        for i in 0..9 {
             app.set_accels_for_action(&format!("kbd.page{}", i), &[&format!("<Primary>{}", i + 1)]);
         }
     }
@@ -122,6 +168,20 @@ impl ConfiguratorApp {
         &self.inner().phony_board_names
     }
 
+    pub fn phony_state_file(&self) -> Option<&Path> {
+        self.inner().phony_state_file.as_deref()
+    }
+
+    /// File to record the daemon session to, for `replay_session`
+    pub fn record_session(&self) -> Option<&Path> {
+        self.inner().record_session.as_deref()
+    }
+
+    /// Recorded session to play back instead of using real keyboards
+    pub fn replay_session(&self) -> Option<&Path> {
+        self.inner().replay_session.as_deref()
+    }
+
     pub fn debug_layers(&self) -> bool {
         self.inner().debug_layers.get()
     }
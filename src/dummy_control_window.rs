@@ -0,0 +1,110 @@
+use cascade::cascade;
+use glib::clone;
+use gtk::prelude::*;
+
+use crate::fl;
+use backend::{DummyControl, DummyError};
+
+/// Window for pressing keys of fake keyboards, and making their commands
+/// fail, to test the configurator without hardware. Shown for
+/// `--fake-keyboard`.
+pub fn show_dummy_control_window<W: IsA<gtk::Window>>(
+    parent: &W,
+    control: &DummyControl,
+    boards: usize,
+) {
+    let spin = |max: usize| {
+        cascade! {
+            gtk::SpinButton::with_range(0., max as f64, 1.);
+            ..set_digits(0);
+        }
+    };
+    let board_spin = spin(boards.saturating_sub(1));
+    let output_spin = spin(u8::MAX.into());
+    let input_spin = spin(u8::MAX.into());
+
+    let position = clone!(@weak board_spin, @weak output_spin, @weak input_spin => @default-return (0, 0, 0), move || {
+        (
+            board_spin.get_value_as_int() as usize,
+            output_spin.get_value_as_int() as u8,
+            input_spin.get_value_as_int() as u8,
+        )
+    });
+    let press_button = cascade! {
+        gtk::ToggleButton::with_label(&fl!("dummy-press"));
+        ..connect_toggled(clone!(@strong control, @strong position => move |button| {
+            let (board, output, input) = position();
+            if button.get_active() {
+                control.press(board, output, input);
+            } else {
+                control.release(board, output, input);
+            }
+        }));
+    };
+    // Release the key before moving to another, so it isn't held forever
+    for spin in &[&board_spin, &output_spin, &input_spin] {
+        spin.connect_value_changed(clone!(@weak press_button => move |_| {
+            press_button.set_active(false);
+        }));
+    }
+
+    let command_combobox = gtk::ComboBoxText::new();
+    for command in DummyControl::commands() {
+        command_combobox.append(Some(command), command);
+    }
+    command_combobox.set_active(Some(0));
+
+    let error_combobox = cascade! {
+        gtk::ComboBoxText::new();
+        ..append(Some("none"), &fl!("dummy-error-none"));
+        ..append(Some("timeout"), &fl!("dummy-error-timeout"));
+        ..append(Some("unsupported"), &fl!("dummy-error-unsupported"));
+        ..set_active_id(Some("none"));
+        ..connect_changed(clone!(@strong control, @weak command_combobox => move |combobox| {
+            let command = match command_combobox.get_active_id() {
+                Some(command) => command,
+                None => return,
+            };
+            let error = match combobox.get_active_id().as_deref() {
+                Some("timeout") => Some(DummyError::Timeout),
+                Some("unsupported") => Some(DummyError::Unsupported),
+                _ => None,
+            };
+            control.set_error(&command, error);
+        }));
+    };
+    // Errors are remembered per command, so start from no error when
+    // switching commands
+    command_combobox.connect_changed(clone!(@weak error_combobox => move |_| {
+        error_combobox.set_active_id(Some("none"));
+    }));
+
+    fn row(label: &str, widget: &impl IsA<gtk::Widget>) -> gtk::Box {
+        cascade! {
+            gtk::Box::new(gtk::Orientation::Horizontal, 8);
+            ..add(&gtk::Label::new(Some(label)));
+            ..pack_end(widget, false, false, 0);
+        }
+    }
+
+    let vbox = cascade! {
+        gtk::Box::new(gtk::Orientation::Vertical, 12);
+        ..set_property_margin(24);
+        ..add(&row(&fl!("dummy-board"), &board_spin));
+        ..add(&row(&fl!("dummy-output"), &output_spin));
+        ..add(&row(&fl!("dummy-input"), &input_spin));
+        ..add(&press_button);
+        ..add(&gtk::Separator::new(gtk::Orientation::Horizontal));
+        ..add(&row(&fl!("dummy-command"), &command_combobox));
+        ..add(&row(&fl!("dummy-error"), &error_combobox));
+    };
+
+    cascade! {
+        gtk::Window::new(gtk::WindowType::Toplevel);
+        ..set_title(&fl!("dummy-title"));
+        ..set_transient_for(Some(parent));
+        ..set_destroy_with_parent(true);
+        ..add(&vbox);
+        ..show_all();
+    };
+}
@@ -0,0 +1,99 @@
+use cascade::cascade;
+use glib::clone;
+use gtk::prelude::*;
+use std::{cell::Cell, rc::Rc};
+
+use crate::{fl, show_error_dialog};
+use backend::{Board, Firmware, FlashProgress};
+
+/// Text and fraction of the progress bar for a stage of flashing. Writing and
+/// verifying each take half of the bar.
+fn progress_text(progress: FlashProgress) -> (String, f64) {
+    let fraction = |done: usize, total: usize| done as f64 / total.max(1) as f64;
+    match progress {
+        FlashProgress::Erasing => (fl!("flash-erasing"), 0.),
+        FlashProgress::Writing(done, total) => (fl!("flash-writing"), fraction(done, total) / 2.),
+        FlashProgress::Verifying(done, total) => {
+            (fl!("flash-verifying"), 0.5 + fraction(done, total) / 2.)
+        }
+        FlashProgress::Done => (fl!("flash-done"), 1.),
+    }
+}
+
+/// Dialog confirming a firmware update, and showing its progress
+pub fn show_flash_dialog<W: IsA<gtk::Window>>(parent: &W, board: &Board, firmware: Firmware) {
+    let label = cascade! {
+        gtk::Label::new(Some(&fl!(
+            "flash-desc",
+            model = board.model(),
+            version = firmware.metadata.version.as_str()
+        )));
+        ..set_line_wrap(true);
+        ..set_max_width_chars(60);
+        ..set_halign(gtk::Align::Start);
+    };
+
+    let progress_bar = cascade! {
+        gtk::ProgressBar::new();
+        ..set_show_text(true);
+        ..set_no_show_all(true);
+    };
+
+    let vbox = cascade! {
+        gtk::Box::new(gtk::Orientation::Vertical, 12);
+        ..set_property_margin(24);
+        ..add(&label);
+        ..add(&progress_bar);
+    };
+
+    let dialog = cascade! {
+        gtk::Dialog::with_buttons(Some(&fl!("flash-title")), Some(parent), gtk::DialogFlags::MODAL | gtk::DialogFlags::USE_HEADER_BAR, &[(&fl!("button-cancel"), gtk::ResponseType::Cancel), (&fl!("button-update"), gtk::ResponseType::Ok)]);
+        ..get_content_area().add(&vbox);
+    };
+
+    // Interrupting flashing would leave the keyboard in its bootloader
+    let flashing = Rc::new(Cell::new(false));
+    dialog.connect_delete_event(clone!(@strong flashing => move |_, _| {
+        Inhibit(flashing.get())
+    }));
+
+    let parent: gtk::Window = parent.clone().upcast();
+    let board = board.clone();
+    dialog.connect_response(move |dialog, response| {
+        if flashing.get() {
+            return;
+        }
+        if response != gtk::ResponseType::Ok {
+            dialog.close();
+            return;
+        }
+
+        flashing.set(true);
+        dialog.set_response_sensitive(gtk::ResponseType::Ok, false);
+        dialog.set_response_sensitive(gtk::ResponseType::Cancel, false);
+        progress_bar.show();
+
+        let board = board.clone();
+        let firmware = firmware.clone();
+        let flashing = flashing.clone();
+        let dialog = dialog.clone();
+        let parent = parent.clone();
+        let progress_bar = progress_bar.clone();
+        glib::MainContext::default().spawn_local(async move {
+            let res = board
+                .flash(&firmware, |progress| {
+                    let (text, fraction) = progress_text(progress);
+                    progress_bar.set_text(Some(&text));
+                    progress_bar.set_fraction(fraction);
+                })
+                .await;
+            flashing.set(false);
+            dialog.close();
+            if let Err(err) = res {
+                show_error_dialog(&parent, &fl!("error-flash"), err);
+            }
+        });
+    });
+
+    dialog.show_all();
+}
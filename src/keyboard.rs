@@ -13,8 +13,11 @@ use std::{
     str,
 };
 
-use crate::{show_error_dialog, Backlight, KeyboardLayer, MainWindow, Page, Picker, Testing};
-use backend::{Board, DerefCell, KeyMap, Layout, Mode};
+use crate::{
+    show_calibration_dialog, show_error_dialog, Backlight, CompoundKeyEditor, KeyboardLayer,
+    MacroEditor, MainWindow, Page, Picker, RawKeyEditor, Testing,
+};
+use backend::{raw_scancode_name, Board, DerefCell, KeyMap, Keycode, Layout, Mode};
 use widgets::SelectedKeys;
 
 #[derive(Default)]
@@ -27,6 +30,9 @@ pub struct KeyboardInner {
     layer_stack: DerefCell<gtk::Stack>,
     stack: DerefCell<gtk::Stack>,
     picker_box: DerefCell<gtk::Box>,
+    compound_key_editor: DerefCell<Option<CompoundKeyEditor>>,
+    macro_editor: DerefCell<Option<MacroEditor>>,
+    raw_key_editor: DerefCell<RawKeyEditor>,
     backlight: DerefCell<Backlight>,
     testing: DerefCell<Option<Testing>>,
 }
@@ -53,7 +59,7 @@ impl ObjectImpl for KeyboardInner {
 
                     debug!("{:?}", page);
                     let last_layer = keyboard.layer();
-                    keyboard.inner().page.set(page.unwrap_or(Page::Layer1));
+                    keyboard.inner().page.set(page.unwrap_or_default());
                     let layer = keyboard.layer();
                     if layer != last_layer {
                         keyboard.set_selected(keyboard.selected());
@@ -110,6 +116,14 @@ impl ObjectImpl for KeyboardInner {
                     keyboard.reset();
                 ));
             });
+            ..add_action(&cascade! {
+                gio::SimpleAction::new("calibrate", None);
+                ..connect_activate(clone!(@weak keyboard => move |_, _|
+                    if let Some(window) = keyboard.window() {
+                        show_calibration_dialog(&window, keyboard.board());
+                    }
+                ));
+            });
         };
 
         self.action_group.set(action_group);
@@ -185,20 +199,72 @@ impl Keyboard {
             keyboard.inner().testing.set(None);
         }
 
-        stack.add_titled(
-            &cascade! {
-                gtk::Box::new(gtk::Orientation::Vertical, 32);
-                ..add(&cascade! {
-                    gtk::Label::new(Some(&fl!("stack-keymap-desc")));
-                    ..set_line_wrap(true);
-                    ..set_max_width_chars(100);
-                    ..set_halign(gtk::Align::Center);
-                });
-                ..add(&*keyboard.inner().picker_box);
-            },
-            "keymap",
-            &fl!("stack-keymap"),
-        );
+        let keymap_box = cascade! {
+            gtk::Box::new(gtk::Orientation::Vertical, 32);
+            ..add(&cascade! {
+                gtk::Label::new(Some(&fl!("stack-keymap-desc")));
+                ..set_line_wrap(true);
+                ..set_max_width_chars(100);
+                ..set_halign(gtk::Align::Center);
+            });
+            ..add(&*keyboard.inner().picker_box);
+        };
+        stack.add_titled(&keymap_box, "keymap", &fl!("stack-keymap"));
+
+        // Compound keys are only supported by QMK
+        if board.layout().meta.is_qmk {
+            let compound_key_editor = cascade! {
+                CompoundKeyEditor::new(&board);
+                ..set_sensitive(false);
+                ..connect_assign(clone!(@weak keyboard => move || {
+                    if let Some(editor) = &*keyboard.inner().compound_key_editor {
+                        if let Some(keycode) = editor.keycode() {
+                            keyboard.assign(keycode.to_string());
+                        }
+                    }
+                }));
+            };
+            keymap_box.add(&compound_key_editor);
+            keyboard
+                .inner()
+                .compound_key_editor
+                .set(Some(compound_key_editor));
+        } else {
+            keyboard.inner().compound_key_editor.set(None);
+        }
+
+        if board.macros().is_empty() {
+            keymap_box.add(&cascade! {
+                gtk::Label::new(Some(&fl!("macro-unsupported")));
+                ..set_halign(gtk::Align::Center);
+            });
+            keyboard.inner().macro_editor.set(None);
+        } else {
+            let macro_editor = cascade! {
+                MacroEditor::new(&board);
+                ..connect_assign(clone!(@weak keyboard => move || {
+                    if let Some(editor) = &*keyboard.inner().macro_editor {
+                        if let Some(index) = editor.index() {
+                            keyboard.assign(Keycode::Macro(index).to_string());
+                        }
+                    }
+                }));
+            };
+            keymap_box.add(&macro_editor);
+            keyboard.inner().macro_editor.set(Some(macro_editor));
+        }
+
+        let raw_key_editor = cascade! {
+            RawKeyEditor::new();
+            ..set_sensitive(false);
+            ..connect_assign(clone!(@weak keyboard => move || {
+                if let Some(value) = keyboard.inner().raw_key_editor.value() {
+                    keyboard.assign(raw_scancode_name(value));
+                }
+            }));
+        };
+        keymap_box.add(&raw_key_editor);
+        keyboard.inner().raw_key_editor.set(raw_key_editor);
 
         let backlight = cascade! {
             Backlight::new(board.clone());
@@ -298,6 +364,26 @@ impl Keyboard {
         self.set_selected(self.selected());
     }
 
+    /// Assign the scancode named `name` to the selected keys
+    fn assign(&self, name: String) {
+        let layer = match self.layer() {
+            Some(layer) => layer,
+            None => return,
+        };
+        info!("Assigning {} layer {}", name, layer);
+
+        let futures = FuturesUnordered::new();
+        for i in self.selected().iter() {
+            let i = *i;
+            let self_ = self.clone();
+            let name = name.clone();
+            futures.push(async move {
+                self_.keymap_set(i, layer, &name).await;
+            });
+        }
+        glib::MainContext::default().spawn_local(async { futures.collect::<()>().await });
+    }
+
     pub fn export_keymap(&self) -> KeyMap {
         self.board().export_keymap()
     }
@@ -335,15 +421,23 @@ impl Keyboard {
 
             let futures = FuturesUnordered::<Pin<Box<dyn Future<Output = ()>>>>::new();
 
+            let num_layers = self_.board().num_layers();
+            if keymap.num_layers() > num_layers {
+                warn!(
+                    "Ignoring {} keymap layers not supported by board",
+                    keymap.num_layers() - num_layers
+                );
+            }
+
             for (k, v) in &keymap.map {
-                for (layer, scancode_name) in v.iter().enumerate() {
+                for (layer, scancode_name) in v.iter().enumerate().take(num_layers) {
                     let n = key_indices[&k];
                     futures.push(Box::pin(self_.keymap_set(n, layer, scancode_name)));
                 }
             }
 
-            for (k, hs) in &keymap.key_leds {
-                let res = self_.board().keys()[key_indices[&k]].set_color(*hs);
+            for (k, hsv) in &keymap.key_leds {
+                let res = self_.board().keys()[key_indices[&k]].set_color(*hsv);
                 futures.push(Box::pin(async move {
                     if let Err(err) = res.await {
                         error!("{}: {}", fl!("error-key-led"), err);
@@ -351,7 +445,25 @@ impl Keyboard {
                 }));
             }
 
-            for (i, keymap_layer) in keymap.layers.iter().enumerate() {
+            let num_macros = self_.board().macros().len();
+            if keymap.macros.len() > num_macros {
+                warn!(
+                    "Ignoring {} macros not supported by board",
+                    keymap.macros.len() - num_macros
+                );
+            }
+
+            for (i, macro_) in keymap.macros.iter().enumerate().take(num_macros) {
+                let res = self_.board().set_macro(i, macro_.clone());
+                futures.push(Box::pin(async move {
+                    if let Err(err) = res.await {
+                        error!("{}: {}", fl!("error-set-macro"), err);
+                    }
+                }));
+            }
+
+            let num_led_layers = self_.board().layers().len();
+            for (i, keymap_layer) in keymap.layers.iter().enumerate().take(num_led_layers) {
                 let layer = &self_.board().layers()[i];
                 futures.push(Box::pin(async move {
                     if let Some((mode, speed)) = keymap_layer.mode {
@@ -460,13 +572,9 @@ impl Keyboard {
     fn add_pages(&self, debug_layers: bool) {
         let layer_stack = &*self.inner().layer_stack;
 
-        for (i, page) in Page::iter_all().enumerate() {
+        for (i, page) in Page::iter_all(self.board().num_layers()).enumerate() {
             if !debug_layers && page.is_debug() {
                 continue;
-            } else if let Some(layer) = page.layer() {
-                if layer >= self.layout().meta.num_layers.into() {
-                    continue;
-                }
             }
 
             let keyboard_layer = cascade! {
@@ -476,6 +584,11 @@ impl Keyboard {
             self.bind_property("selected", &keyboard_layer, "selected")
                 .flags(glib::BindingFlags::BIDIRECTIONAL)
                 .build();
+            self.inner()
+                .backlight
+                .bind_property("preview", &keyboard_layer, "preview")
+                .flags(glib::BindingFlags::SYNC_CREATE)
+                .build();
             if let Some(testing) = &*self.inner().testing {
                 testing
                     .bind_property("colors", &keyboard_layer, "testing-colors")
@@ -517,16 +630,39 @@ impl Keyboard {
         };
         let keys = self.board().keys();
 
+        let mut selected_values = Vec::new();
         let mut selected_scancodes = Vec::new();
         for i in selected.iter() {
             let k = &keys[*i];
             debug!("{:#?}", k);
             if let Some(layer) = self.layer() {
-                if let Some((_scancode, scancode_name)) = k.get_scancode(layer) {
+                if let Some((scancode, scancode_name)) = k.get_scancode(layer) {
+                    selected_values.push(scancode);
                     selected_scancodes.push(scancode_name);
                 }
             }
         }
+        let keycode = match selected_scancodes.as_slice() {
+            [name] => name.parse::<Keycode>().ok(),
+            _ => None,
+        };
+        if let Some(editor) = &*self.inner().compound_key_editor {
+            editor.set_sensitive(selected.len() > 0 && self.layer() != None);
+            if let Some(keycode) = &keycode {
+                editor.set_keycode(keycode);
+            }
+        }
+        if let Some(editor) = &*self.inner().macro_editor {
+            if let Some(Keycode::Macro(index)) = keycode {
+                editor.set_index(index);
+            }
+        }
+        let raw_key_editor = &*self.inner().raw_key_editor;
+        raw_key_editor.set_sensitive(selected.len() > 0 && self.layer() != None);
+        if let [value] = selected_values.as_slice() {
+            raw_key_editor.set_value(*value);
+        }
+
         picker.set_selected(selected_scancodes);
 
         picker.set_sensitive(selected.len() > 0 && self.layer() != None);
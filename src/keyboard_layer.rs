@@ -5,11 +5,13 @@ use gtk::subclass::prelude::*;
 use once_cell::unsync::OnceCell;
 use std::{
     cell::{Cell, RefCell},
+    collections::HashMap,
     f64::consts::PI,
+    time::Instant,
 };
 
 use crate::{Page, TestingColors};
-use backend::{Board, DerefCell, Key, Rect, Rgb};
+use backend::{effect_keys, Board, DerefCell, Effect, Hs, Key, ModePreview, Rect, Rgb};
 use widgets::SelectedKeys;
 
 const SCALE: f64 = 64.;
@@ -27,6 +29,9 @@ pub struct KeyboardLayerInner {
     wide_height: OnceCell<i32>,
     narrow_width: OnceCell<i32>,
     testing_colors: RefCell<TestingColors>,
+    preview: RefCell<Option<(ModePreview, Instant)>>,
+    preview_colors: RefCell<HashMap<usize, Rgb>>,
+    preview_tick: RefCell<Option<gtk::TickCallbackId>>,
 }
 
 #[glib::object_subclass]
@@ -61,6 +66,13 @@ impl ObjectImpl for KeyboardLayerInner {
                     TestingColors::get_type(),
                     glib::ParamFlags::READWRITE,
                 ),
+                glib::ParamSpec::boolean(
+                    "preview",
+                    "preview",
+                    "preview",
+                    false,
+                    glib::ParamFlags::READWRITE,
+                ),
             ]
         });
 
@@ -81,6 +93,7 @@ impl ObjectImpl for KeyboardLayerInner {
                     .replace(value.get_some::<&TestingColors>().unwrap().clone());
                 widget.queue_draw();
             }
+            "preview" => widget.set_preview(value.get_some().unwrap()),
             _ => unimplemented!(),
         }
     }
@@ -94,6 +107,7 @@ impl ObjectImpl for KeyboardLayerInner {
         match pspec.get_name() {
             "selected" => self.selected.borrow().to_value(),
             "testing-colors" => self.testing_colors.borrow().to_value(),
+            "preview" => self.preview.borrow().is_some().to_value(),
             _ => unimplemented!(),
         }
     }
@@ -106,12 +120,15 @@ impl WidgetImpl for KeyboardLayerInner {
         let selected = Rgb::new(0xfb, 0xb8, 0x6c).to_floats();
 
         let testing_colors = self.testing_colors.borrow();
+        let preview_colors = self.preview_colors.borrow();
 
         for (i, k) in widget.keys().iter().enumerate() {
             let Rect { x, y, w, h } = widget.key_position(&k);
 
             let mut bg = if let Some(rgb) = testing_colors.0.get(&i) {
                 rgb
+            } else if let Some(rgb) = preview_colors.get(&i) {
+                rgb
             } else {
                 &k.background_color
             }
@@ -268,6 +285,81 @@ impl KeyboardLayer {
         self.notify("selected");
     }
 
+    /// Show the LEDs of the layer's `Mode` as the background of keys, like
+    /// they'd look on the keyboard
+    pub fn set_preview(&self, preview: bool) {
+        let inner = self.inner();
+        if preview == inner.preview.borrow().is_some() {
+            return;
+        }
+        if preview {
+            let preview = ModePreview::new(0, 0, Hs::new(0., 0.));
+            inner.preview.replace(Some((preview, Instant::now())));
+            let tick = self.add_tick_callback(|widget, _| {
+                widget.render_preview();
+                Continue(true)
+            });
+            inner.preview_tick.replace(Some(tick));
+        } else {
+            inner.preview.replace(None);
+            if let Some(tick) = inner.preview_tick.take() {
+                tick.remove();
+            }
+            inner.preview_colors.borrow_mut().clear();
+            self.queue_draw();
+        }
+        self.notify("preview");
+    }
+
+    fn render_preview(&self) {
+        let inner = self.inner();
+        let board = &*inner.board;
+        let layer = if board.layout().meta.has_per_layer {
+            self.page().layer().unwrap_or(0)
+        } else {
+            0
+        };
+        let layer = match board.layers().get(layer) {
+            Some(layer) => layer,
+            None => return,
+        };
+
+        let mut preview = inner.preview.borrow_mut();
+        let (preview, start) = match &mut *preview {
+            Some(preview) => preview,
+            None => return,
+        };
+        // Boards without modes have nothing to preview
+        let (mode, speed) = match layer.mode() {
+            Some(mode) => mode,
+            None => return,
+        };
+        preview.mode = mode.index;
+        preview.speed = speed;
+        preview.color = layer.color();
+        preview.brightness = if board.max_brightness() > 0 {
+            f64::from(layer.brightness()) / f64::from(board.max_brightness())
+        } else {
+            1.
+        };
+
+        let keys = board
+            .keys()
+            .iter()
+            .enumerate()
+            .filter(|(_, key)| !key.leds.is_empty())
+            .collect::<Vec<_>>();
+        preview.key_colors = keys.iter().map(|(_, key)| key.color()).collect();
+        preview.pressed = keys.iter().map(|(_, key)| key.pressed()).collect();
+        let positions = effect_keys(&keys.iter().map(|(_, key)| key.physical).collect::<Vec<_>>());
+        let colors = preview.render(start.elapsed().as_secs_f64(), &positions);
+
+        let mut preview_colors = inner.preview_colors.borrow_mut();
+        preview_colors.clear();
+        preview_colors.extend(keys.iter().map(|(i, _)| *i).zip(colors));
+        self.queue_draw();
+    }
+
     pub fn set_selectable(&self, selectable: bool) {
         self.inner().selectable.set(selectable);
         self.queue_draw();
@@ -9,21 +9,27 @@ use std::process;
 
 mod about_dialog;
 mod backlight;
+mod backlight_agent;
+mod calibration_dialog;
 mod configurator_app;
+mod dummy_control_window;
 mod error_dialog;
+mod flash_dialog;
 mod keyboard;
 mod keyboard_layer;
 mod localize;
 mod main_window;
 mod page;
+mod paint_editor;
 mod picker;
 mod shortcuts_window;
 mod testing;
 
 pub use self::configurator_app::run;
 use self::{
-    backlight::*, configurator_app::*, error_dialog::*, keyboard::*, keyboard_layer::*,
-    main_window::*, page::*, picker::*, shortcuts_window::*, testing::*,
+    backlight::*, backlight_agent::*, calibration_dialog::*, configurator_app::*,
+    dummy_control_window::*, error_dialog::*, flash_dialog::*, keyboard::*, keyboard_layer::*,
+    main_window::*, page::*, paint_editor::*, picker::*, shortcuts_window::*, testing::*,
 };
 
 fn main() {
@@ -39,6 +45,8 @@ fn main() {
     for arg in args.iter().skip(1) {
         if arg.as_str() == "--daemon" {
             backend::run_daemon();
+        } else if arg.as_str() == "--backlight-agent" {
+            run_backlight_agent(args.iter().any(|arg| arg == "--pkexec"));
         }
     }
 
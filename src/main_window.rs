@@ -3,10 +3,13 @@ use cascade::cascade;
 use glib::clone;
 use gtk::prelude::*;
 use gtk::subclass::prelude::*;
-use std::{cell::RefCell, time::Duration};
+use std::{cell::RefCell, path::Path, thread, time::Duration};
 
-use crate::{shortcuts_window, ConfiguratorApp, Keyboard, KeyboardLayer, Page, Picker};
-use backend::{Backend, Board, DerefCell};
+use crate::{
+    shortcuts_window, show_error_dialog, show_flash_dialog, ConfiguratorApp, Keyboard,
+    KeyboardLayer, Page, Picker,
+};
+use backend::{Backend, Board, DerefCell, Firmware};
 
 pub struct Loader(MainWindow, gtk::Box);
 
@@ -30,6 +33,7 @@ pub struct MainWindowInner {
     layer_switcher: DerefCell<gtk::StackSwitcher>,
     load_box: DerefCell<gtk::Box>,
     load_revealer: DerefCell<gtk::Revealer>,
+    menu: DerefCell<gio::Menu>,
     picker: DerefCell<Picker>,
     stack: DerefCell<gtk::Stack>,
     keyboards: RefCell<Vec<(Keyboard, gtk::ListBoxRow)>>,
@@ -68,6 +72,7 @@ impl ObjectImpl for MainWindowInner {
                 ..append(Some(&fl!("layout-import")), Some("kbd.import"));
                 ..append(Some(&fl!("layout-export")), Some("kbd.export"));
                 ..append(Some(&fl!("layout-reset")), Some("kbd.reset"));
+                ..append(Some(&fl!("calibrate-title")), Some("kbd.calibrate"));
             });
             ..append_section(None, &cascade! {
                 gio::Menu::new();
@@ -178,6 +183,7 @@ impl ObjectImpl for MainWindowInner {
         self.layer_switcher.set(layer_switcher);
         self.load_box.set(load_box);
         self.load_revealer.set(load_revealer);
+        self.menu.set(menu);
         self.picker.set(picker);
         self.stack.set(stack);
         self.board_list_stack.set(board_list_stack);
@@ -205,8 +211,12 @@ impl MainWindow {
         let window: Self = glib::Object::new(&[]).unwrap();
         app.add_window(&window);
 
+        let backend = match app.replay_session() {
+            Some(path) => Backend::new_replay(path).expect("Failed to replay session"),
+            None => daemon(app.record_session()),
+        };
         let backend = cascade! {
-            daemon();
+            backend;
             ..connect_board_loading(clone!(@weak window => move || {
                 let loader = window.display_loader(&fl!("loading"));
                 *window.inner().board_loading.borrow_mut() = Some(loader);
@@ -216,6 +226,9 @@ impl MainWindow {
             }));
             ..connect_board_added(clone!(@weak window => move |board| window.add_keyboard(board)));
             ..connect_board_removed(clone!(@weak window => move |board| window.remove_keyboard(board)));
+            ..connect_daemon_died(clone!(@weak window => move |reason| {
+                show_error_dialog(&window, &fl!("error-daemon-died"), reason);
+            }));
             ..refresh();
         };
 
@@ -235,7 +248,22 @@ impl MainWindow {
 
         let phony_board_names = app.phony_board_names().to_vec();
         if !phony_board_names.is_empty() {
-            let backend = Backend::new_dummy(phony_board_names).unwrap();
+            let boards = phony_board_names.len();
+            let (backend, control) =
+                Backend::new_dummy_controlled(phony_board_names, app.phony_state_file()).unwrap();
+            window.add_action(&cascade! {
+                gio::SimpleAction::new("dummy-control", None);
+                ..connect_activate(clone!(@weak window => move |_, _| {
+                    show_dummy_control_window(&window, &control, boards);
+                }));
+            });
+            window.inner().menu.append_section(
+                None,
+                &cascade! {
+                    gio::Menu::new();
+                    ..append(Some(&fl!("dummy-title")), Some("win.dummy-control"));
+                },
+            );
             backend.connect_board_added(
                 clone!(@weak window => move |board| window.add_keyboard(board)),
             );
@@ -312,7 +340,7 @@ impl MainWindow {
             }));
         };
         let keyboard_layer = cascade! {
-            KeyboardLayer::new(Page::Layer1, keyboard.board().clone());
+            KeyboardLayer::new(Page::default(), keyboard.board().clone());
             ..set_halign(gtk::Align::Center);
         };
         let keyboard_box = cascade! {
@@ -331,7 +359,7 @@ impl MainWindow {
         };
         self.inner().keyboard_box.add(&row);
 
-        if !board.has_keymap() {
+        if !board.capabilities().keymap {
             button.hide();
             let label = cascade! {
                 gtk::Label::new(Some(&fl!("firmware-version", version = board.version())));
@@ -344,6 +372,24 @@ impl MainWindow {
             keyboard_box.add(&label);
         }
 
+        // Reading the image may be slow, so it is done on another thread
+        let (sender, receiver) = glib::MainContext::channel(glib::PRIORITY_DEFAULT);
+        let model = board.model().to_string();
+        thread::spawn(move || {
+            let _ = sender.send(Firmware::find(&model));
+        });
+        receiver.attach(
+            None,
+            clone!(@weak window, @weak keyboard_box, @strong board => @default-return glib::Continue(false), move |firmware| {
+                if let Some(firmware) = firmware
+                    .filter(|firmware| firmware.is_update_for(board.model(), board.version()))
+                {
+                    window.add_firmware_update(&keyboard_box, &board, firmware);
+                }
+                glib::Continue(false)
+            }),
+        );
+
         self.inner().stack.add(&keyboard);
         self.inner().keyboards.borrow_mut().push((keyboard, row));
 
@@ -352,6 +398,29 @@ impl MainWindow {
             .set_visible_child_name("keyboards");
     }
 
+    /// Show that `firmware` is available for `board`, with a button to
+    /// flash it
+    fn add_firmware_update(&self, keyboard_box: &gtk::Box, board: &Board, firmware: Firmware) {
+        let version = firmware.metadata.version.clone();
+        let window = self;
+        let button = cascade! {
+            gtk::Button::with_label(&fl!("button-update-firmware"));
+            ..set_halign(gtk::Align::Center);
+            ..connect_clicked(clone!(@weak window, @strong board => move |_| {
+                show_flash_dialog(&window, &board, firmware.clone());
+            }));
+        };
+        cascade! {
+            keyboard_box;
+            ..add(&gtk::Label::new(Some(&fl!(
+                "firmware-update-available",
+                version = version.as_str()
+            ))));
+            ..add(&button);
+            ..show_all();
+        };
+    }
+
     fn remove_keyboard(&self, board: Board) {
         let mut boards = self.inner().keyboards.borrow_mut();
         if let Some(idx) = boards.iter().position(|(kb, _)| kb.board() == &board) {
@@ -394,19 +463,31 @@ impl MainWindow {
     }
 }
 
+/// Backend for the keyboards of this computer, recording the session to
+/// `record` if it is set
 #[cfg(target_os = "linux")]
-fn daemon() -> Backend {
+pub(crate) fn daemon(record: Option<&Path>) -> Backend {
     if unsafe { libc::geteuid() == 0 } {
         info!("Already running as root");
-        Backend::new()
+        match record {
+            Some(path) => Backend::new_recording(path),
+            None => Backend::new(),
+        }
     } else {
         info!("Not running as root, spawning daemon with pkexec");
-        Backend::new_pkexec()
+        match record {
+            Some(path) => Backend::new_pkexec_recording(path),
+            None => Backend::new_pkexec(),
+        }
     }
     .expect("Failed to create server")
 }
 
 #[cfg(not(target_os = "linux"))]
-fn daemon() -> Backend {
-    Backend::new().expect("Failed to create server")
+pub(crate) fn daemon(record: Option<&Path>) -> Backend {
+    match record {
+        Some(path) => Backend::new_recording(path),
+        None => Backend::new(),
+    }
+    .expect("Failed to create server")
 }
@@ -1,13 +1,11 @@
 use crate::fl;
 use crate::picker::SCANCODE_LABELS;
-use backend::Key;
+use backend::{Key, Keycode, Mods};
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum Page {
-    Layer1,
-    Layer2,
-    Layer3,
-    Layer4,
+    /// Keymap of a layer, counting from 0
+    Layer(usize),
     Keycaps,
     Logical,
     Electrical,
@@ -17,10 +15,7 @@ pub enum Page {
 impl Page {
     pub fn name(&self) -> String {
         match self {
-            Self::Layer1 => fl!("page-layer1"),
-            Self::Layer2 => fl!("page-layer2"),
-            Self::Layer3 => fl!("page-layer3"),
-            Self::Layer4 => fl!("page-layer4"),
+            Self::Layer(layer) => fl!("page-layer", num = layer + 1),
             Self::Keycaps => fl!("page-keycaps"),
             Self::Logical => fl!("page-logical"),
             Self::Electrical => fl!("page-electrical"),
@@ -30,10 +25,7 @@ impl Page {
 
     pub fn layer(&self) -> Option<usize> {
         match self {
-            Self::Layer1 => Some(0),
-            Self::Layer2 => Some(1),
-            Self::Layer3 => Some(2),
-            Self::Layer4 => Some(3),
+            Self::Layer(layer) => Some(*layer),
             _ => None,
         }
     }
@@ -45,28 +37,24 @@ impl Page {
         )
     }
 
-    pub fn iter_all() -> impl Iterator<Item = Self> {
-        vec![
-            Self::Layer1,
-            Self::Layer2,
-            Self::Layer3,
-            Self::Layer4,
+    /// Pages for a board with `num_layers` layers
+    pub fn iter_all(num_layers: usize) -> impl Iterator<Item = Self> {
+        (0..num_layers).map(Self::Layer).chain(vec![
             Self::Keycaps,
             Self::Logical,
             Self::Electrical,
             Self::Leds,
-        ]
-        .into_iter()
+        ])
     }
 
     pub fn get_label(&self, key: &Key) -> String {
         match self {
-            Page::Layer1 | Page::Layer2 | Page::Layer3 | Page::Layer4 => {
-                let scancode_name = key.get_scancode(self.layer().unwrap()).unwrap().1;
-                SCANCODE_LABELS
-                    .get(&scancode_name)
-                    .unwrap_or(&scancode_name)
-                    .into()
+            Page::Layer(layer) => {
+                let scancode_name = key.get_scancode(*layer).unwrap().1;
+                if let Ok(keycode) = scancode_name.parse() {
+                    return keycode_label(&keycode);
+                }
+                scancode_label(&scancode_name)
             }
             Page::Keycaps => key.physical_name.clone(),
             Page::Logical => key.logical_name.clone(),
@@ -76,8 +64,50 @@ impl Page {
     }
 }
 
+fn scancode_label(scancode_name: &str) -> String {
+    SCANCODE_LABELS
+        .get(scancode_name)
+        .map(String::as_str)
+        .unwrap_or(scancode_name)
+        .to_string()
+}
+
+/// Label for modifiers, like "Ctrl+Shift"
+fn mods_label(mods: &Mods) -> String {
+    let mut names = Vec::new();
+    if mods.ctrl {
+        names.push(fl!("compound-key-ctrl"));
+    }
+    if mods.shift {
+        names.push(fl!("compound-key-shift"));
+    }
+    if mods.alt {
+        names.push(fl!("compound-key-alt"));
+    }
+    if mods.super_ {
+        names.push(fl!("compound-key-super"));
+    }
+    names.join("+")
+}
+
+/// Label for a compound key, like "Ctrl+C", or "Esc" over "(Ctrl)" for a key
+/// that is Ctrl when held
+fn keycode_label(keycode: &Keycode) -> String {
+    let key = keycode.basic_key().map(scancode_label).unwrap_or_default();
+    match keycode {
+        Keycode::Mods(mods, _) => format!("{}+{}", mods_label(mods), key),
+        Keycode::ModTap(mods, _) => format!("{}\n({})", key, mods_label(mods)),
+        Keycode::LayerTap(layer, _) => format!(
+            "{}\n({})",
+            key,
+            fl!("page-layer", num = *layer as usize + 1)
+        ),
+        Keycode::Macro(index) => fl!("macro-label", num = *index as usize + 1),
+    }
+}
+
 impl Default for Page {
     fn default() -> Self {
-        Self::Layer1
+        Self::Layer(0)
     }
 }
@@ -0,0 +1,326 @@
+use cascade::cascade;
+use glib::clone;
+use gtk::prelude::*;
+use gtk::subclass::prelude::*;
+use std::{cell::RefCell, f64::consts::PI, path::Path};
+
+use crate::fl;
+use backend::{
+    Board, DerefCell, Gradient, GradientShape, GradientStop, Paint, PaintImage, Rect, Rgb,
+};
+
+/// Scale of the preview, in pixels per key width
+const PREVIEW_SCALE: f64 = 24.;
+/// Images are shrunk to at most this size, which is plenty for a keyboard
+const MAX_IMAGE_SIZE: i32 = 256;
+
+/// Chooses an image or gradient to paint onto the per-key LEDs, with a
+/// preview of the colors each key will have
+#[derive(Default)]
+pub struct PaintEditorInner {
+    board: DerefCell<Board>,
+    shape_combobox: DerefCell<gtk::ComboBoxText>,
+    angle_spin: DerefCell<gtk::SpinButton>,
+    angle_row: DerefCell<gtk::Box>,
+    stops_box: DerefCell<gtk::Box>,
+    gradient_box: DerefCell<gtk::Box>,
+    image_box: DerefCell<gtk::Box>,
+    preview: DerefCell<gtk::DrawingArea>,
+    image: RefCell<Option<PaintImage>>,
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for PaintEditorInner {
+    const NAME: &'static str = "S76PaintEditor";
+    type ParentType = gtk::Box;
+    type Type = PaintEditor;
+}
+
+impl ObjectImpl for PaintEditorInner {
+    fn constructed(&self, obj: &Self::Type) {
+        self.parent_constructed(obj);
+
+        let shape_combobox = cascade! {
+            gtk::ComboBoxText::new();
+            ..append(Some("linear"), &fl!("paint-linear-gradient"));
+            ..append(Some("radial"), &fl!("paint-radial-gradient"));
+            ..append(Some("image"), &fl!("paint-image"));
+            ..set_active_id(Some("linear"));
+            ..connect_changed(clone!(@weak obj => move |_| obj.changed()));
+        };
+
+        let angle_spin = cascade! {
+            gtk::SpinButton::with_range(0., 359., 15.);
+            ..set_wrap(true);
+            ..connect_value_changed(clone!(@weak obj => move |_| obj.changed()));
+        };
+
+        let angle_row = cascade! {
+            gtk::Box::new(gtk::Orientation::Horizontal, 8);
+            ..add(&gtk::Label::new(Some(&fl!("paint-angle"))));
+            ..pack_end(&angle_spin, false, false, 0);
+        };
+
+        let stops_box = gtk::Box::new(gtk::Orientation::Vertical, 8);
+
+        let gradient_box = cascade! {
+            gtk::Box::new(gtk::Orientation::Vertical, 8);
+            ..add(&angle_row);
+            ..add(&stops_box);
+            ..add(&cascade! {
+                gtk::Button::with_label(&fl!("paint-add-stop"));
+                ..set_halign(gtk::Align::Start);
+                ..connect_clicked(clone!(@weak obj => move |_| {
+                    obj.add_stop(1., Rgb::new(255, 255, 255));
+                    obj.changed();
+                }));
+            });
+        };
+
+        let image_filter = cascade! {
+            gtk::FileFilter::new();
+            ..set_name(Some(&fl!("paint-image-filter")));
+            ..add_mime_type("image/png");
+            ..add_mime_type("image/jpeg");
+        };
+
+        let image_box = cascade! {
+            gtk::Box::new(gtk::Orientation::Horizontal, 8);
+            ..set_no_show_all(true);
+            ..add(&gtk::Label::new(Some(&fl!("paint-image-file"))));
+            ..pack_end(&cascade! {
+                gtk::FileChooserButton::new(&fl!("paint-image"), gtk::FileChooserAction::Open);
+                ..add_filter(&image_filter);
+                ..connect_file_set(clone!(@weak obj => move |button| {
+                    if let Some(path) = button.get_filename() {
+                        obj.load_image(&path);
+                    }
+                }));
+            }, false, false, 0);
+        };
+
+        let preview = cascade! {
+            gtk::DrawingArea::new();
+            ..connect_draw(clone!(@weak obj => @default-return Inhibit(false), move |_, cr| {
+                obj.draw_preview(cr);
+                Inhibit(false)
+            }));
+        };
+
+        cascade! {
+            obj;
+            ..set_orientation(gtk::Orientation::Vertical);
+            ..set_spacing(8);
+            ..add(&cascade! {
+                gtk::Box::new(gtk::Orientation::Horizontal, 8);
+                ..add(&gtk::Label::new(Some(&fl!("paint-shape"))));
+                ..pack_end(&shape_combobox, false, false, 0);
+            });
+            ..add(&gradient_box);
+            ..add(&image_box);
+            ..add(&preview);
+        };
+
+        self.shape_combobox.set(shape_combobox);
+        self.angle_spin.set(angle_spin);
+        self.angle_row.set(angle_row);
+        self.stops_box.set(stops_box);
+        self.gradient_box.set(gradient_box);
+        self.image_box.set(image_box);
+        self.preview.set(preview);
+
+        obj.add_stop(0., Rgb::new(255, 0, 0));
+        obj.add_stop(1., Rgb::new(0, 0, 255));
+    }
+}
+
+impl WidgetImpl for PaintEditorInner {}
+impl ContainerImpl for PaintEditorInner {}
+impl BoxImpl for PaintEditorInner {}
+
+glib::wrapper! {
+    pub struct PaintEditor(ObjectSubclass<PaintEditorInner>)
+        @extends gtk::Box, gtk::Container, gtk::Widget, @implements gtk::Orientable;
+}
+
+impl PaintEditor {
+    pub fn new(board: &Board) -> Self {
+        let obj: Self = glib::Object::new(&[]).unwrap();
+        obj.inner().board.set(board.clone());
+
+        let (width, height) = obj.preview_size();
+        obj.inner()
+            .preview
+            .set_size_request(width as i32, height as i32);
+
+        obj
+    }
+
+    fn inner(&self) -> &PaintEditorInner {
+        PaintEditorInner::from_instance(self)
+    }
+
+    /// What is painted with the current settings, if an image was chosen
+    /// when painting an image
+    pub fn paint(&self) -> Option<Paint> {
+        let inner = self.inner();
+        let shape = match inner.shape_combobox.get_active_id()?.as_str() {
+            "linear" => GradientShape::Linear {
+                angle: inner.angle_spin.get_value() * PI / 180.,
+            },
+            "radial" => GradientShape::Radial,
+            _ => return inner.image.borrow().clone().map(Paint::Image),
+        };
+        let stops = inner
+            .stops_box
+            .get_children()
+            .iter()
+            .filter_map(|row| {
+                let children = row.downcast_ref::<gtk::Box>()?.get_children();
+                let position = children.get(0)?.downcast_ref::<gtk::SpinButton>()?;
+                let color = children.get(1)?.downcast_ref::<gtk::ColorButton>()?;
+                let rgba = color.get_rgba();
+                Some(GradientStop {
+                    position: position.get_value() / 100.,
+                    color: Rgb::from_floats(rgba.red, rgba.green, rgba.blue),
+                })
+            })
+            .collect();
+        Some(Paint::Gradient(Gradient { shape, stops }))
+    }
+
+    fn add_stop(&self, position: f64, color: Rgb) {
+        let (r, g, b) = color.to_floats();
+        let row = cascade! {
+            gtk::Box::new(gtk::Orientation::Horizontal, 8);
+            ..add(&cascade! {
+                gtk::SpinButton::with_range(0., 100., 5.);
+                ..set_value(position * 100.);
+                ..connect_value_changed(clone!(@weak self as self_ => move |_| self_.changed()));
+            });
+            ..add(&cascade! {
+                gtk::ColorButton::with_rgba(&gdk::RGBA { red: r, green: g, blue: b, alpha: 1. });
+                ..connect_color_set(clone!(@weak self as self_ => move |_| self_.changed()));
+            });
+        };
+        row.pack_end(
+            &cascade! {
+                gtk::Button::from_icon_name(Some("list-remove-symbolic"), gtk::IconSize::Button);
+                ..connect_clicked(clone!(@weak self as self_, @weak row => move |_| {
+                    self_.inner().stops_box.remove(&row);
+                    self_.changed();
+                }));
+            },
+            false,
+            false,
+            0,
+        );
+        row.show_all();
+        self.inner().stops_box.add(&row);
+    }
+
+    fn load_image(&self, path: &Path) {
+        let pixbuf = match gdk_pixbuf::Pixbuf::from_file(path) {
+            Ok(pixbuf) => pixbuf,
+            Err(err) => {
+                error!("{}: {}", fl!("error-load-image"), err);
+                return;
+            }
+        };
+        let (width, height) = (pixbuf.get_width(), pixbuf.get_height());
+        let scale = (f64::from(MAX_IMAGE_SIZE) / f64::from(width.max(height))).min(1.);
+        let (width, height) = (
+            ((f64::from(width) * scale) as i32).max(1),
+            ((f64::from(height) * scale) as i32).max(1),
+        );
+        let pixbuf = match pixbuf.scale_simple(width, height, gdk_pixbuf::InterpType::Bilinear) {
+            Some(pixbuf) => pixbuf,
+            None => return,
+        };
+        let bytes = match pixbuf.read_pixel_bytes() {
+            Some(bytes) => bytes,
+            None => return,
+        };
+
+        let stride = pixbuf.get_rowstride() as usize;
+        let channels = pixbuf.get_n_channels() as usize;
+        let has_alpha = pixbuf.get_has_alpha();
+        let mut pixels = Vec::with_capacity((width * height) as usize);
+        for y in 0..height as usize {
+            for x in 0..width as usize {
+                let pixel = &bytes[y * stride + x * channels..][..channels];
+                // Transparent parts are unlit
+                let alpha = if has_alpha { pixel[3] } else { 255 };
+                let blend = |value: u8| (u16::from(value) * u16::from(alpha) / 255) as u8;
+                pixels.push(Rgb::new(blend(pixel[0]), blend(pixel[1]), blend(pixel[2])));
+            }
+        }
+
+        match PaintImage::new(width as usize, height as usize, pixels) {
+            Ok(image) => {
+                self.inner().image.replace(Some(image));
+                self.changed();
+            }
+            Err(err) => error!("{}: {}", fl!("error-load-image"), err),
+        }
+    }
+
+    fn changed(&self) {
+        let inner = self.inner();
+        let shape = inner.shape_combobox.get_active_id();
+        let is_image = shape.as_deref() == Some("image");
+        inner.gradient_box.set_visible(!is_image);
+        inner
+            .angle_row
+            .set_visible(shape.as_deref() == Some("linear"));
+        inner.image_box.set_visible(is_image);
+        if is_image {
+            inner.image_box.show_all();
+        }
+        inner.preview.queue_draw();
+    }
+
+    /// Position of a key in the preview, which like `KeyboardLayer` flips
+    /// the physical `y` axis
+    fn preview_rect(&self, physical: &Rect) -> Rect {
+        Rect::new(
+            physical.x * PREVIEW_SCALE + 1.,
+            -physical.y * PREVIEW_SCALE + 1.,
+            physical.w * PREVIEW_SCALE - 2.,
+            physical.h * PREVIEW_SCALE - 2.,
+        )
+    }
+
+    fn preview_size(&self) -> (f64, f64) {
+        self.inner()
+            .board
+            .keys()
+            .iter()
+            .map(|key| self.preview_rect(&key.physical))
+            .fold((0., 0.), |(w, h), rect| {
+                (f64::max(w, rect.x + rect.w), f64::max(h, rect.y + rect.h))
+            })
+    }
+
+    fn draw_preview(&self, cr: &cairo::Context) {
+        let board = &*self.inner().board;
+        let colors = self
+            .paint()
+            .map(|paint| board.paint_colors(&paint))
+            .unwrap_or_default();
+
+        let (width, _) = self.preview_size();
+        let offset = (f64::from(self.inner().preview.get_allocated_width()) - width) / 2.;
+
+        for (i, key) in board.keys().iter().enumerate() {
+            let rect = self.preview_rect(&key.physical);
+            let (r, g, b) = match colors.iter().find(|(index, _)| *index == i) {
+                Some((_, hsv)) => hsv.to_rgb().to_floats(),
+                None => (0.2, 0.2, 0.2),
+            };
+            cr.rectangle(rect.x + offset, rect.y, rect.w, rect.h);
+            cr.set_source_rgb(r, g, b);
+            cr.fill();
+        }
+    }
+}
@@ -0,0 +1,232 @@
+use cascade::cascade;
+use glib::{clone, subclass::Signal, SignalHandlerId};
+use gtk::prelude::*;
+use gtk::subclass::prelude::*;
+use once_cell::sync::Lazy;
+
+use super::SCANCODE_LABELS;
+use crate::fl;
+use backend::{Board, DerefCell, Keycode, Mods};
+
+/// Edits a key combining a basic key with modifiers or a layer, like Ctrl+C,
+/// or Ctrl when held and Esc when tapped
+#[derive(Default)]
+pub struct CompoundKeyEditorInner {
+    kind_combobox: DerefCell<gtk::ComboBoxText>,
+    mods_box: DerefCell<gtk::Box>,
+    ctrl_button: DerefCell<gtk::CheckButton>,
+    shift_button: DerefCell<gtk::CheckButton>,
+    alt_button: DerefCell<gtk::CheckButton>,
+    super_button: DerefCell<gtk::CheckButton>,
+    right_button: DerefCell<gtk::CheckButton>,
+    key_combobox: DerefCell<gtk::ComboBoxText>,
+    assign_button: DerefCell<gtk::Button>,
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for CompoundKeyEditorInner {
+    const NAME: &'static str = "S76CompoundKeyEditor";
+    type ParentType = gtk::Box;
+    type Type = CompoundKeyEditor;
+}
+
+impl ObjectImpl for CompoundKeyEditorInner {
+    fn constructed(&self, obj: &Self::Type) {
+        self.parent_constructed(obj);
+
+        let kind_combobox = cascade! {
+            gtk::ComboBoxText::new();
+            ..connect_changed(clone!(@weak obj => move |_| obj.changed()));
+        };
+
+        let check_button = |label: String| {
+            cascade! {
+                gtk::CheckButton::with_label(&label);
+                ..connect_toggled(clone!(@weak obj => move |_| obj.changed()));
+            }
+        };
+        let ctrl_button = check_button(fl!("compound-key-ctrl"));
+        let shift_button = check_button(fl!("compound-key-shift"));
+        let alt_button = check_button(fl!("compound-key-alt"));
+        let super_button = check_button(fl!("compound-key-super"));
+        let right_button = check_button(fl!("compound-key-right"));
+
+        let mods_box = cascade! {
+            gtk::Box::new(gtk::Orientation::Horizontal, 8);
+            ..add(&ctrl_button);
+            ..add(&shift_button);
+            ..add(&alt_button);
+            ..add(&super_button);
+            ..add(&right_button);
+        };
+
+        let key_combobox = cascade! {
+            gtk::ComboBoxText::new();
+            ..connect_changed(clone!(@weak obj => move |_| obj.changed()));
+        };
+
+        let assign_button = cascade! {
+            gtk::Button::with_label(&fl!("button-assign"));
+            ..set_sensitive(false);
+            ..connect_clicked(clone!(@weak obj => move |_| {
+                obj.emit_by_name("assign", &[]).unwrap();
+            }));
+        };
+
+        cascade! {
+            obj;
+            ..set_orientation(gtk::Orientation::Vertical);
+            ..set_spacing(8);
+            ..set_halign(gtk::Align::Center);
+            ..add(&cascade! {
+                gtk::Label::new(Some(&fl!("compound-key-title")));
+                ..set_halign(gtk::Align::Start);
+            });
+            ..add(&cascade! {
+                gtk::Box::new(gtk::Orientation::Horizontal, 8);
+                ..add(&kind_combobox);
+                ..add(&mods_box);
+            });
+            ..add(&cascade! {
+                gtk::Box::new(gtk::Orientation::Horizontal, 8);
+                ..add(&gtk::Label::new(Some(&fl!("compound-key-key"))));
+                ..add(&key_combobox);
+                ..pack_end(&assign_button, false, false, 0);
+            });
+        };
+
+        self.kind_combobox.set(kind_combobox);
+        self.mods_box.set(mods_box);
+        self.ctrl_button.set(ctrl_button);
+        self.shift_button.set(shift_button);
+        self.alt_button.set(alt_button);
+        self.super_button.set(super_button);
+        self.right_button.set(right_button);
+        self.key_combobox.set(key_combobox);
+        self.assign_button.set(assign_button);
+    }
+
+    fn signals() -> &'static [Signal] {
+        static SIGNALS: Lazy<Vec<Signal>> =
+            Lazy::new(|| vec![Signal::builder("assign", &[], glib::Type::UNIT.into()).build()]);
+        SIGNALS.as_ref()
+    }
+}
+
+impl WidgetImpl for CompoundKeyEditorInner {}
+impl ContainerImpl for CompoundKeyEditorInner {}
+impl BoxImpl for CompoundKeyEditorInner {}
+
+glib::wrapper! {
+    pub struct CompoundKeyEditor(ObjectSubclass<CompoundKeyEditorInner>)
+        @extends gtk::Box, gtk::Container, gtk::Widget, @implements gtk::Orientable;
+}
+
+impl CompoundKeyEditor {
+    pub fn new(board: &Board) -> Self {
+        let obj: Self = glib::Object::new(&[]).unwrap();
+
+        let kind_combobox = &*obj.inner().kind_combobox;
+        kind_combobox.append(Some("mods"), &fl!("compound-key-mods"));
+        kind_combobox.append(Some("mod-tap"), &fl!("compound-key-mod-tap"));
+        for layer in 0..board.num_layers().min(16) {
+            kind_combobox.append(
+                Some(&format!("layer{}", layer)),
+                &fl!("compound-key-layer-tap", num = layer + 1),
+            );
+        }
+        kind_combobox.set_active_id(Some("mods"));
+
+        // Only keys in the picker have a meaningful label
+        for name in board.layout().basic_scancode_names() {
+            if name == "NONE" {
+                continue;
+            }
+            if let Some(label) = SCANCODE_LABELS.get(name) {
+                obj.inner()
+                    .key_combobox
+                    .append(Some(name), &label.replace('\n', " "));
+            }
+        }
+
+        obj
+    }
+
+    fn inner(&self) -> &CompoundKeyEditorInner {
+        CompoundKeyEditorInner::from_instance(self)
+    }
+
+    /// Called when the "Assign" button is clicked, to assign `keycode` to the
+    /// selected keys
+    pub fn connect_assign<F: Fn() + 'static>(&self, cb: F) -> SignalHandlerId {
+        self.connect_local("assign", false, move |_| {
+            cb();
+            None
+        })
+        .unwrap()
+    }
+
+    fn mods(&self) -> Mods {
+        let inner = self.inner();
+        Mods {
+            ctrl: inner.ctrl_button.get_active(),
+            shift: inner.shift_button.get_active(),
+            alt: inner.alt_button.get_active(),
+            super_: inner.super_button.get_active(),
+            right: inner.right_button.get_active(),
+        }
+    }
+
+    /// Keycode described by the editor, if it is complete
+    pub fn keycode(&self) -> Option<Keycode> {
+        let key = self.inner().key_combobox.get_active_id()?.to_string();
+        let kind = self.inner().kind_combobox.get_active_id()?;
+        let mods = self.mods();
+        match kind.as_str() {
+            "mods" if !mods.is_empty() => Some(Keycode::Mods(mods, key)),
+            "mod-tap" if !mods.is_empty() => Some(Keycode::ModTap(mods, key)),
+            "mods" | "mod-tap" => None,
+            _ => {
+                let layer = kind.trim_start_matches("layer").parse().ok()?;
+                Some(Keycode::LayerTap(layer, key))
+            }
+        }
+    }
+
+    /// Show an existing keycode, so it can be modified
+    pub fn set_keycode(&self, keycode: &Keycode) {
+        let inner = self.inner();
+        let mods = match keycode {
+            Keycode::Mods(mods, _) => {
+                inner.kind_combobox.set_active_id(Some("mods"));
+                *mods
+            }
+            Keycode::ModTap(mods, _) => {
+                inner.kind_combobox.set_active_id(Some("mod-tap"));
+                *mods
+            }
+            Keycode::LayerTap(layer, _) => {
+                inner
+                    .kind_combobox
+                    .set_active_id(Some(&format!("layer{}", layer)));
+                Mods::default()
+            }
+            // Edited with the `MacroEditor`
+            Keycode::Macro(_) => return,
+        };
+        inner.ctrl_button.set_active(mods.ctrl);
+        inner.shift_button.set_active(mods.shift);
+        inner.alt_button.set_active(mods.alt);
+        inner.super_button.set_active(mods.super_);
+        inner.right_button.set_active(mods.right);
+        inner.key_combobox.set_active_id(keycode.basic_key());
+    }
+
+    fn changed(&self) {
+        let inner = self.inner();
+        let kind = inner.kind_combobox.get_active_id();
+        let has_mods = matches!(kind.as_deref(), Some("mods") | Some("mod-tap"));
+        inner.mods_box.set_sensitive(has_mods);
+        inner.assign_button.set_sensitive(self.keycode().is_some());
+    }
+}
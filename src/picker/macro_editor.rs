@@ -0,0 +1,216 @@
+use cascade::cascade;
+use glib::{clone, subclass::Signal, SignalHandlerId};
+use gtk::prelude::*;
+use gtk::subclass::prelude::*;
+use once_cell::sync::Lazy;
+
+use crate::fl;
+use backend::{Board, DerefCell, Macro};
+
+/// Edits the macros stored in the firmware, and assigns them to keys
+#[derive(Default)]
+pub struct MacroEditorInner {
+    board: DerefCell<Board>,
+    slot_combobox: DerefCell<gtk::ComboBoxText>,
+    events_entry: DerefCell<gtk::Entry>,
+    text_entry: DerefCell<gtk::Entry>,
+    save_button: DerefCell<gtk::Button>,
+    assign_button: DerefCell<gtk::Button>,
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for MacroEditorInner {
+    const NAME: &'static str = "S76MacroEditor";
+    type ParentType = gtk::Box;
+    type Type = MacroEditor;
+}
+
+impl ObjectImpl for MacroEditorInner {
+    fn constructed(&self, obj: &Self::Type) {
+        self.parent_constructed(obj);
+
+        let slot_combobox = cascade! {
+            gtk::ComboBoxText::new();
+            ..connect_changed(clone!(@weak obj => move |_| obj.load()));
+        };
+
+        let events_entry = cascade! {
+            gtk::Entry::new();
+            ..set_width_chars(40);
+            ..set_placeholder_text(Some("+LEFT_CTRL C -LEFT_CTRL 100ms"));
+            ..connect_changed(clone!(@weak obj => move |_| obj.changed()));
+        };
+
+        let text_entry = cascade! {
+            gtk::Entry::new();
+            ..set_width_chars(40);
+            ..connect_activate(clone!(@weak obj => move |_| obj.type_text()));
+            ..connect_changed(|entry| entry.get_style_context().remove_class("error"));
+        };
+
+        let save_button = cascade! {
+            gtk::Button::with_label(&fl!("button-save"));
+            ..set_sensitive(false);
+            ..connect_clicked(clone!(@weak obj => move |_| obj.save()));
+        };
+
+        let assign_button = cascade! {
+            gtk::Button::with_label(&fl!("button-assign"));
+            ..connect_clicked(clone!(@weak obj => move |_| {
+                obj.emit_by_name("assign", &[]).unwrap();
+            }));
+        };
+
+        cascade! {
+            obj;
+            ..set_orientation(gtk::Orientation::Vertical);
+            ..set_spacing(8);
+            ..set_halign(gtk::Align::Center);
+            ..add(&cascade! {
+                gtk::Label::new(Some(&fl!("macro-title")));
+                ..set_halign(gtk::Align::Start);
+            });
+            ..add(&cascade! {
+                gtk::Box::new(gtk::Orientation::Horizontal, 8);
+                ..add(&slot_combobox);
+                ..pack_end(&assign_button, false, false, 0);
+            });
+            ..add(&cascade! {
+                gtk::Box::new(gtk::Orientation::Horizontal, 8);
+                ..add(&gtk::Label::new(Some(&fl!("macro-events"))));
+                ..add(&events_entry);
+                ..pack_end(&save_button, false, false, 0);
+            });
+            ..add(&cascade! {
+                gtk::Box::new(gtk::Orientation::Horizontal, 8);
+                ..add(&gtk::Label::new(Some(&fl!("macro-text"))));
+                ..add(&text_entry);
+                ..pack_end(&cascade! {
+                    gtk::Button::with_label(&fl!("button-type"));
+                    ..connect_clicked(clone!(@weak obj => move |_| obj.type_text()));
+                }, false, false, 0);
+            });
+        };
+
+        self.slot_combobox.set(slot_combobox);
+        self.events_entry.set(events_entry);
+        self.text_entry.set(text_entry);
+        self.save_button.set(save_button);
+        self.assign_button.set(assign_button);
+    }
+
+    fn signals() -> &'static [Signal] {
+        static SIGNALS: Lazy<Vec<Signal>> =
+            Lazy::new(|| vec![Signal::builder("assign", &[], glib::Type::UNIT.into()).build()]);
+        SIGNALS.as_ref()
+    }
+}
+
+impl WidgetImpl for MacroEditorInner {}
+impl ContainerImpl for MacroEditorInner {}
+impl BoxImpl for MacroEditorInner {}
+
+glib::wrapper! {
+    pub struct MacroEditor(ObjectSubclass<MacroEditorInner>)
+        @extends gtk::Box, gtk::Container, gtk::Widget, @implements gtk::Orientable;
+}
+
+impl MacroEditor {
+    pub fn new(board: &Board) -> Self {
+        let obj: Self = glib::Object::new(&[]).unwrap();
+        obj.inner().board.set(board.clone());
+
+        let slot_combobox = &*obj.inner().slot_combobox;
+        for index in 0..board.macros().len() {
+            slot_combobox.append(
+                Some(&index.to_string()),
+                &fl!("macro-label", num = index + 1),
+            );
+        }
+        slot_combobox.set_active_id(Some("0"));
+
+        obj
+    }
+
+    fn inner(&self) -> &MacroEditorInner {
+        MacroEditorInner::from_instance(self)
+    }
+
+    /// Called when the "Assign" button is clicked, to assign the macro at
+    /// `index` to the selected keys
+    pub fn connect_assign<F: Fn() + 'static>(&self, cb: F) -> SignalHandlerId {
+        self.connect_local("assign", false, move |_| {
+            cb();
+            None
+        })
+        .unwrap()
+    }
+
+    /// Index of the macro being edited
+    pub fn index(&self) -> Option<u8> {
+        self.inner().slot_combobox.get_active_id()?.parse().ok()
+    }
+
+    /// Show the macro at `index`, like when a key playing it is selected
+    pub fn set_index(&self, index: u8) {
+        self.inner()
+            .slot_combobox
+            .set_active_id(Some(&index.to_string()));
+    }
+
+    fn macro_(&self) -> Result<Macro, String> {
+        let macro_ = self.inner().events_entry.get_text().parse::<Macro>()?;
+        // Check that every key can be sent by the firmware
+        macro_.to_bytes(self.inner().board.layout())?;
+        Ok(macro_)
+    }
+
+    fn load(&self) {
+        let index = match self.index() {
+            Some(index) => index as usize,
+            None => return,
+        };
+        if let Some(macro_) = self.inner().board.macros().get(index) {
+            self.inner().events_entry.set_text(&macro_.to_string());
+        }
+        self.inner().save_button.set_sensitive(false);
+    }
+
+    fn changed(&self) {
+        let inner = self.inner();
+        let valid = self.macro_().is_ok();
+        let style_context = inner.events_entry.get_style_context();
+        if valid {
+            style_context.remove_class("error");
+        } else {
+            style_context.add_class("error");
+        }
+        inner.save_button.set_sensitive(valid);
+    }
+
+    fn type_text(&self) {
+        let text_entry = &*self.inner().text_entry;
+        match Macro::from_text(&text_entry.get_text()) {
+            Ok(macro_) => self.inner().events_entry.set_text(&macro_.to_string()),
+            Err(err) => {
+                error!("{}", err);
+                text_entry.get_style_context().add_class("error");
+            }
+        }
+    }
+
+    fn save(&self) {
+        let (index, macro_) = match (self.index(), self.macro_()) {
+            (Some(index), Ok(macro_)) => (index as usize, macro_),
+            _ => return,
+        };
+        info!("Setting macro {} to {}", index + 1, macro_);
+        let self_ = self.clone();
+        glib::MainContext::default().spawn_local(async move {
+            match self_.inner().board.set_macro(index, macro_).await {
+                Ok(()) => self_.inner().save_button.set_sensitive(false),
+                Err(err) => error!("{}: {}", fl!("error-set-macro"), err),
+            }
+        });
+    }
+}
@@ -9,13 +9,19 @@ use std::{cell::RefCell, collections::HashMap, rc::Rc};
 use crate::Keyboard;
 use backend::DerefCell;
 
+mod compound_key_editor;
+mod macro_editor;
 mod picker_group;
 mod picker_json;
 mod picker_key;
+mod raw_key_editor;
 
+pub use compound_key_editor::CompoundKeyEditor;
+pub use macro_editor::MacroEditor;
 use picker_group::PickerGroup;
 use picker_json::picker_json;
 use picker_key::PickerKey;
+pub use raw_key_editor::RawKeyEditor;
 
 const DEFAULT_COLS: usize = 3;
 const HSPACING: i32 = 64;
@@ -0,0 +1,128 @@
+use cascade::cascade;
+use glib::{clone, subclass::Signal, SignalHandlerId};
+use gtk::prelude::*;
+use gtk::subclass::prelude::*;
+use once_cell::sync::Lazy;
+
+use crate::fl;
+use backend::{raw_scancode_name, DerefCell};
+
+/// Edits the raw 16-bit value of a key, for keycodes the picker doesn't have
+#[derive(Default)]
+pub struct RawKeyEditorInner {
+    entry: DerefCell<gtk::Entry>,
+    assign_button: DerefCell<gtk::Button>,
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for RawKeyEditorInner {
+    const NAME: &'static str = "S76RawKeyEditor";
+    type ParentType = gtk::Box;
+    type Type = RawKeyEditor;
+}
+
+impl ObjectImpl for RawKeyEditorInner {
+    fn constructed(&self, obj: &Self::Type) {
+        self.parent_constructed(obj);
+
+        let assign_button = cascade! {
+            gtk::Button::with_label(&fl!("button-assign"));
+            ..set_sensitive(false);
+            ..connect_clicked(clone!(@weak obj => move |_| {
+                obj.emit_by_name("assign", &[]).unwrap();
+            }));
+        };
+
+        let entry = cascade! {
+            gtk::Entry::new();
+            ..set_width_chars(8);
+            ..set_placeholder_text(Some("0x0000"));
+            ..connect_changed(clone!(@weak obj => move |_| obj.changed()));
+            ..connect_activate(clone!(@weak assign_button => move |_| {
+                if assign_button.get_sensitive() {
+                    assign_button.clicked();
+                }
+            }));
+        };
+
+        cascade! {
+            obj;
+            ..set_halign(gtk::Align::Center);
+            ..add(&cascade! {
+                gtk::Expander::new(Some(&fl!("raw-key-title")));
+                ..add(&cascade! {
+                    gtk::Box::new(gtk::Orientation::Horizontal, 8);
+                    ..set_margin_top(8);
+                    ..add(&gtk::Label::new(Some(&fl!("raw-key-value"))));
+                    ..add(&entry);
+                    ..add(&assign_button);
+                });
+            });
+        };
+
+        self.entry.set(entry);
+        self.assign_button.set(assign_button);
+    }
+
+    fn signals() -> &'static [Signal] {
+        static SIGNALS: Lazy<Vec<Signal>> =
+            Lazy::new(|| vec![Signal::builder("assign", &[], glib::Type::UNIT.into()).build()]);
+        SIGNALS.as_ref()
+    }
+}
+
+impl WidgetImpl for RawKeyEditorInner {}
+impl ContainerImpl for RawKeyEditorInner {}
+impl BoxImpl for RawKeyEditorInner {}
+
+glib::wrapper! {
+    pub struct RawKeyEditor(ObjectSubclass<RawKeyEditorInner>)
+        @extends gtk::Box, gtk::Container, gtk::Widget, @implements gtk::Orientable;
+}
+
+impl RawKeyEditor {
+    pub fn new() -> Self {
+        glib::Object::new(&[]).unwrap()
+    }
+
+    fn inner(&self) -> &RawKeyEditorInner {
+        RawKeyEditorInner::from_instance(self)
+    }
+
+    /// Called when the "Assign" button is clicked, to assign `value` to the
+    /// selected keys
+    pub fn connect_assign<F: Fn() + 'static>(&self, cb: F) -> SignalHandlerId {
+        self.connect_local("assign", false, move |_| {
+            cb();
+            None
+        })
+        .unwrap()
+    }
+
+    /// Value entered in hex, with or without a `0x` prefix
+    pub fn value(&self) -> Option<u16> {
+        let text = self.inner().entry.get_text();
+        let text = text.trim();
+        let hex = text.strip_prefix("0x").unwrap_or(text);
+        if hex.is_empty() || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        u16::from_str_radix(hex, 16).ok()
+    }
+
+    /// Show the value of the selected key
+    pub fn set_value(&self, value: u16) {
+        self.inner().entry.set_text(&raw_scancode_name(value));
+    }
+
+    fn changed(&self) {
+        let valid = self.value().is_some();
+        let style_context = self.inner().entry.get_style_context();
+        if valid || self.inner().entry.get_text().is_empty() {
+            style_context.remove_class("error");
+        } else {
+            style_context.add_class("error");
+        }
+        self.inner().assign_button.set_sensitive(valid);
+    }
+}
@@ -3,55 +3,69 @@ use cascade::cascade;
 use futures::future::abortable;
 use glib::clone;
 use gtk::prelude::*;
-use std::{cell::RefCell, rc::Rc};
+use std::{
+    cell::{Cell, RefCell},
+    rc::Rc,
+};
 
-use crate::{ColorWheel, KeyboardColorIndex};
-use backend::{Board, Hs};
+use crate::{import_palette, update_palette, ColorWheel, KeyboardColorIndex, PaletteView};
+use backend::{Board, Hsv, Rgb, Swatch};
 
 pub async fn choose_color<W: IsA<gtk::Widget>>(
     board: Board,
     w: &W,
     title: &str,
-    color: Option<Hs>,
+    color: Option<Hsv>,
     index: KeyboardColorIndex,
-) -> Option<Hs> {
+) -> Option<Hsv> {
     let index = Rc::new(index);
     let original_colors = index.get_colors(&board);
     let abort_handle = Rc::new(RefCell::new(None));
     board.block_led_save();
 
+    let color = color.unwrap_or_default();
     let color_wheel = cascade! {
         ColorWheel::new();
-        ..set_hs(color.unwrap_or_default());
+        ..set_hs(color.hs());
         ..set_size_request(300, 300);
     };
 
+    // Only keys have a value, since layers have their own brightness
+    let has_value = matches!(*index, KeyboardColorIndex::Keys(_));
+    let value_adjustment = gtk::Adjustment::new(*color.v * 100., 0., 100., 1., 1., 0.);
+    let hsv = clone!(@weak color_wheel, @weak value_adjustment => @default-panic, move || {
+        let hs = color_wheel.hs();
+        Hsv::new(*hs.h, *hs.s, value_adjustment.get_value() / 100.)
+    });
+
     let preview = cascade! {
         gtk::DrawingArea::new();
         ..set_halign(gtk::Align::Center);
         ..set_size_request(300, 25);
-        ..connect_draw(clone!(@weak color_wheel => @default-panic, move |_w, cr| {
-            let (r, g, b) = color_wheel.hs().to_rgb().to_floats();
+        ..connect_draw(clone!(@strong hsv => @default-panic, move |_w, cr| {
+            let (r, g, b) = hsv().to_rgb().to_floats();
             cr.set_source_rgb(r, g, b);
             cr.paint();
             Inhibit(false)
         }));
     };
 
-    color_wheel.connect_hs_changed(
-        clone!(@strong board, @strong index, @weak preview => @default-panic, move |wheel| {
-            glib::MainContext::default().spawn_local(clone!(@strong board, @strong wheel, @strong index, @strong abort_handle => async move {
-                let (res, new_abort_handle) = abortable(index.set_color(&board, wheel.hs()));
-                if let Some(handle) = abort_handle.replace(Some(new_abort_handle)) {
-                    handle.abort();
-                }
-                if let Ok(Err(err)) = res.await {
-                    error!("{}: {}", fl!("error-set-color"), err);
-                }
-            }));
-            preview.queue_draw();
-        }),
-    );
+    let changed = clone!(@strong board, @strong index, @strong hsv, @weak preview => @default-panic, move || {
+        let hsv = hsv();
+        glib::MainContext::default().spawn_local(clone!(@strong board, @strong index, @strong abort_handle => async move {
+            let (res, new_abort_handle) = abortable(index.set_color(&board, hsv));
+            if let Some(handle) = abort_handle.replace(Some(new_abort_handle)) {
+                handle.abort();
+            }
+            if let Ok(Err(err)) = res.await {
+                error!("{}: {}", fl!("error-set-color"), err);
+            }
+        }));
+        preview.queue_draw();
+    });
+    let changed = Rc::new(changed);
+    color_wheel.connect_hs_changed(clone!(@strong changed => move |_| changed()));
+    value_adjustment.connect_value_changed(clone!(@strong changed => move |_| changed()));
 
     let hue_adjustment = gtk::Adjustment::new(0., 0., 360., 1., 1., 0.);
     let saturation_adjustment = gtk::Adjustment::new(0., 0., 100., 1., 1., 0.);
@@ -87,6 +101,151 @@ pub async fn choose_color<W: IsA<gtk::Widget>>(
         ..add(&gtk::SpinButton::new(Some(&saturation_adjustment), 0., 0));
     };
 
+    let value_box = cascade! {
+        gtk::Box::new(gtk::Orientation::Horizontal, 0);
+        ..set_no_show_all(!has_value);
+        ..add(&gtk::Label::new(Some(&fl!("label-value"))));
+        ..add(&cascade! {
+            gtk::Scale::new(gtk::Orientation::Horizontal, Some(&value_adjustment));
+            ..set_hexpand(true);
+            ..set_draw_value(false);
+        });
+        ..add(&gtk::SpinButton::new(Some(&value_adjustment), 0., 0));
+    };
+
+    // Numeric inputs being edited, so they aren't rewritten while typing
+    let editing = Rc::new(Cell::new(None::<&'static str>));
+
+    let hex_entry = cascade! {
+        gtk::Entry::new();
+        ..set_max_length(7);
+        ..set_width_chars(7);
+    };
+    let rgb_adjustments = (0..3)
+        .map(|_| gtk::Adjustment::new(0., 0., 255., 1., 1., 0.))
+        .collect::<Vec<_>>();
+
+    let set_rgb = clone!(@weak color_wheel, @weak value_adjustment => move |rgb: Rgb| {
+        let hsv = rgb.to_hsv();
+        color_wheel.set_hs(hsv.hs());
+        if has_value {
+            value_adjustment.set_value(*hsv.v * 100.);
+        }
+    });
+    let set_rgb = Rc::new(set_rgb);
+
+    let sync_numeric = clone!(@strong hsv, @strong editing, @weak hex_entry, @strong rgb_adjustments => move || {
+        let source = editing.get();
+        if source.is_none() {
+            editing.set(Some("wheel"));
+        }
+        let rgb = hsv().to_rgb();
+        if source != Some("hex") {
+            hex_entry.set_text(&rgb.to_string());
+            hex_entry.get_style_context().remove_class("error");
+        }
+        if source != Some("rgb") {
+            for (adjustment, value) in rgb_adjustments.iter().zip(&[rgb.r, rgb.g, rgb.b]) {
+                adjustment.set_value((*value).into());
+            }
+        }
+        editing.set(source);
+    });
+    let sync_numeric = Rc::new(sync_numeric);
+    color_wheel.connect_hs_changed(clone!(@strong sync_numeric => move |_| sync_numeric()));
+    value_adjustment.connect_value_changed(clone!(@strong sync_numeric => move |_| sync_numeric()));
+
+    hex_entry.connect_changed(clone!(@strong editing, @strong set_rgb => move |entry| {
+        if editing.get().is_some() {
+            return;
+        }
+        let text = entry.get_text();
+        let text = if text.starts_with('#') {
+            text.to_string()
+        } else {
+            format!("#{}", text)
+        };
+        match Rgb::parse(&text) {
+            Some(rgb) => {
+                entry.get_style_context().remove_class("error");
+                editing.set(Some("hex"));
+                set_rgb(rgb);
+                editing.set(None);
+            }
+            None => entry.get_style_context().add_class("error"),
+        }
+    }));
+    for adjustment in &rgb_adjustments {
+        adjustment.connect_value_changed(
+            clone!(@strong editing, @strong set_rgb, @strong rgb_adjustments => move |_| {
+                if editing.get().is_some() {
+                    return;
+                }
+                let value = |i: usize| rgb_adjustments[i].get_value() as u8;
+                editing.set(Some("rgb"));
+                set_rgb(Rgb::new(value(0), value(1), value(2)));
+                editing.set(None);
+            }),
+        );
+    }
+    sync_numeric();
+
+    let numeric_box = cascade! {
+        gtk::Box::new(gtk::Orientation::Horizontal, 8);
+        ..add(&gtk::Label::new(Some(&fl!("label-hex"))));
+        ..add(&hex_entry);
+    };
+    for (label, adjustment) in [fl!("label-red"), fl!("label-green"), fl!("label-blue")]
+        .iter()
+        .zip(&rgb_adjustments)
+    {
+        numeric_box.add(&gtk::Label::new(Some(label)));
+        numeric_box.add(&gtk::SpinButton::new(Some(adjustment), 0., 0));
+    }
+
+    let palette_view = cascade! {
+        PaletteView::new();
+        ..connect_color_activated(clone!(@weak color_wheel, @weak value_adjustment => move |rgb| {
+            let hsv = rgb.to_hsv();
+            color_wheel.set_hs(hsv.hs());
+            value_adjustment.set_value(*hsv.v * 100.);
+        }));
+    };
+
+    let swatch_name_entry = cascade! {
+        gtk::Entry::new();
+        ..set_placeholder_text(Some(&fl!("palette-swatch-name")));
+        ..set_hexpand(true);
+    };
+
+    let swatch_box = cascade! {
+        gtk::Box::new(gtk::Orientation::Horizontal, 8);
+        ..add(&swatch_name_entry);
+        ..add(&cascade! {
+            gtk::Button::with_label(&fl!("palette-save-swatch"));
+            ..connect_clicked(clone!(@strong hsv, @weak swatch_name_entry => move |_| {
+                let color = hsv().to_rgb();
+                let name = swatch_name_entry.get_text();
+                let name = if name.is_empty() {
+                    color.to_string()
+                } else {
+                    name.to_string()
+                };
+                update_palette(|palette| palette.swatches.push(Swatch { name, color }));
+                swatch_name_entry.set_text("");
+            }));
+        });
+        ..add(&cascade! {
+            gtk::Button::with_label(&fl!("palette-import"));
+            ..connect_clicked(|button| {
+                let window = button
+                    .get_toplevel()
+                    .and_then(|x| x.downcast::<gtk::Window>().ok());
+                import_palette(window.as_ref());
+            });
+        });
+    };
+
     let vbox = cascade! {
         gtk::Box::new(gtk::Orientation::Vertical, 12);
         ..set_property_margin(24);
@@ -94,6 +253,10 @@ pub async fn choose_color<W: IsA<gtk::Widget>>(
         ..add(&preview);
         ..add(&hue_box);
         ..add(&saturation_box);
+        ..add(&value_box);
+        ..add(&numeric_box);
+        ..add(&palette_view);
+        ..add(&swatch_box);
     };
 
     let window = w
@@ -123,7 +286,9 @@ pub async fn choose_color<W: IsA<gtk::Widget>>(
     board.unblock_led_save();
 
     if response == gtk::ResponseType::Ok {
-        Some(color_wheel.hs())
+        let hsv = hsv();
+        update_palette(|palette| palette.add_recent(hsv.to_rgb()));
+        Some(hsv)
     } else {
         if let Err(err) = index.set_colors(&board, &original_colors).await {
             error!("{}: {}", fl!("error-set-color"), err);
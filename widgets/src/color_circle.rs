@@ -3,13 +3,13 @@ use gtk::prelude::*;
 use gtk::subclass::prelude::*;
 use std::{cell::RefCell, collections::BTreeSet, f64::consts::PI};
 
-use backend::Hs;
+use backend::Hsv;
 
 const BORDER: f64 = 1.;
 
 #[derive(Default)]
 pub struct ColorCircleInner {
-    colors: RefCell<BTreeSet<Hs>>,
+    colors: RefCell<BTreeSet<Hsv>>,
 }
 
 #[glib::object_subclass]
@@ -39,12 +39,12 @@ impl WidgetImpl for ColorCircleInner {
         let total = colors.len() as f64;
 
         let mut angle1 = 0.;
-        for hs in colors.iter() {
+        for hsv in colors.iter() {
             let angle2 = angle1 + (2. * PI) / total;
             cr.move_to(radius, radius);
             cr.arc(radius, radius, radius - 2. * BORDER, angle1, angle2);
             cr.close_path();
-            let (r, g, b) = hs.to_rgb().to_floats();
+            let (r, g, b) = hsv.to_rgb().to_floats();
             cr.set_source_rgba(r, g, b, alpha);
             cr.fill();
             angle1 = angle2;
@@ -84,7 +84,7 @@ impl ColorCircle {
         ColorCircleInner::from_instance(self)
     }
 
-    pub fn set_colors(&self, colors: BTreeSet<Hs>) {
+    pub fn set_colors(&self, colors: BTreeSet<Hsv>) {
         self.inner().colors.replace(colors);
         self.queue_draw();
     }
@@ -55,14 +55,19 @@ fn page(board: Board) -> gtk::Widget {
         ..connect_change_value(clone!(@strong board => move |_scale, _, value| {
             glib::MainContext::default().spawn_local(clone!(@strong board => async move {
                 if let Err(err) = board.layers()[0].set_brightness(value as i32).await {
-                    eprintln!("{}: {}", fl!("error-set-brightness"), err);
+                    if !err.is_superseded() {
+                        eprintln!("{}: {}", fl!("error-set-brightness"), err);
+                    }
                 }
             }));
             Inhibit(false)
         }));
     };
 
-    // TODO detect when brightness changed in daemon
+    // Update when changed by hotkeys or another program
+    board.connect_leds_changed(clone!(@weak brightness_scale, @weak board => move || {
+        brightness_scale.set_value(board.layers()[0].brightness() as f64);
+    }));
 
     let button = KeyboardColor::new(Some(board), KeyboardColorIndex::Layer(0));
 
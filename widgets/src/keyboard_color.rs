@@ -10,7 +10,8 @@ use std::{
 };
 
 use crate::{choose_color, ColorCircle, DerefCell, SelectedKeys};
-use backend::{Board, Hs};
+use backend::{Board, Hs, Hsv};
+use glib::SignalHandlerId;
 
 #[derive(Clone)]
 pub enum KeyboardColorIndex {
@@ -19,21 +20,23 @@ pub enum KeyboardColorIndex {
 }
 
 impl KeyboardColorIndex {
-    pub async fn set_color(&self, board: &Board, hs: Hs) -> Result<(), String> {
+    /// Set the color of the keys or layer. Layers have a brightness of their
+    /// own, so their color ignores the value.
+    pub async fn set_color(&self, board: &Board, hsv: Hsv) -> Result<(), String> {
         match self {
             KeyboardColorIndex::Keys(keys) => {
                 let futures = FuturesUnordered::new();
                 for i in keys.iter() {
-                    futures.push(board.keys()[*i as usize].set_color(Some(hs)));
+                    futures.push(board.keys()[*i as usize].set_color(Some(hsv)));
                 }
                 futures.try_collect::<()>().await?
             }
-            KeyboardColorIndex::Layer(i) => board.layers()[*i as usize].set_color(hs).await?,
+            KeyboardColorIndex::Layer(i) => board.layers()[*i as usize].set_color(hsv.hs()).await?,
         };
         Ok(())
     }
 
-    pub fn get_color_set(&self, board: &Board) -> BTreeSet<Hs> {
+    pub fn get_color_set(&self, board: &Board) -> BTreeSet<Hsv> {
         match self {
             KeyboardColorIndex::Keys(keys) => keys
                 .iter()
@@ -41,12 +44,12 @@ impl KeyboardColorIndex {
                 .collect(),
             KeyboardColorIndex::Layer(i) => cascade! {
                 BTreeSet::new();
-                ..insert(board.layers()[*i as usize].color());
+                ..insert(board.layers()[*i as usize].color().into());
             },
         }
     }
 
-    pub fn get_colors(&self, board: &Board) -> HashMap<usize, Hs> {
+    pub fn get_colors(&self, board: &Board) -> HashMap<usize, Hsv> {
         match self {
             KeyboardColorIndex::Keys(keys) => keys
                 .iter()
@@ -54,7 +57,7 @@ impl KeyboardColorIndex {
                 .collect(),
             KeyboardColorIndex::Layer(i) => cascade! {
                 HashMap::new();
-                ..insert(*i, board.layers()[*i].color());
+                ..insert(*i, board.layers()[*i].color().into());
             },
         }
     }
@@ -62,7 +65,7 @@ impl KeyboardColorIndex {
     pub async fn set_colors(
         &self,
         board: &Board,
-        colors: &HashMap<usize, Hs>,
+        colors: &HashMap<usize, Hsv>,
     ) -> Result<(), String> {
         match self {
             KeyboardColorIndex::Keys(keys) => {
@@ -74,7 +77,7 @@ impl KeyboardColorIndex {
             }
             KeyboardColorIndex::Layer(i) => {
                 board.layers()[*i as usize]
-                    .set_color(*colors.get(i).unwrap())
+                    .set_color(colors.get(i).unwrap().hs())
                     .await?
             }
         };
@@ -92,8 +95,11 @@ impl Default for KeyboardColorIndex {
 pub struct KeyboardColorInner {
     circle: DerefCell<ColorCircle>,
     board: RefCell<Option<Board>>,
-    hs: Cell<Hs>,
+    leds_changed_id: RefCell<Option<SignalHandlerId>>,
+    hsv: Cell<Hsv>,
     index: RefCell<KeyboardColorIndex>,
+    /// Number of colors being set, during which `leds-changed` is ignored
+    changing: Cell<usize>,
 }
 
 #[glib::object_subclass]
@@ -155,7 +161,7 @@ impl ObjectImpl for KeyboardColorInner {
         pspec: &glib::ParamSpec,
     ) -> glib::Value {
         match pspec.get_name() {
-            "hs" => self.hs.get().to_value(),
+            "hs" => self.hsv.get().hs().to_value(),
             _ => unimplemented!(),
         }
     }
@@ -191,11 +197,11 @@ impl KeyboardColor {
                 self_.board().unwrap().clone(),
                 &self_,
                 &title,
-                Some(self_.hs()),
+                Some(self_.hsv()),
                 self_.index().clone(),
             );
             if let Some(color) = resp.await {
-                self_.set_hs(color);
+                self_.set_hsv(color);
             }
         }));
     }
@@ -210,27 +216,50 @@ impl KeyboardColor {
     }
 
     pub fn set_board(&self, board: Option<Board>) {
+        if let Some(old_board) = self.inner().board.borrow().as_ref() {
+            if let Some(id) = self.inner().leds_changed_id.borrow_mut().take() {
+                old_board.disconnect(id);
+            }
+        }
         self.inner().circle.set_sensitive(board.is_some());
+        if let Some(board) = &board {
+            let self_ = self;
+            let id = board.connect_leds_changed(clone!(@weak self_ => move || {
+                if self_.inner().changing.get() == 0 {
+                    self_.read_color();
+                }
+            }));
+            *self.inner().leds_changed_id.borrow_mut() = Some(id);
+        }
         *self.inner().board.borrow_mut() = board;
         self.read_color();
     }
 
-    fn hs(&self) -> Hs {
-        self.inner().hs.get()
+    fn hsv(&self) -> Hsv {
+        self.inner().hsv.get()
     }
 
+    /// Set the hue and saturation, keeping the value
     fn set_hs(&self, hs: Hs) {
+        let v = self.hsv().v;
+        self.set_hsv(Hsv::new(*hs.h, *hs.s, *v));
+    }
+
+    pub fn set_hsv(&self, hsv: Hsv) {
         let self_ = self.clone();
         let board = self.board().unwrap().clone();
-        if self.inner().hs.replace(hs) != hs {
+        if self.inner().hsv.replace(hsv) != hsv {
             self.inner().circle.set_colors(cascade! {
                 BTreeSet::new();
-                ..insert(hs);
+                ..insert(hsv);
             });
+            self.inner().changing.set(self.inner().changing.get() + 1);
             glib::MainContext::default().spawn_local(async move {
-                if let Err(err) = self_.index().set_color(&board, hs).await {
+                let index = self_.index().clone();
+                if let Err(err) = index.set_color(&board, hsv).await {
                     error!("Failed to set keyboard color: {}", err);
                 }
+                self_.inner().changing.set(self_.inner().changing.get() - 1);
                 self_.notify("hs");
             });
         }
@@ -243,8 +272,8 @@ impl KeyboardColor {
     fn read_color(&self) {
         if let Some(board) = self.board() {
             let colors = self.index().get_color_set(&board);
-            let hs = colors.iter().next().copied().unwrap_or(Hs::new(0., 0.));
-            if self.inner().hs.replace(hs) != hs {
+            let hsv = colors.iter().next().copied().unwrap_or_default();
+            if self.inner().hsv.replace(hsv) != hsv {
                 self.notify("hs");
             }
             self.inner().circle.set_colors(colors);
@@ -6,11 +6,12 @@ mod color_circle;
 mod color_wheel;
 mod keyboard_color;
 mod localize;
+mod palette_view;
 mod selected_keys;
 
 pub use crate::{
     choose_color::*, color_circle::*, color_wheel::*, keyboard_color::*, localize::*,
-    selected_keys::*,
+    palette_view::*, selected_keys::*,
 };
 pub use backend;
 use backend::DerefCell;
@@ -0,0 +1,220 @@
+use crate::fl;
+use cascade::cascade;
+use glib::{clone, subclass::Signal, SignalHandlerId};
+use gtk::prelude::*;
+use gtk::subclass::prelude::*;
+use once_cell::sync::Lazy;
+use std::{cell::RefCell, collections::BTreeSet, fs};
+
+use crate::{ColorCircle, DerefCell};
+use backend::{Palette, Rgb};
+
+thread_local! {
+    static PALETTE: RefCell<Option<Palette>> = RefCell::new(None);
+    static VIEWS: RefCell<Vec<glib::WeakRef<PaletteView>>> = RefCell::new(Vec::new());
+}
+
+/// Saved colors, shared by every `PaletteView`
+pub fn palette() -> Palette {
+    PALETTE.with(|palette| {
+        palette
+            .borrow_mut()
+            .get_or_insert_with(Palette::load)
+            .clone()
+    })
+}
+
+/// Change the saved colors, saving them and updating every `PaletteView`
+pub fn update_palette<F: FnOnce(&mut Palette)>(f: F) {
+    let mut new_palette = palette();
+    f(&mut new_palette);
+    if let Err(err) = new_palette.save() {
+        error!("{}: {}", fl!("error-save-palette"), err);
+    }
+    PALETTE.with(|palette| palette.replace(Some(new_palette)));
+    VIEWS.with(|views| {
+        views.borrow_mut().retain(|view| match view.upgrade() {
+            Some(view) => {
+                view.update();
+                true
+            }
+            None => false,
+        })
+    });
+}
+
+/// Add the colors of a GIMP palette file chosen by the user
+pub fn import_palette<W: IsA<gtk::Window>>(parent: Option<&W>) {
+    let filter = cascade! {
+        gtk::FileFilter::new();
+        ..set_name(Some(&fl!("palette-filter")));
+        ..add_pattern("*.gpl");
+    };
+
+    let chooser = cascade! {
+        gtk::FileChooserNative::new(Some(&fl!("palette-import")), parent, gtk::FileChooserAction::Open, Some(&fl!("button-import")), Some(&fl!("button-cancel")));
+        ..add_filter(&filter);
+    };
+
+    if chooser.run() == gtk::ResponseType::Accept {
+        let path = chooser.get_filename().unwrap();
+        match fs::read_to_string(&path) {
+            Ok(gpl) => update_palette(|palette| {
+                if let Err(err) = palette.import_gpl(&gpl) {
+                    error!("{}: {}", fl!("error-import-palette"), err);
+                }
+            }),
+            Err(err) => error!("{}: {}", fl!("error-import-palette"), err),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct PaletteViewInner {
+    recent_box: DerefCell<gtk::FlowBox>,
+    swatches_box: DerefCell<gtk::FlowBox>,
+    swatches_label: DerefCell<gtk::Label>,
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for PaletteViewInner {
+    const NAME: &'static str = "S76PaletteView";
+    type ParentType = gtk::Box;
+    type Type = PaletteView;
+}
+
+impl ObjectImpl for PaletteViewInner {
+    fn constructed(&self, obj: &PaletteView) {
+        self.parent_constructed(obj);
+
+        let flow_box = || {
+            cascade! {
+                gtk::FlowBox::new();
+                ..set_selection_mode(gtk::SelectionMode::None);
+                ..set_max_children_per_line(16);
+                ..set_row_spacing(4);
+                ..set_column_spacing(4);
+            }
+        };
+        let label = |text: &str| {
+            cascade! {
+                gtk::Label::new(Some(text));
+                ..set_halign(gtk::Align::Start);
+            }
+        };
+
+        let recent_box = flow_box();
+        let swatches_box = flow_box();
+        let swatches_label = cascade! {
+            label(&fl!("palette-swatches"));
+            ..set_no_show_all(true);
+        };
+
+        cascade! {
+            obj;
+            ..set_orientation(gtk::Orientation::Vertical);
+            ..set_spacing(4);
+            ..add(&label(&fl!("palette-recent")));
+            ..add(&recent_box);
+            ..add(&swatches_label);
+            ..add(&swatches_box);
+        };
+
+        self.recent_box.set(recent_box);
+        self.swatches_box.set(swatches_box);
+        self.swatches_label.set(swatches_label);
+
+        VIEWS.with(|views| views.borrow_mut().push(obj.downgrade()));
+        obj.update();
+    }
+
+    fn signals() -> &'static [Signal] {
+        static SIGNALS: Lazy<Vec<Signal>> = Lazy::new(|| {
+            vec![Signal::builder(
+                "color-activated",
+                &[Rgb::static_type().into()],
+                glib::Type::UNIT.into(),
+            )
+            .build()]
+        });
+        SIGNALS.as_ref()
+    }
+}
+
+impl WidgetImpl for PaletteViewInner {}
+impl ContainerImpl for PaletteViewInner {}
+impl BoxImpl for PaletteViewInner {}
+
+glib::wrapper! {
+    /// Recently used colors and saved swatches, which can be clicked to use
+    /// them again. Right clicking a swatch removes it.
+    pub struct PaletteView(ObjectSubclass<PaletteViewInner>)
+        @extends gtk::Box, gtk::Container, gtk::Widget, @implements gtk::Orientable;
+}
+
+impl PaletteView {
+    pub fn new() -> Self {
+        glib::Object::new(&[]).unwrap()
+    }
+
+    fn inner(&self) -> &PaletteViewInner {
+        PaletteViewInner::from_instance(self)
+    }
+
+    pub fn connect_color_activated<F: Fn(Rgb) + 'static>(&self, cb: F) -> SignalHandlerId {
+        self.connect_local("color-activated", false, move |values| {
+            cb(*values[1].get_some::<&Rgb>().unwrap());
+            None
+        })
+        .unwrap()
+    }
+
+    fn circle(&self, color: Rgb, tooltip: &str) -> ColorCircle {
+        cascade! {
+            ColorCircle::new(20);
+            ..set_colors(cascade! {
+                BTreeSet::new();
+                ..insert(color.to_hsv());
+            });
+            ..set_tooltip_text(Some(tooltip));
+            ..connect_clicked(clone!(@weak self as self_ => move |_| {
+                self_.emit_by_name("color-activated", &[&color]).unwrap();
+            }));
+        }
+    }
+
+    fn update(&self) {
+        let palette = palette();
+        let inner = self.inner();
+
+        inner.recent_box.foreach(|w| inner.recent_box.remove(w));
+        for color in &palette.recent {
+            inner
+                .recent_box
+                .add(&self.circle(*color, &color.to_string()));
+        }
+
+        inner.swatches_box.foreach(|w| inner.swatches_box.remove(w));
+        for (i, swatch) in palette.swatches.iter().enumerate() {
+            let circle = cascade! {
+                self.circle(swatch.color, &swatch.name);
+                ..connect_button_press_event(move |_, evt| {
+                    if evt.get_button() == 3 {
+                        update_palette(|palette| {
+                            palette.swatches.remove(i);
+                        });
+                        Inhibit(true)
+                    } else {
+                        Inhibit(false)
+                    }
+                });
+            };
+            inner.swatches_box.add(&circle);
+        }
+        inner
+            .swatches_label
+            .set_visible(!palette.swatches.is_empty());
+        inner.recent_box.show_all();
+        inner.swatches_box.show_all();
+    }
+}